@@ -0,0 +1,39 @@
+//! Interning throughput on a concatenation-heavy workload, run with `cargo bench`
+
+use std::time::{Duration, Instant};
+
+use alox_bytecode::interner::Interner;
+
+const ROUNDS: usize = 20;
+const STRINGS: usize = 20_000;
+
+/// Builds strings the way `s = s + x` does in a loop, interning every step, then interns
+/// each piece again
+fn concatenation(words: &[String]) -> Duration {
+    let mut interner = Interner::new();
+    let start = Instant::now();
+    let mut built = String::new();
+    for word in words {
+        built.push_str(word);
+        if built.len() > 256 {
+            built.clear();
+        }
+        interner.intern(&built);
+    }
+    for word in words {
+        interner.intern(word);
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let words: Vec<String> = (0..STRINGS).map(|i| format!("w{}", i)).collect();
+    let best = (0..ROUNDS)
+        .map(|_| concatenation(&words))
+        .min()
+        .expect("at least one round");
+    println!(
+        "intern {} concatenations and {} lookups: {:?} (best of {})",
+        STRINGS, STRINGS, best, ROUNDS
+    );
+}
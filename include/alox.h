@@ -0,0 +1,46 @@
+/* C interface to the alox interpreter, built with `cargo build --features ffi`.
+ * Strings are NUL terminated UTF-8. See src/ffi.rs for details on each function. */
+#ifndef ALOX_H
+#define ALOX_H
+
+#include <stdbool.h>
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum AloxStatus {
+    ALOX_OK = 0,
+    ALOX_COMPILE_ERROR = 1,
+    ALOX_RUNTIME_ERROR = 2,
+    ALOX_INVALID_ARGUMENT = 3,
+    ALOX_NOT_FOUND = 4,
+    ALOX_TYPE_MISMATCH = 5,
+    ALOX_PANICKED = 6,
+} AloxStatus;
+
+typedef struct AloxVm AloxVm;
+
+AloxVm *alox_vm_new(const char *source);
+void alox_vm_free(AloxVm *vm);
+AloxStatus alox_run(AloxVm *vm);
+const char *alox_last_error(const AloxVm *vm);
+
+AloxStatus alox_get_global_number(AloxVm *vm, const char *name, double *out);
+AloxStatus alox_get_global_bool(AloxVm *vm, const char *name, bool *out);
+AloxStatus alox_get_global_string(AloxVm *vm, const char *name, char **out);
+void alox_string_free(char *string);
+
+AloxStatus alox_set_global_number(AloxVm *vm, const char *name, double value);
+AloxStatus alox_set_global_bool(AloxVm *vm, const char *name, bool value);
+AloxStatus alox_set_global_string(AloxVm *vm, const char *name, const char *value);
+
+AloxStatus alox_call_number(AloxVm *vm, const char *name, const double *args,
+                            size_t arg_count, double *result);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
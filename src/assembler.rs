@@ -0,0 +1,321 @@
+use std::{convert::TryFrom, fmt::Display, str::FromStr, sync::Arc};
+
+use ahash::AHashMap;
+
+use crate::{
+    chunk::Chunk,
+    interner::Interner,
+    object::{AloxString, ObjFunction, Object},
+    opcodes::Op,
+    value::Value,
+};
+
+/// A line of assembly that couldn't be assembled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblyError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for AssemblyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[line {}] Error: {}", self.line, self.message)
+    }
+}
+
+impl Chunk {
+    /// Assembles a chunk from text, instruction by instruction, without going through the
+    /// compiler. Nothing is checked beyond the syntax, `verify` the result if it should be
+    /// runnable. A chunk has a `.constants` and a `.code` section:
+    ///
+    /// ```text
+    /// .constants
+    ///     "hi"            ; strings, integers, floats, true, false and nil
+    ///     .function twice 1
+    ///         .code
+    ///             GetLocal 1
+    ///             Return
+    ///     .end
+    /// .code
+    /// loop:
+    ///     Constant 0      ; mnemonics as the disassembler prints them
+    ///     Print
+    ///     Jump loop       ; jumps take a label of their chunk or an offset
+    /// ```
+    ///
+    /// Constants are numbered from zero in the order they are listed, and `.function` takes
+    /// the name, arity and upvalue count of a function whose chunk follows up to its `.end`.
+    /// `Closure` lists its captures after the constant, as `local 1` or `upvalue 0`. Every
+    /// byte gets the line of the assembly it came from
+    pub fn from_assembly(source: &str, interner: &mut Interner) -> Result<Chunk, AssemblyError> {
+        let mut lines = source
+            .lines()
+            .enumerate()
+            .map(|(index, text)| (index + 1, strip_comment(text).trim()))
+            .filter(|(_, text)| !text.is_empty());
+        Assembler {
+            lines: &mut lines,
+            interner,
+        }
+        .chunk(None)
+    }
+}
+
+/// Text before a `;` that isn't inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn error(line: usize, message: impl ToString) -> AssemblyError {
+    AssemblyError {
+        line,
+        message: message.to_string(),
+    }
+}
+
+fn operand<T: FromStr>(line: usize, text: &str) -> Result<T, AssemblyError> {
+    text.parse()
+        .map_err(|_| error(line, format!("Invalid operand '{}'.", text)))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Constants,
+    Code,
+}
+
+/// A jump written before its target was known
+struct Fixup<'s> {
+    line: usize,
+    /// Offset of the two distance bytes, which end the instruction
+    at: usize,
+    sign: i64,
+    target: &'s str,
+}
+
+struct Assembler<'a, 's> {
+    lines: &'a mut dyn Iterator<Item = (usize, &'s str)>,
+    interner: &'a mut Interner,
+}
+
+impl<'s> Assembler<'_, 's> {
+    /// Assembles lines up to the end of the input, or up to the `.end` of the `.function`
+    /// on line `function`
+    fn chunk(&mut self, function: Option<usize>) -> Result<Chunk, AssemblyError> {
+        let mut chunk = Chunk::init();
+        let mut section = None;
+        let mut labels = AHashMap::new();
+        let mut fixups = Vec::new();
+        let mut ended = false;
+        while let Some((line, text)) = self.lines.next() {
+            match text {
+                ".constants" => section = Some(Section::Constants),
+                ".code" => section = Some(Section::Code),
+                ".end" if function.is_some() => {
+                    ended = true;
+                    break;
+                }
+                ".end" => return Err(error(line, "'.end' without a '.function'.")),
+                _ => match section {
+                    Some(Section::Constants) => self.constant(&mut chunk, line, text)?,
+                    Some(Section::Code) => match text.strip_suffix(':') {
+                        Some(label) => {
+                            if labels.insert(label, chunk.code.len()).is_some() {
+                                let message = format!("Label '{}' is already defined.", label);
+                                return Err(error(line, message));
+                            }
+                        }
+                        None => instruction(&mut chunk, &mut fixups, line, text)?,
+                    },
+                    None => return Err(error(line, "Expected '.constants' or '.code' first.")),
+                },
+            }
+        }
+        if let (Some(line), false) = (function, ended) {
+            return Err(error(line, "'.function' without an '.end'."));
+        }
+        for fixup in fixups {
+            let target = match labels.get(fixup.target) {
+                Some(&target) => target,
+                None => operand(fixup.line, fixup.target)
+                    .map_err(|_| error(fixup.line, format!("Unknown label '{}'.", fixup.target)))?,
+            };
+            let distance = fixup.sign * (target as i64 - (fixup.at + 2) as i64);
+            let distance = u16::try_from(distance).map_err(|_| {
+                let message = format!("Can't jump to '{}' from here.", fixup.target);
+                error(fixup.line, message)
+            })?;
+            chunk.code[fixup.at..fixup.at + 2].copy_from_slice(&distance.to_be_bytes());
+        }
+        Ok(chunk)
+    }
+
+    fn constant(
+        &mut self,
+        chunk: &mut Chunk,
+        line: usize,
+        text: &str,
+    ) -> Result<(), AssemblyError> {
+        let value = match text {
+            "nil" => Value::Nil,
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            _ if text.starts_with(".function") => self.function(line, text)?,
+            _ if text.starts_with('"') => {
+                let string = unescape(text).ok_or_else(|| error(line, "Invalid string."))?;
+                Value::Obj(Object::String(AloxString(self.interner.intern(&string))))
+            }
+            _ => match (text.parse::<i64>(), text.parse::<f64>()) {
+                (Ok(int), _) => Value::Int(int),
+                (_, Ok(number)) => Value::Number(number),
+                _ => return Err(error(line, format!("Invalid constant '{}'.", text))),
+            },
+        };
+        chunk.push_constant(value);
+        Ok(())
+    }
+
+    /// Assembles the function declared by the `.function` on `line`
+    fn function(&mut self, line: usize, text: &str) -> Result<Value, AssemblyError> {
+        let parts: Vec<&str> = text.split_whitespace().skip(1).collect();
+        let (name, arity, upvalues) = match parts[..] {
+            [name, arity] => (name, arity, "0"),
+            [name, arity, upvalues] => (name, arity, upvalues),
+            _ => {
+                return Err(error(
+                    line,
+                    "Expected a name, an arity and an upvalue count.",
+                ))
+            }
+        };
+        let mut function = ObjFunction::new(Some(AloxString(self.interner.intern(name))));
+        function.arity = operand(line, arity)?;
+        function.upvalue_count = operand(line, upvalues)?;
+        function.chunk = self.chunk(Some(line))?;
+        Ok(Value::Obj(Object::Function(Arc::new(function))))
+    }
+}
+
+/// Contents of a quoted string with `\n`, `\t`, `\"` and `\\` escapes
+fn unescape(text: &str) -> Option<String> {
+    let inner = text.strip_prefix('"')?.strip_suffix('"')?;
+    let mut string = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        string.push(match c {
+            '\\' => match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                '"' => '"',
+                '\\' => '\\',
+                _ => return None,
+            },
+            '"' => return None,
+            c => c,
+        });
+    }
+    Some(string)
+}
+
+fn instruction<'s>(
+    chunk: &mut Chunk,
+    fixups: &mut Vec<Fixup<'s>>,
+    line: usize,
+    text: &'s str,
+) -> Result<(), AssemblyError> {
+    let mut words = text.split_whitespace();
+    let mnemonic = words.next().unwrap_or_default();
+    let operands: Vec<&str> = words.collect();
+    let op = (0..Op::COUNT as u8)
+        .filter_map(Op::from_u8)
+        .find(|op| format!("{:?}", op) == mnemonic)
+        .ok_or_else(|| error(line, format!("Unknown instruction '{}'.", mnemonic)))?;
+    let expected = match op {
+        _ if op.short_form().is_some() => 1,
+        _ if op.superinstruction().is_some() => 2,
+        _ if op.jump_sign().is_some() => op.operand_len() - 1,
+        _ => op.operand_len().min(1),
+    };
+    let closure = matches!(op, Op::Closure | Op::ClosureLong);
+    let has_captures = closure && operands.len() > 1 && operands.len() % 2 == 1;
+    if operands.len() != expected && !has_captures {
+        let message = format!(
+            "{} takes {} operand{}, not {}.",
+            mnemonic,
+            expected,
+            if expected == 1 { "" } else { "s" },
+            operands.len()
+        );
+        return Err(error(line, message));
+    }
+    chunk.write(op.u8(), line);
+    match op {
+        _ if op.short_form().is_some() => {
+            let index: u32 = operand(line, operands[0])?;
+            if index >= 1 << 24 {
+                return Err(error(line, format!("Invalid operand '{}'.", operands[0])));
+            }
+            for byte in &index.to_le_bytes()[..3] {
+                chunk.write(*byte, line);
+            }
+            captures(chunk, line, op, &operands[1..])?;
+        }
+        _ if op.jump_sign().is_some() => {
+            let (target, slot) = operands.split_last().expect("jumps take a target");
+            for slot in slot {
+                chunk.write(operand(line, slot)?, line);
+            }
+            fixups.push(Fixup {
+                line,
+                at: chunk.code.len(),
+                sign: op.jump_sign().expect("checked above"),
+                target,
+            });
+            chunk.write(0, line);
+            chunk.write(0, line);
+        }
+        _ if op.operand_len() == 2 && op.superinstruction().is_none() => {
+            let slot: u16 = operand(line, operands[0])?;
+            for byte in &slot.to_be_bytes() {
+                chunk.write(*byte, line);
+            }
+        }
+        _ => {
+            if let Some(first) = operands.first() {
+                chunk.write(operand(line, first)?, line);
+            }
+            captures(chunk, line, op, operands.get(1..).unwrap_or_default())?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the operands after the first, the captures of a closure written as `local 1`
+fn captures(
+    chunk: &mut Chunk,
+    line: usize,
+    op: Op,
+    operands: &[&str],
+) -> Result<(), AssemblyError> {
+    let closure = matches!(op, Op::Closure | Op::ClosureLong);
+    for text in operands {
+        let byte = match *text {
+            "local" if closure => 1,
+            "upvalue" if closure => 0,
+            _ => operand(line, text)?,
+        };
+        chunk.write(byte, line);
+    }
+    Ok(())
+}
@@ -0,0 +1,210 @@
+use std::{fs, path::Path, process};
+
+use alox_bytecode::{
+    chunk::Chunk,
+    compile,
+    globals::Globals,
+    interner::Interner,
+    load_script,
+    object::Object,
+    serialize::MAGIC,
+    value::Value,
+    vm::{InterpreterError, Vm},
+    AloxError, EXIT_DATA_ERROR, EXIT_IO_ERROR, EXIT_SOFTWARE,
+};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+#[cfg(not(target_arch = "wasm32"))]
+use alox_bytecode::repl::{run_prompt, ReplOptions};
+
+/// Extension `compile` gives its output when `-o` isn't passed
+const BYTECODE_EXTENSION: &str = "aloxc";
+
+fn main() {
+    const VERSION: &str = env!("CARGO_PKG_VERSION");
+    let file = || {
+        Arg::with_name("file")
+            .value_name("FILE")
+            .required(true)
+            .help("Script to read")
+    };
+    let matches = App::new("alox")
+        .version(VERSION)
+        .about("A Lox programming language")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Runs a script, or bytecode written by `compile`")
+                .arg(file())
+                .arg(
+                    Arg::with_name("args")
+                        .value_name("ARGS")
+                        .multiple(true)
+                        .help("Arguments given to the script as the global `args`"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("compile")
+                .about("Compiles a script to bytecode")
+                .arg(file())
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .value_name("OUT")
+                        .takes_value(true)
+                        .help(
+                            "Where to write the bytecode, FILE with an .aloxc extension by default",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("disasm")
+                .about("Prints a script's bytecode without running it")
+                .arg(file()),
+        )
+        .subcommand(
+            SubCommand::with_name("repl")
+                .about("Starts an interactive prompt")
+                .arg(
+                    Arg::with_name("time")
+                        .long("time")
+                        .help("Reports the run time and instruction count of each entry"),
+                )
+                .arg(
+                    Arg::with_name("no-color")
+                        .long("no-color")
+                        .help("Leaves input and errors uncolored"),
+                ),
+        )
+        .get_matches();
+
+    let result = match matches.subcommand() {
+        ("run", Some(args)) => run(args),
+        ("compile", Some(args)) => compile_file(args),
+        ("disasm", Some(args)) => disasm(args),
+        #[cfg(not(target_arch = "wasm32"))]
+        ("repl", Some(args)) => {
+            run_prompt(ReplOptions {
+                timing: args.is_present("time"),
+                no_color: args.is_present("no-color"),
+            });
+            Ok(())
+        }
+        // the prompt needs a terminal, which wasm32 builds don't have
+        #[cfg(target_arch = "wasm32")]
+        ("repl", Some(_)) => Err(Failure::new(EXIT_SOFTWARE, "No REPL in wasm builds.")),
+        _ => unreachable!("clap requires a subcommand"),
+    };
+    if let Err(failure) = result {
+        if let Some(message) = failure.message {
+            eprintln!("{}", message);
+        }
+        process::exit(failure.code);
+    }
+}
+
+/// Why a subcommand failed, and the status to exit with
+struct Failure {
+    code: i32,
+    /// Printed before exiting, `None` when the script ended itself with `exit`
+    message: Option<String>,
+}
+
+impl Failure {
+    fn new(code: i32, message: impl ToString) -> Self {
+        Self {
+            code,
+            message: Some(message.to_string()),
+        }
+    }
+}
+
+impl From<AloxError> for Failure {
+    fn from(error: AloxError) -> Self {
+        match error {
+            AloxError::Compile(_) => Failure::new(EXIT_DATA_ERROR, error),
+            AloxError::Runtime { error, .. } => error.into(),
+        }
+    }
+}
+
+impl From<InterpreterError> for Failure {
+    fn from(error: InterpreterError) -> Self {
+        match error {
+            InterpreterError::Exit(code) => Failure {
+                code,
+                message: None,
+            },
+            _ => Failure::new(EXIT_SOFTWARE, error),
+        }
+    }
+}
+
+fn run(args: &ArgMatches) -> Result<(), Failure> {
+    let path = args.value_of("file").expect("file is required");
+    let script_args: Vec<String> = args
+        .values_of("args")
+        .map(|values| values.map(str::to_string).collect())
+        .unwrap_or_default();
+    let bytes = fs::read(path)
+        .map_err(|err| Failure::new(EXIT_IO_ERROR, format!("Can't open {}: {}", path, err)))?;
+    let vm = if bytes.starts_with(MAGIC) {
+        let mut interner = Interner::new();
+        let mut globals = Globals::new();
+        let chunk = Chunk::deserialize(&bytes, &mut interner, &mut globals).map_err(|err| {
+            Failure::new(EXIT_DATA_ERROR, format!("Can't load {}: {}", path, err))
+        })?;
+        Vm::new(chunk, interner, globals)
+    } else {
+        let source = String::from_utf8(bytes)
+            .map_err(|_| Failure::new(EXIT_DATA_ERROR, format!("{} isn't UTF-8", path)))?;
+        load_script(&source).map_err(AloxError::Compile)?
+    };
+    let mut vm = vm.with_script_path(path);
+    vm.set_args(&script_args)?;
+    let result = vm.run();
+    #[cfg(feature = "vm-stats")]
+    eprint!("{}", vm.stats());
+    Ok(result?)
+}
+
+fn compile_file(args: &ArgMatches) -> Result<(), Failure> {
+    let path = args.value_of("file").expect("file is required");
+    let output = match args.value_of("output") {
+        Some(output) => output.into(),
+        None => Path::new(path).with_extension(BYTECODE_EXTENSION),
+    };
+    let (chunk, interner, globals) = compile_source(path)?;
+    fs::write(&output, chunk.serialize(&interner, &globals)).map_err(|err| {
+        let message = format!("Can't write {}: {}", output.display(), err);
+        Failure::new(EXIT_IO_ERROR, message)
+    })
+}
+
+fn disasm(args: &ArgMatches) -> Result<(), Failure> {
+    let path = args.value_of("file").expect("file is required");
+    let (chunk, interner, _) = compile_source(path)?;
+    print!("{}", chunk.disassemble("<script>", &interner));
+    print_functions(&chunk, &interner);
+    Ok(())
+}
+
+/// Prints the bytecode of the functions among `chunk`'s constants, and of those they define
+fn print_functions(chunk: &Chunk, interner: &Interner) {
+    for constant in &chunk.constants {
+        if let Value::Obj(Object::Function(function)) = constant {
+            let name = function.name.map_or("<fn>", |name| interner.lookup(name.0));
+            print!("{}", function.chunk.disassemble(name, interner));
+            print_functions(&function.chunk, interner);
+        }
+    }
+}
+
+fn compile_source(path: &str) -> Result<(Chunk, Interner, Globals), Failure> {
+    let source = fs::read_to_string(path)
+        .map_err(|err| Failure::new(EXIT_IO_ERROR, format!("Can't open {}: {}", path, err)))?;
+    let mut interner = Interner::new();
+    let mut globals = Globals::new();
+    let chunk = compile(&source, &mut interner, &mut globals).map_err(AloxError::Compile)?;
+    Ok((chunk, interner, globals))
+}
@@ -1,7 +1,9 @@
-use std::fs;
+use std::{fs, process};
 
-use alox_bytecode::repl::run_prompt;
-use clap::{App, Arg, SubCommand};
+#[cfg(not(target_arch = "wasm32"))]
+use alox_bytecode::repl::{run_prompt, ReplOptions};
+use alox_bytecode::{vm::InterpreterError, AloxError, EXIT_IO_ERROR};
+use clap::{App, Arg, ArgMatches, SubCommand};
 
 fn main() {
     // let mut chunk = Chunk::init();
@@ -31,19 +33,60 @@ fn main() {
                 .takes_value(true)
                 .help("Sets an input script file to run"),
         )
-        .subcommand(SubCommand::with_name("repl").about("a REPL"))
+        .subcommand(
+            SubCommand::with_name("repl")
+                .about("a REPL")
+                .arg(
+                    Arg::with_name("time")
+                        .long("time")
+                        .help("Reports the run time and instruction count of each entry"),
+                )
+                .arg(
+                    Arg::with_name("no-color")
+                        .long("no-color")
+                        .help("Leaves input and errors uncolored"),
+                ),
+        )
         .get_matches();
 
-    if let Some("repl") = matches.subcommand_name() {
-        run_prompt()
-    }
     if let Some(filepath) = matches.value_of("script") {
         let file = fs::read_to_string(filepath);
         match file {
-            Ok(contents) => alox_bytecode::run_script(&contents),
-            Err(err) => println!("Can't open file: {:?}", err),
+            Ok(contents) => {
+                if let Err(err) = alox_bytecode::run_script_at(filepath, &contents, &[]) {
+                    // a script ending itself with `exit` has nothing to report
+                    if !matches!(
+                        err,
+                        AloxError::Runtime {
+                            error: InterpreterError::Exit(_),
+                            ..
+                        }
+                    ) {
+                        eprintln!("{}", err);
+                    }
+                    process::exit(err.exit_code());
+                }
+            }
+            Err(err) => {
+                eprintln!("Can't open {}: {}", filepath, err);
+                process::exit(EXIT_IO_ERROR);
+            }
         }
     } else {
-        run_prompt()
+        prompt(matches.subcommand_matches("repl"))
     }
 }
+
+#[cfg(not(target_arch = "wasm32"))]
+fn prompt(repl: Option<&ArgMatches>) {
+    run_prompt(ReplOptions {
+        timing: repl.is_some_and(|repl| repl.is_present("time")),
+        no_color: repl.is_some_and(|repl| repl.is_present("no-color")),
+    })
+}
+
+/// The prompt needs a terminal, which wasm32 builds don't have
+#[cfg(target_arch = "wasm32")]
+fn prompt(_: Option<&ArgMatches>) {
+    eprintln!("No REPL in wasm builds.");
+}
@@ -0,0 +1,118 @@
+use std::io::Write;
+
+use crate::{
+    chunk::Chunk,
+    globals::Globals,
+    interner::Interner,
+    vm::{Capabilities, TraceOptions, Vm, VmConfig},
+};
+
+/// Gathers the settings of a `Vm` before it's created. `VmBuilder::sandboxed` starts from
+/// a VM that can't reach outside itself, for running untrusted scripts
+#[derive(Default)]
+pub struct VmBuilder<'vm> {
+    config: VmConfig,
+    capabilities: Capabilities,
+    without_prelude: bool,
+    fuel: Option<u64>,
+    trace: TraceOptions,
+    output: Option<Box<dyn Write + Send + 'vm>>,
+    error_output: Option<Box<dyn Write + Send + 'vm>>,
+}
+
+impl<'vm> VmBuilder<'vm> {
+    /// Every capability allowed, default limits and no fuel limit, like `Vm::new`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// No capabilities, so scripts can only compute and print. Add `fuel` to also stop
+    /// scripts that never finish
+    pub fn sandboxed() -> Self {
+        Self::new().capabilities(Capabilities::none())
+    }
+
+    pub fn config(mut self, config: VmConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn max_stack(mut self, max_stack: usize) -> Self {
+        self.config = self.config.max_stack(max_stack);
+        self
+    }
+
+    pub fn max_frames(mut self, max_frames: usize) -> Self {
+        self.config = self.config.max_frames(max_frames);
+        self
+    }
+
+    /// See `Vm::without_prelude`
+    pub fn without_prelude(mut self) -> Self {
+        self.without_prelude = true;
+        self
+    }
+
+    pub fn capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn allow_io(mut self, allow: bool) -> Self {
+        self.capabilities.io = allow;
+        self
+    }
+
+    pub fn allow_clock(mut self, allow: bool) -> Self {
+        self.capabilities.clock = allow;
+        self
+    }
+
+    pub fn allow_environment(mut self, allow: bool) -> Self {
+        self.capabilities.environment = allow;
+        self
+    }
+
+    /// See `Vm::with_fuel`
+    pub fn fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    pub fn trace(mut self, trace: TraceOptions) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    /// Where `print` writes, stdout unless set
+    pub fn output(mut self, writer: impl Write + Send + 'vm) -> Self {
+        self.output = Some(Box::new(writer));
+        self
+    }
+
+    /// Where `interpret_current_chunk` reports failures, stderr unless set
+    pub fn error_output(mut self, writer: impl Write + Send + 'vm) -> Self {
+        self.error_output = Some(Box::new(writer));
+        self
+    }
+
+    /// A VM ready to run `chunk`, which must have been compiled against `globals`
+    pub fn build(self, chunk: Chunk, interner: Interner, globals: Globals) -> Vm<'vm> {
+        let mut vm = Vm::with_config(chunk, interner, globals, self.config)
+            .with_capabilities(self.capabilities);
+        if self.without_prelude {
+            vm = vm.without_prelude();
+        }
+        if let Some(fuel) = self.fuel {
+            vm = vm.with_fuel(fuel);
+        }
+        if let Some(output) = self.output {
+            vm = vm.with_output(output);
+        }
+        if let Some(error_output) = self.error_output {
+            vm = vm.with_error_output(error_output);
+        }
+        vm.set_trace(self.trace);
+        vm
+    }
+}
@@ -1,10 +1,50 @@
-use crate::{interner::Interner, object::Object, opcodes::Op, value::Value};
-use std::usize;
+use std::fmt;
+
+use ahash::AHashMap;
+
+use crate::{
+    interner::Interner,
+    object::Object,
+    opcodes::{FusedOperand, Op},
+    value::Value,
+};
 #[derive(Clone)]
 pub struct Chunk {
     pub code: Vec<u8>,
     pub constants: Vec<Value>,
-    pub lines: Vec<usize>,
+    /// Source line of each run of bytes, as `(line, count)` pairs in code order
+    pub lines: Vec<(usize, usize)>,
+    /// Index of each number and string already in `constants`, so repeats share a slot
+    constant_indices: AHashMap<ConstantKey, usize>,
+}
+
+/// Hashable identity of a constant that can safely be shared. Numbers compare by bits so
+/// `0` and `-0` stay distinct
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Number(u64),
+    Int(i64),
+    String(u32),
+}
+
+impl ConstantKey {
+    fn of(value: &Value) -> Option<Self> {
+        match value {
+            Value::Number(number) => Some(ConstantKey::Number(number.to_bits())),
+            Value::Int(int) => Some(ConstantKey::Int(*int)),
+            Value::Obj(Object::String(string)) => Some(ConstantKey::String(string.0)),
+            _ => None,
+        }
+    }
+}
+
+/// Adds `count` bytes from `line` to run-length encoded `lines`, extending the last run if
+/// it is the same line
+pub(crate) fn push_line(lines: &mut Vec<(usize, usize)>, line: usize, count: usize) {
+    match lines.last_mut() {
+        Some((last, run)) if *last == line => *run += count,
+        _ => lines.push((line, count)),
+    }
 }
 
 impl Chunk {
@@ -13,26 +53,88 @@ impl Chunk {
             code: Vec::new(),
             constants: Vec::new(),
             lines: Vec::new(),
+            constant_indices: AHashMap::new(),
         }
     }
     pub fn write(&mut self, byte: u8, line: usize) {
-        self.lines.push(line);
+        push_line(&mut self.lines, line, 1);
         self.code.push(byte);
     }
 
-    pub fn disassemble(&mut self, name: &str, interner: &Interner) {
-        println!("== {} ==", name);
+    /// Source line of the byte at `offset`, `None` past the bytes the lines cover
+    pub fn line_at(&self, offset: usize) -> Option<usize> {
+        let mut end = 0;
+        for &(line, count) in &self.lines {
+            end += count;
+            if offset < end {
+                return Some(line);
+            }
+        }
+        None
+    }
+
+    /// Every instruction in human readable form, under a `name` header
+    pub fn disassemble(&self, name: &str, interner: &Interner) -> String {
+        let mut listing = format!("== {} ==\n", name);
+        self.disassemble_to(&mut listing, interner)
+            .expect("writing to a String can't fail");
+        listing
+    }
+
+    /// Writes every instruction in human readable form to `out`
+    pub fn disassemble_to(&self, out: &mut impl fmt::Write, interner: &Interner) -> fmt::Result {
         let mut offset = 0;
-        loop {
-            if offset >= self.code.len() {
-                break;
+        while offset < self.code.len() {
+            offset = self.write_instruction(out, offset, interner)?;
+        }
+        Ok(())
+    }
+
+    /// Length in bytes of the instruction starting at `offset`, including its operands
+    pub fn instruction_len(&self, offset: usize) -> usize {
+        let op = match Op::from_u8(self.code[offset]) {
+            Some(op) => op,
+            None => return 1,
+        };
+        let upvalues = match op {
+            Op::Closure | Op::ClosureLong => {
+                match self
+                    .constant_index(offset)
+                    .and_then(|index| self.constants.get(index))
+                {
+                    Some(Value::Obj(Object::Function(function))) => function.upvalue_count,
+                    _ => 0,
+                }
             }
-            offset = self.disassemble_instruction(offset, interner);
+            _ => 0,
+        };
+        1 + op.operand_len() + upvalues * 2
+    }
+
+    /// Index of the constant the instruction at `offset` takes, `None` if it takes none or
+    /// its operand is cut off
+    pub fn constant_index(&self, offset: usize) -> Option<usize> {
+        let op = Op::from_u8(*self.code.get(offset)?)?;
+        if op.short_form().is_some() {
+            let bytes = self.code.get(offset + 1..offset + 4)?;
+            Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]) as usize)
+        } else if op.long_form().is_some() {
+            self.code.get(offset + 1).map(|&index| index as usize)
+        } else {
+            None
         }
     }
 
+    /// Where the jump instruction at `offset` lands, `None` for other instructions
+    pub fn jump_target(&self, offset: usize) -> Option<usize> {
+        let op = Op::from_u8(self.code[offset])?;
+        let sign = op.jump_sign()?;
+        let end = offset + 1 + op.operand_len();
+        let jump = u16::from_be_bytes([self.code[end - 2], self.code[end - 1]]);
+        Some((end as i64 + sign * jump as i64) as usize)
+    }
+
     pub fn write_constant(&mut self, value: Value, line: usize) {
-        self.lines.push(line);
         let constant = self.add_constant(value);
         if constant < 256 {
             self.write(Op::Constant.u8(), line);
@@ -48,61 +150,273 @@ impl Chunk {
     }
 
     pub fn add_constant(&mut self, value: Value) -> usize {
+        let key = ConstantKey::of(&value);
+        if let Some(&index) = key.and_then(|key| self.constant_indices.get(&key)) {
+            return index;
+        }
         self.constants.push(value);
-        self.constants.len() - 1
+        let index = self.constants.len() - 1;
+        if let Some(key) = key {
+            self.constant_indices.insert(key, index);
+        }
+        index
     }
 
+    /// Appends a constant without sharing a slot with an equal one, for chunks rebuilt from
+    /// an existing constant table
+    pub(crate) fn push_constant(&mut self, value: Value) {
+        let index = self.constants.len();
+        if let Some(key) = ConstantKey::of(&value) {
+            self.constant_indices.entry(key).or_insert(index);
+        }
+        self.constants.push(value);
+    }
+
+    /// Prints the instruction at `offset`, returning the offset of the next one
     pub fn disassemble_instruction(&self, offset: usize, interner: &Interner) -> usize {
-        print!("{:04} ", offset);
+        let mut line = String::new();
+        let next = self
+            .write_instruction(&mut line, offset, interner)
+            .expect("writing to a String can't fail");
+        print!("{}", line);
+        next
+    }
 
-        if offset > 0 && self.lines[offset] == self.lines[offset - 1] {
-            print!("    | ");
+    /// Writes the instruction at `offset` in human readable form, returning the offset of the
+    /// next one
+    pub fn write_instruction(
+        &self,
+        out: &mut dyn fmt::Write,
+        offset: usize,
+        interner: &Interner,
+    ) -> Result<usize, fmt::Error> {
+        write!(out, "{:04} ", offset)?;
+
+        let line = self.line_at(offset);
+        if offset > 0 && line == self.line_at(offset - 1) {
+            write!(out, "    | ")?;
         } else {
-            print!("  {} ", self.lines[offset]);
+            match line {
+                Some(line) => write!(out, "  {} ", line)?,
+                None => write!(out, "  ? ")?,
+            }
         }
 
         let instruction = self.code[offset];
-        let opcode = Op::from_u8(instruction);
+        let opcode = match Op::from_u8(instruction) {
+            Some(opcode) => opcode,
+            None => {
+                writeln!(out, "Unknown opcode {}", instruction)?;
+                return Ok(offset + 1);
+            }
+        };
+        if offset + 1 + opcode.operand_len() > self.code.len() {
+            writeln!(out, "{:?}\t{} <cut off>", opcode, offset)?;
+            return Ok(self.code.len());
+        }
 
         match opcode {
-            Op::Constant => self.print_constant_instruction(opcode, offset, interner),
-            Op::DefineGlobal => self.print_constant_instruction(opcode, offset, interner),
-            Op::GetGlobal => self.print_constant_instruction(opcode, offset, interner),
-            Op::SetGlobal => self.print_constant_instruction(opcode, offset, interner),
-            Op::SetLocal => self.print_byte_instruction(opcode, offset),
-            Op::GetLocal => self.print_byte_instruction(opcode, offset),
-            Op::ConstantLong => self.print_constant_long_instruction(opcode, offset, interner),
+            Op::Constant => self.print_constant_instruction(out, opcode, offset, interner),
+            Op::DefineGlobal => self.print_global_instruction(out, opcode, offset),
+            Op::DefineGlobalConst => self.print_global_instruction(out, opcode, offset),
+            Op::GetGlobal => self.print_global_instruction(out, opcode, offset),
+            Op::SetGlobal => self.print_global_instruction(out, opcode, offset),
+            Op::SetLocal => self.print_byte_instruction(out, opcode, offset),
+            Op::GetLocal => self.print_byte_instruction(out, opcode, offset),
+            Op::ConstantLong
+            | Op::ClassLong
+            | Op::ImportLong
+            | Op::AssertLong
+            | Op::MethodLong
+            | Op::GetterLong
+            | Op::SetterLong
+            | Op::GetPropertyLong
+            | Op::SetPropertyLong => {
+                self.print_constant_long_instruction(out, opcode, offset, interner)
+            }
+            Op::Call | Op::TailCall => self.print_byte_instruction(out, opcode, offset),
+            Op::PopN => self.print_byte_instruction(out, opcode, offset),
+            Op::BuildString => self.print_byte_instruction(out, opcode, offset),
+            Op::BuildList => self.print_byte_instruction(out, opcode, offset),
+            Op::BuildMap => self.print_byte_instruction(out, opcode, offset),
+            Op::BuildTuple => self.print_byte_instruction(out, opcode, offset),
+            Op::IsType => self.print_byte_instruction(out, opcode, offset),
+            Op::Unpack => self.print_byte_instruction(out, opcode, offset),
+            Op::Class => self.print_constant_instruction(out, opcode, offset, interner),
+            Op::Import => self.print_constant_instruction(out, opcode, offset, interner),
+            Op::Assert => self.print_constant_instruction(out, opcode, offset, interner),
+            Op::Method | Op::Getter | Op::Setter => {
+                self.print_constant_instruction(out, opcode, offset, interner)
+            }
+            Op::GetProperty => self.print_constant_instruction(out, opcode, offset, interner),
+            Op::SetProperty => self.print_constant_instruction(out, opcode, offset, interner),
+            Op::Jump => self.print_jump_instruction(out, opcode, 1, offset),
+            Op::JumpIfFalse => self.print_jump_instruction(out, opcode, 1, offset),
+            Op::JumpIfNil => self.print_jump_instruction(out, opcode, 1, offset),
+            Op::Loop => self.print_jump_instruction(out, opcode, -1, offset),
+            Op::LoopIfTrue => self.print_jump_instruction(out, opcode, -1, offset),
+            Op::PushHandler => self.print_jump_instruction(out, opcode, 1, offset),
+            Op::IterNext | Op::JumpIfPassed => {
+                self.print_slot_jump_instruction(out, opcode, offset)
+            }
+            Op::Closure | Op::ClosureLong => {
+                self.print_closure_instruction(out, opcode, offset, interner)
+            }
+            Op::GetUpvalue => self.print_byte_instruction(out, opcode, offset),
+            Op::GetLocalConstantAdd
+            | Op::GetLocalConstantSubtract
+            | Op::GetLocalConstantLess
+            | Op::GetLocalGetLocalAdd
+            | Op::GetLocalGetLocalLess => self.print_superinstruction(out, opcode, offset),
+            Op::SetUpvalue => self.print_byte_instruction(out, opcode, offset),
             _default => {
-                println!("{:?}", opcode);
-                offset + 1
+                writeln!(out, "{:?}", opcode)?;
+                Ok(offset + 1)
             }
         }
     }
 
-    fn print_byte_instruction(&self, op: Op, offset: usize) -> usize {
+    fn print_byte_instruction(
+        &self,
+        out: &mut dyn fmt::Write,
+        op: Op,
+        offset: usize,
+    ) -> Result<usize, fmt::Error> {
         let slot = self.code[offset + 1];
-        println!("{:?}\t{} Slot {}", op, offset, slot);
-        offset + 2
+        writeln!(out, "{:?}\t{} Slot {}", op, offset, slot)?;
+        Ok(offset + 2)
     }
 
-    fn print_constant_instruction(&self, op: Op, offset: usize, interner: &Interner) -> usize {
-        let constant = self.code[offset + 1];
-        let value = &self.constants[constant as usize];
-        match value {
-            Value::Obj(obj) => match obj {
-                Object::String(str) => println!(
-                    "{:?}\t{} '{:?}'",
-                    op,
-                    offset,
-                    (str.0, interner.lookup(str.0))
+    fn print_global_instruction(
+        &self,
+        out: &mut dyn fmt::Write,
+        op: Op,
+        offset: usize,
+    ) -> Result<usize, fmt::Error> {
+        let slot = u16::from_be_bytes([self.code[offset + 1], self.code[offset + 2]]);
+        writeln!(out, "{:?}\t{} Global {}", op, offset, slot)?;
+        Ok(offset + 3)
+    }
+
+    fn print_superinstruction(
+        &self,
+        out: &mut dyn fmt::Write,
+        op: Op,
+        offset: usize,
+    ) -> Result<usize, fmt::Error> {
+        let slot = self.code[offset + 1];
+        let operand = self.code[offset + 2];
+        match op.superinstruction() {
+            Some((FusedOperand::Constant, _)) => match self.constants.get(operand as usize) {
+                Some(value) => writeln!(out, "{:?}\t{} Slot {} '{}'", op, offset, slot, value),
+                None => writeln!(
+                    out,
+                    "{:?}\t{} Slot {} <bad constant {}>",
+                    op, offset, slot, operand
                 ),
             },
-            _ => println!("{:?} \t{} '{}'", op, offset, value),
+            _ => writeln!(out, "{:?}\t{} Slot {} Slot {}", op, offset, slot, operand),
+        }?;
+        Ok(offset + 3)
+    }
+
+    fn print_closure_instruction(
+        &self,
+        out: &mut dyn fmt::Write,
+        op: Op,
+        offset: usize,
+        interner: &Interner,
+    ) -> Result<usize, fmt::Error> {
+        let upvalue_count = match self
+            .constant_index(offset)
+            .and_then(|index| self.constants.get(index))
+        {
+            Some(Value::Obj(Object::Function(function))) => function.upvalue_count,
+            _ => 0,
+        };
+        let mut offset = match op {
+            Op::ClosureLong => self.print_constant_long_instruction(out, op, offset, interner)?,
+            _ => self.print_constant_instruction(out, op, offset, interner)?,
+        };
+        for _ in 0..upvalue_count {
+            if offset + 2 > self.code.len() {
+                writeln!(out, "{:04}    |                     <cut off>", offset)?;
+                return Ok(self.code.len());
+            }
+            let is_local = self.code[offset];
+            let index = self.code[offset + 1];
+            let kind = if is_local == 1 { "local" } else { "upvalue" };
+            writeln!(
+                out,
+                "{:04}    |                     {} {}",
+                offset, kind, index
+            )?;
+            offset += 2;
         }
-        offset + 2
+        Ok(offset)
+    }
+
+    fn print_slot_jump_instruction(
+        &self,
+        out: &mut dyn fmt::Write,
+        op: Op,
+        offset: usize,
+    ) -> Result<usize, fmt::Error> {
+        let slot = self.code[offset + 1];
+        let jump = u16::from_be_bytes([self.code[offset + 2], self.code[offset + 3]]);
+        writeln!(
+            out,
+            "{:?}\t{} Slot {} -> {}",
+            op,
+            offset,
+            slot,
+            offset + 4 + jump as usize
+        )?;
+        Ok(offset + 4)
+    }
+
+    fn print_jump_instruction(
+        &self,
+        out: &mut dyn fmt::Write,
+        op: Op,
+        sign: i64,
+        offset: usize,
+    ) -> Result<usize, fmt::Error> {
+        let jump = u16::from_be_bytes([self.code[offset + 1], self.code[offset + 2]]);
+        let target = offset as i64 + 3 + sign * jump as i64;
+        writeln!(out, "{:?}\t{} -> {}", op, offset, target)?;
+        Ok(offset + 3)
     }
 
-    fn print_constant_long_instruction(&self, op: Op, offset: usize, interner: &Interner) -> usize {
+    fn print_constant_instruction(
+        &self,
+        out: &mut dyn fmt::Write,
+        op: Op,
+        offset: usize,
+        interner: &Interner,
+    ) -> Result<usize, fmt::Error> {
+        let constant = self.code[offset + 1];
+        match self.constants.get(constant as usize) {
+            Some(Value::Obj(Object::String(str))) => writeln!(
+                out,
+                "{:?}\t{} '{:?}'",
+                op,
+                offset,
+                (str.0, interner.lookup(str.0))
+            ),
+            Some(value) => writeln!(out, "{:?} \t{} '{}'", op, offset, value.display(interner)),
+            None => writeln!(out, "{:?} \t{} <bad constant {}>", op, offset, constant),
+        }?;
+        Ok(offset + 2)
+    }
+
+    fn print_constant_long_instruction(
+        &self,
+        out: &mut dyn fmt::Write,
+        op: Op,
+        offset: usize,
+        interner: &Interner,
+    ) -> Result<usize, fmt::Error> {
         let start = offset + 1;
         let end = offset + 3;
         let mut index = [0u8; 4];
@@ -111,19 +425,18 @@ impl Chunk {
         num.copy_from_slice(constant);
         padding.fill(0);
         let constant = u32::from_le_bytes(index);
-        let value = &self.constants[constant as usize];
 
-        match value {
-            Value::Obj(obj) => match obj {
-                Object::String(str) => println!(
-                    "{:?} \t{} '{:?}'",
-                    op,
-                    offset,
-                    (str.0, interner.lookup(str.0))
-                ),
-            },
-            _ => println!("{:?} \t{} '{}'", op, offset, value),
-        }
-        offset + 4
+        match self.constants.get(constant as usize) {
+            Some(Value::Obj(Object::String(str))) => writeln!(
+                out,
+                "{:?} \t{} '{:?}'",
+                op,
+                offset,
+                (str.0, interner.lookup(str.0))
+            ),
+            Some(value) => writeln!(out, "{:?} \t{} '{}'", op, offset, value.display(interner)),
+            None => writeln!(out, "{:?} \t{} <bad constant {}>", op, offset, constant),
+        }?;
+        Ok(offset + 4)
     }
 }
@@ -1,27 +1,162 @@
-use crate::token::Token;
+use crate::{
+    object::{AloxString, ObjFunction},
+    opcodes::Op,
+    token::{Token, TokenKind},
+};
 
 pub const U8_COUNT: usize = (u8::MAX as usize) + 1;
 
 pub struct Compiler<'a> {
+    pub enclosing: Option<Box<Compiler<'a>>>,
+    pub function: ObjFunction,
+    pub kind: FunctionKind,
     pub locals: [Local<'a>; U8_COUNT],
     pub count: usize,
     pub scope_depth: i32,
+    pub loops: Vec<LoopContext>,
+    pub upvalues: Vec<UpvalueRef>,
+    /// Number of `try` blocks around the code being compiled
+    pub try_depth: usize,
+    /// Offset of the last `Call` emitted, a `return` directly after it becomes a tail call
+    pub last_call: Option<usize>,
+    /// End offset, get op and operand of the last variable read, so `a[i] += e` can read `a` again
+    pub last_read: Option<(usize, Op, i32)>,
 }
 
 #[derive(Clone, Default, Copy, Debug)]
 pub struct Local<'a> {
     pub name: Token<'a>,
     pub depth: i32,
+    /// Whether a closure captures this local, so it must be closed instead of popped
+    pub is_captured: bool,
+    pub is_const: bool,
+}
+
+/// Where a closure finds a captured variable when it is created
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UpvalueRef {
+    pub index: u8,
+    /// True if `index` is a local slot of the enclosing function, false if it is one of its upvalues
+    pub is_local: bool,
+    /// Whether the captured variable was declared `const`
+    pub is_const: bool,
+}
+
+/// Bookkeeping for a loop whose body is currently being compiled
+#[derive(Clone, Debug)]
+pub struct LoopContext {
+    /// Offset `continue` jumps back to, the increment clause or the condition.
+    /// `None` while the target lies ahead, as with the condition of a do-while
+    pub start: Option<usize>,
+    /// Offsets of forward `continue` jumps waiting for the target to be known
+    pub continues: Vec<usize>,
+    /// Scope depth outside the loop body, locals deeper than this are popped on exit
+    pub scope_depth: i32,
+    /// Offsets of `break` jumps waiting to be patched to the loop exit
+    pub breaks: Vec<usize>,
+    /// `try` depth outside the loop, handlers deeper than this are popped on exit
+    pub try_depth: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FunctionKind {
+    Function,
+    Getter,
+    Initializer,
+    Method,
+    Script,
+    Setter,
+}
+
+impl FunctionKind {
+    /// Whether slot zero holds `this` rather than the callee
+    pub fn has_receiver(self) -> bool {
+        matches!(
+            self,
+            FunctionKind::Getter
+                | FunctionKind::Initializer
+                | FunctionKind::Method
+                | FunctionKind::Setter
+        )
+    }
 }
 
 impl Compiler<'_> {
-    pub fn new() -> Self {
-        let locals = [Local::default(); U8_COUNT];
+    pub fn new(kind: FunctionKind, name: Option<AloxString>) -> Self {
+        let mut locals = [Local::default(); U8_COUNT];
+        // slot zero holds the callee, or the receiver inside methods
+        if kind.has_receiver() {
+            locals[0].name = Token {
+                kind: TokenKind::This,
+                line: 0,
+                column: 0,
+                lexeme: "this",
+            };
+        }
         Self {
-            count: 0,
+            enclosing: None,
+            function: ObjFunction::new(name),
+            kind,
+            count: 1,
             scope_depth: 0,
             locals,
+            loops: Vec::new(),
+            upvalues: Vec::new(),
+            try_depth: 0,
+            last_call: None,
+            last_read: None,
+        }
+    }
+
+    /// Finds the slot of a local by name, and whether it has finished initializing
+    pub fn resolve_local(&self, name: &str) -> Option<(usize, bool)> {
+        self.locals[..self.count]
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, local)| local.name.lexeme == name)
+            .map(|(slot, local)| (slot, local.depth != -1))
+    }
+
+    /// Resolves a variable from an enclosing function, threading it through every
+    /// function in between
+    pub fn resolve_upvalue(&mut self, name: &str) -> Result<Option<u8>, &'static str> {
+        let enclosing = match self.enclosing.as_mut() {
+            Some(enclosing) => enclosing,
+            None => return Ok(None),
+        };
+        if let Some((slot, _)) = enclosing.resolve_local(name) {
+            enclosing.locals[slot].is_captured = true;
+            let is_const = enclosing.locals[slot].is_const;
+            return self.add_upvalue(slot as u8, true, is_const).map(Some);
+        }
+        if let Some(index) = enclosing.resolve_upvalue(name)? {
+            let is_const = enclosing.upvalues[index as usize].is_const;
+            return self.add_upvalue(index, false, is_const).map(Some);
+        }
+        Ok(None)
+    }
+
+    fn add_upvalue(
+        &mut self,
+        index: u8,
+        is_local: bool,
+        is_const: bool,
+    ) -> Result<u8, &'static str> {
+        let upvalue = UpvalueRef {
+            index,
+            is_local,
+            is_const,
+        };
+        if let Some(existing) = self.upvalues.iter().position(|u| *u == upvalue) {
+            return Ok(existing as u8);
+        }
+        if self.upvalues.len() == U8_COUNT {
+            return Err("Too many closure variables in function.");
         }
+        self.upvalues.push(upvalue);
+        self.function.upvalue_count = self.upvalues.len();
+        Ok((self.upvalues.len() - 1) as u8)
     }
 
     #[inline]
@@ -34,3 +169,9 @@ impl Compiler<'_> {
         self.scope_depth -= 1;
     }
 }
+
+impl Default for Compiler<'_> {
+    fn default() -> Self {
+        Self::new(FunctionKind::Script, None)
+    }
+}
@@ -0,0 +1,169 @@
+use std::fmt::Display;
+
+use crate::{interner::Interner, object::Object, value::Value};
+
+/// Rust values that can be handed to scripts
+pub trait IntoAloxValue {
+    /// Converts the value, interning any strings in `interner`
+    fn into_value(self, interner: &mut Interner) -> Value;
+}
+
+/// Rust values that can be read out of script values
+pub trait FromAloxValue: Sized {
+    /// Converts `value`, looking up its strings in `interner`
+    fn from_value(value: &Value, interner: &Interner) -> Result<Self, ConversionError>;
+}
+
+/// A value wasn't of the type a conversion expected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversionError {
+    pub expected: &'static str,
+    pub found: &'static str,
+}
+
+impl ConversionError {
+    fn new(expected: &'static str, found: &Value) -> Self {
+        Self {
+            expected,
+            found: found.type_name(),
+        }
+    }
+}
+
+impl Display for ConversionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Expected {} but got {}.", self.expected, self.found)
+    }
+}
+
+impl IntoAloxValue for Value {
+    fn into_value(self, _: &mut Interner) -> Value {
+        self
+    }
+}
+
+impl IntoAloxValue for () {
+    fn into_value(self, _: &mut Interner) -> Value {
+        Value::Nil
+    }
+}
+
+impl IntoAloxValue for bool {
+    fn into_value(self, _: &mut Interner) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl IntoAloxValue for f64 {
+    fn into_value(self, _: &mut Interner) -> Value {
+        Value::Number(self)
+    }
+}
+
+impl IntoAloxValue for i64 {
+    fn into_value(self, _: &mut Interner) -> Value {
+        Value::Int(self)
+    }
+}
+
+impl IntoAloxValue for &str {
+    fn into_value(self, interner: &mut Interner) -> Value {
+        Value::from_str(self, interner)
+    }
+}
+
+impl IntoAloxValue for String {
+    fn into_value(self, interner: &mut Interner) -> Value {
+        Value::from_string(self, interner)
+    }
+}
+
+/// `None` becomes `nil`
+impl<T: IntoAloxValue> IntoAloxValue for Option<T> {
+    fn into_value(self, interner: &mut Interner) -> Value {
+        match self {
+            Some(value) => value.into_value(interner),
+            None => Value::Nil,
+        }
+    }
+}
+
+/// Vectors become lists
+impl<T: IntoAloxValue> IntoAloxValue for Vec<T> {
+    fn into_value(self, interner: &mut Interner) -> Value {
+        let items = self
+            .into_iter()
+            .map(|item| item.into_value(interner))
+            .collect();
+        Value::from_list(items)
+    }
+}
+
+impl FromAloxValue for Value {
+    fn from_value(value: &Value, _: &Interner) -> Result<Self, ConversionError> {
+        Ok(value.clone())
+    }
+}
+
+impl FromAloxValue for bool {
+    fn from_value(value: &Value, _: &Interner) -> Result<Self, ConversionError> {
+        value
+            .as_bool()
+            .ok_or_else(|| ConversionError::new("bool", value))
+    }
+}
+
+/// Integers are converted too
+impl FromAloxValue for f64 {
+    fn from_value(value: &Value, _: &Interner) -> Result<Self, ConversionError> {
+        value
+            .as_number()
+            .ok_or_else(|| ConversionError::new("number", value))
+    }
+}
+
+/// Floats without a fractional part are converted too
+impl FromAloxValue for i64 {
+    fn from_value(value: &Value, _: &Interner) -> Result<Self, ConversionError> {
+        value
+            .as_integer()
+            .ok_or_else(|| ConversionError::new("int", value))
+    }
+}
+
+impl FromAloxValue for String {
+    fn from_value(value: &Value, interner: &Interner) -> Result<Self, ConversionError> {
+        match value {
+            Value::Obj(Object::String(string)) => Ok(interner.lookup(string.0).to_string()),
+            Value::Obj(Object::StringBuffer(buffer)) => Ok(buffer.contents().to_string()),
+            _ => Err(ConversionError::new("string", value)),
+        }
+    }
+}
+
+/// `nil` becomes `None`
+impl<T: FromAloxValue> FromAloxValue for Option<T> {
+    fn from_value(value: &Value, interner: &Interner) -> Result<Self, ConversionError> {
+        match value {
+            Value::Nil => Ok(None),
+            _ => T::from_value(value, interner).map(Some),
+        }
+    }
+}
+
+/// From a list or a tuple, converting every item
+impl<T: FromAloxValue> FromAloxValue for Vec<T> {
+    fn from_value(value: &Value, interner: &Interner) -> Result<Self, ConversionError> {
+        let convert = |items: &[Value]| {
+            items
+                .iter()
+                .map(|item| T::from_value(item, interner))
+                .collect()
+        };
+        match value {
+            Value::Obj(Object::List(list)) => convert(&list.borrow()),
+            Value::Obj(Object::Tuple(tuple)) => convert(tuple),
+            _ => Err(ConversionError::new("list", value)),
+        }
+    }
+}
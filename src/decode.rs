@@ -0,0 +1,151 @@
+use crate::{
+    chunk::Chunk,
+    object::Object,
+    opcodes::{FusedOperand, Op},
+    value::Value,
+};
+
+/// An instruction read from a chunk, for tools that inspect bytecode without parsing the
+/// disassembler's text
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedInstruction {
+    pub offset: usize,
+    /// Source line the instruction was compiled from, `None` if the chunk has no line for it
+    pub line: Option<usize>,
+    /// `None` for a byte that isn't an opcode, which is skipped on its own
+    pub op: Option<Op>,
+    /// The raw opcode byte, the only thing known about an unknown opcode
+    pub byte: u8,
+    pub operands: Vec<Operand>,
+    /// The constant an `Operand::Constant` refers to, if it is in range
+    pub constant: Option<Value>,
+    /// Offset of the next instruction
+    pub next: usize,
+}
+
+/// An operand of a decoded instruction, labelled with what the VM uses it for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// Index into the chunk's constants
+    Constant(usize),
+    /// Stack slot of a local, counted from the start of the call frame
+    Local(u8),
+    /// Slot of a global variable
+    Global(u16),
+    /// Index into the closure's upvalues
+    Upvalue(u8),
+    /// Number of values taken off the stack, or of arguments passed
+    Count(u8),
+    /// Byte of the `ValueType` an `IsType` checks for
+    ValueType(u8),
+    /// Offset a jump lands on
+    Target(usize),
+    /// A variable captured by a `Closure`, one of the enclosing function's locals or upvalues
+    Capture { is_local: bool, index: u8 },
+}
+
+impl Chunk {
+    /// Decodes the chunk's instructions in order. Stops at an instruction cut off by the end
+    /// of the code
+    pub fn instructions(&self) -> impl Iterator<Item = DecodedInstruction> + '_ {
+        let mut offset = 0;
+        std::iter::from_fn(move || {
+            let instruction = self.decode(offset)?;
+            offset = instruction.next;
+            Some(instruction)
+        })
+    }
+
+    /// Decodes the instruction starting at `offset`, `None` past the end or if it is cut off
+    pub fn decode(&self, offset: usize) -> Option<DecodedInstruction> {
+        let byte = *self.code.get(offset)?;
+        let line = self.line_at(offset);
+        let op = match Op::from_u8(byte) {
+            Some(op) => op,
+            None => {
+                return Some(DecodedInstruction {
+                    offset,
+                    line,
+                    op: None,
+                    byte,
+                    operands: Vec::new(),
+                    constant: None,
+                    next: offset + 1,
+                })
+            }
+        };
+        let bytes = self.code.get(offset + 1..offset + 1 + op.operand_len())?;
+        let short = |at: usize| u16::from_be_bytes([bytes[at], bytes[at + 1]]);
+        let mut operands = match op {
+            Op::Constant
+            | Op::Class
+            | Op::Import
+            | Op::Assert
+            | Op::Method
+            | Op::Getter
+            | Op::Setter
+            | Op::GetProperty
+            | Op::SetProperty
+            | Op::Closure => vec![Operand::Constant(bytes[0] as usize)],
+            _ if op.short_form().is_some() => {
+                let index = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]);
+                vec![Operand::Constant(index as usize)]
+            }
+            Op::GetLocal | Op::SetLocal => vec![Operand::Local(bytes[0])],
+            Op::GetUpvalue | Op::SetUpvalue => vec![Operand::Upvalue(bytes[0])],
+            Op::IsType => vec![Operand::ValueType(bytes[0])],
+            Op::PopN
+            | Op::BuildString
+            | Op::BuildList
+            | Op::BuildMap
+            | Op::BuildTuple
+            | Op::Unpack
+            | Op::Call
+            | Op::TailCall => vec![Operand::Count(bytes[0])],
+            Op::GetGlobal | Op::DefineGlobal | Op::DefineGlobalConst | Op::SetGlobal => {
+                vec![Operand::Global(short(0))]
+            }
+            _ => match op.superinstruction() {
+                Some((FusedOperand::Constant, _)) => vec![
+                    Operand::Local(bytes[0]),
+                    Operand::Constant(bytes[1] as usize),
+                ],
+                Some((FusedOperand::Local, _)) => {
+                    vec![Operand::Local(bytes[0]), Operand::Local(bytes[1])]
+                }
+                None => Vec::new(),
+            },
+        };
+        let mut next = offset + 1 + bytes.len();
+        if let Some(sign) = op.jump_sign() {
+            if op.operand_len() == 3 {
+                operands.push(Operand::Local(bytes[0]));
+            }
+            let jump = short(bytes.len() - 2) as i64;
+            operands.push(Operand::Target((next as i64 + sign * jump) as usize));
+        }
+        let constant = operands.iter().find_map(|operand| match operand {
+            Operand::Constant(index) => self.constants.get(*index).cloned(),
+            _ => None,
+        });
+        if let Some(Value::Obj(Object::Function(function))) = &constant {
+            if matches!(op, Op::Closure | Op::ClosureLong) {
+                let captures = self.code.get(next..next + function.upvalue_count * 2)?;
+                operands.extend(captures.chunks(2).map(|capture| Operand::Capture {
+                    is_local: capture[0] == 1,
+                    index: capture[1],
+                }));
+                next += captures.len();
+            }
+        }
+        Some(DecodedInstruction {
+            offset,
+            line,
+            op: Some(op),
+            byte,
+            operands,
+            constant,
+            next,
+        })
+    }
+}
@@ -0,0 +1,379 @@
+//! C interface for embedding the interpreter, declared in `include/alox.h`. Every function
+//! takes the `AloxVm` returned by `alox_vm_new`, strings are NUL terminated UTF-8
+
+use std::{
+    ffi::{CStr, CString},
+    os::raw::c_char,
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
+
+use crate::{load_script, value::Value, vm::Vm};
+
+/// Outcome of an `alox_*` call
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AloxStatus {
+    Ok = 0,
+    CompileError = 1,
+    RuntimeError = 2,
+    /// A pointer was null or a string wasn't UTF-8
+    InvalidArgument = 3,
+    /// The global doesn't exist
+    NotFound = 4,
+    /// The global, argument or result isn't of the requested type
+    TypeMismatch = 5,
+    /// The interpreter panicked. The VM may be left half updated, free it without using it
+    /// further
+    Panicked = 6,
+}
+
+/// A compiled script and the VM running it, opaque to C
+pub struct AloxVm {
+    /// `None` when the script didn't compile
+    vm: Option<Vm<'static>>,
+    /// Message of the last failure, returned by `alox_last_error`
+    error: Option<CString>,
+}
+
+impl AloxVm {
+    fn fail(&mut self, status: AloxStatus, message: String) -> AloxStatus {
+        // a message can't contain NUL in C
+        self.error = CString::new(message.replace('\0', " ")).ok();
+        status
+    }
+}
+
+/// Runs the body of an `alox_*` function, returning `fallback` if it panics rather than
+/// unwinding into C
+fn catch_panic<T>(fallback: T, body: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or(fallback)
+}
+
+/// Converts a C string argument, `None` if it's null or not UTF-8
+///
+/// # Safety
+/// `string` must be null or point to a NUL terminated string
+unsafe fn str_arg<'a>(string: *const c_char) -> Option<&'a str> {
+    if string.is_null() {
+        return None;
+    }
+    CStr::from_ptr(string).to_str().ok()
+}
+
+/// Compiles `source` into a new VM, to be released with `alox_vm_free`. Returns null only if
+/// `source` is null or not UTF-8, compile errors are reported by `alox_run`
+///
+/// # Safety
+/// `source` must be null or point to a NUL terminated string
+#[no_mangle]
+pub unsafe extern "C" fn alox_vm_new(source: *const c_char) -> *mut AloxVm {
+    catch_panic(ptr::null_mut(), || {
+        let source = match str_arg(source) {
+            Some(source) => source,
+            None => return ptr::null_mut(),
+        };
+        let mut handle = AloxVm {
+            vm: None,
+            error: None,
+        };
+        match load_script(source) {
+            Ok(vm) => handle.vm = Some(vm),
+            Err(diagnostics) => {
+                let message = diagnostics
+                    .iter()
+                    .map(|diagnostic| diagnostic.to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                handle.fail(AloxStatus::CompileError, message);
+            }
+        }
+        Box::into_raw(Box::new(handle))
+    })
+}
+
+/// Releases a VM, doing nothing if `vm` is null
+///
+/// # Safety
+/// `vm` must be null or come from `alox_vm_new`, and not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn alox_vm_free(vm: *mut AloxVm) {
+    catch_panic((), || {
+        if !vm.is_null() {
+            drop(Box::from_raw(vm));
+        }
+    })
+}
+
+/// Runs the script, printing to stdout. A script can only run once
+///
+/// # Safety
+/// `vm` must be null or a live VM from `alox_vm_new`
+#[no_mangle]
+pub unsafe extern "C" fn alox_run(vm: *mut AloxVm) -> AloxStatus {
+    catch_panic(AloxStatus::Panicked, || {
+        let handle = match vm.as_mut() {
+            Some(handle) => handle,
+            None => return AloxStatus::InvalidArgument,
+        };
+        let result = match &mut handle.vm {
+            Some(vm) => vm.run(),
+            None => return AloxStatus::CompileError,
+        };
+        match result {
+            Ok(()) => AloxStatus::Ok,
+            Err(err) => handle.fail(AloxStatus::RuntimeError, err.to_string()),
+        }
+    })
+}
+
+/// Message of the last failure, or null if nothing failed. The string belongs to the VM and
+/// stays valid until its next failing call
+///
+/// # Safety
+/// `vm` must be null or a live VM from `alox_vm_new`
+#[no_mangle]
+pub unsafe extern "C" fn alox_last_error(vm: *const AloxVm) -> *const c_char {
+    catch_panic(ptr::null(), || {
+        match vm.as_ref().and_then(|handle| handle.error.as_ref()) {
+            Some(error) => error.as_ptr(),
+            None => ptr::null(),
+        }
+    })
+}
+
+/// The VM and a global's name, or the status to fail with
+///
+/// # Safety
+/// `vm` must be null or a live VM, `name` null or a NUL terminated string
+unsafe fn global_args<'a>(
+    vm: *mut AloxVm,
+    name: *const c_char,
+) -> Result<(&'a mut AloxVm, &'a str), AloxStatus> {
+    let handle = vm.as_mut().ok_or(AloxStatus::InvalidArgument)?;
+    let name = str_arg(name).ok_or(AloxStatus::InvalidArgument)?;
+    if handle.vm.is_none() {
+        return Err(AloxStatus::CompileError);
+    }
+    Ok((handle, name))
+}
+
+/// Reads a number, or an integer, global into `out`
+///
+/// # Safety
+/// `vm` must be a live VM, `name` a NUL terminated string and `out` writable
+#[no_mangle]
+pub unsafe extern "C" fn alox_get_global_number(
+    vm: *mut AloxVm,
+    name: *const c_char,
+    out: *mut f64,
+) -> AloxStatus {
+    catch_panic(AloxStatus::Panicked, || {
+        let (handle, name) = match global_args(vm, name) {
+            Ok(args) => args,
+            Err(status) => return status,
+        };
+        let value = handle.vm.as_mut().and_then(|vm| vm.get_global(name));
+        match value.as_ref().map(Value::as_number) {
+            Some(Some(number)) if !out.is_null() => {
+                *out = number;
+                AloxStatus::Ok
+            }
+            Some(Some(_)) => AloxStatus::InvalidArgument,
+            Some(None) => AloxStatus::TypeMismatch,
+            None => AloxStatus::NotFound,
+        }
+    })
+}
+
+/// Reads a boolean global into `out`
+///
+/// # Safety
+/// `vm` must be a live VM, `name` a NUL terminated string and `out` writable
+#[no_mangle]
+pub unsafe extern "C" fn alox_get_global_bool(
+    vm: *mut AloxVm,
+    name: *const c_char,
+    out: *mut bool,
+) -> AloxStatus {
+    catch_panic(AloxStatus::Panicked, || {
+        let (handle, name) = match global_args(vm, name) {
+            Ok(args) => args,
+            Err(status) => return status,
+        };
+        let value = handle.vm.as_mut().and_then(|vm| vm.get_global(name));
+        match value.as_ref().map(Value::as_bool) {
+            Some(Some(b)) if !out.is_null() => {
+                *out = b;
+                AloxStatus::Ok
+            }
+            Some(Some(_)) => AloxStatus::InvalidArgument,
+            Some(None) => AloxStatus::TypeMismatch,
+            None => AloxStatus::NotFound,
+        }
+    })
+}
+
+/// Copies a string global into `out`, a new string to be released with `alox_string_free`
+///
+/// # Safety
+/// `vm` must be a live VM, `name` a NUL terminated string and `out` writable
+#[no_mangle]
+pub unsafe extern "C" fn alox_get_global_string(
+    vm: *mut AloxVm,
+    name: *const c_char,
+    out: *mut *mut c_char,
+) -> AloxStatus {
+    catch_panic(AloxStatus::Panicked, || {
+        let (handle, name) = match global_args(vm, name) {
+            Ok(args) => args,
+            Err(status) => return status,
+        };
+        let vm = handle.vm.as_mut().expect("checked by global_args");
+        let value = match vm.get_global(name) {
+            Some(value) => value,
+            None => return AloxStatus::NotFound,
+        };
+        let string = match vm.from_value::<String>(&value) {
+            Ok(string) => string,
+            Err(err) => return handle.fail(AloxStatus::TypeMismatch, err.to_string()),
+        };
+        if out.is_null() {
+            return AloxStatus::InvalidArgument;
+        }
+        *out = CString::new(string.replace('\0', " "))
+            .expect("NULs replaced")
+            .into_raw();
+        AloxStatus::Ok
+    })
+}
+
+/// Releases a string returned by `alox_get_global_string`, doing nothing if it's null
+///
+/// # Safety
+/// `string` must be null or come from `alox_get_global_string`, and not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn alox_string_free(string: *mut c_char) {
+    catch_panic((), || {
+        if !string.is_null() {
+            drop(CString::from_raw(string));
+        }
+    })
+}
+
+/// Defines the global `name` as a number, typically before `alox_run`
+///
+/// # Safety
+/// `vm` must be a live VM and `name` a NUL terminated string
+#[no_mangle]
+pub unsafe extern "C" fn alox_set_global_number(
+    vm: *mut AloxVm,
+    name: *const c_char,
+    value: f64,
+) -> AloxStatus {
+    catch_panic(AloxStatus::Panicked, || {
+        set_global(vm, name, Value::Number(value))
+    })
+}
+
+/// Defines the global `name` as a boolean, typically before `alox_run`
+///
+/// # Safety
+/// `vm` must be a live VM and `name` a NUL terminated string
+#[no_mangle]
+pub unsafe extern "C" fn alox_set_global_bool(
+    vm: *mut AloxVm,
+    name: *const c_char,
+    value: bool,
+) -> AloxStatus {
+    catch_panic(AloxStatus::Panicked, || {
+        set_global(vm, name, Value::Bool(value))
+    })
+}
+
+/// Defines the global `name` as a string, typically before `alox_run`
+///
+/// # Safety
+/// `vm` must be a live VM, `name` and `value` NUL terminated strings
+#[no_mangle]
+pub unsafe extern "C" fn alox_set_global_string(
+    vm: *mut AloxVm,
+    name: *const c_char,
+    value: *const c_char,
+) -> AloxStatus {
+    catch_panic(AloxStatus::Panicked, || {
+        let string = match str_arg(value) {
+            Some(string) => string,
+            None => return AloxStatus::InvalidArgument,
+        };
+        let value = match vm.as_mut().and_then(|handle| handle.vm.as_mut()) {
+            Some(vm) => vm.to_value(string),
+            None => return AloxStatus::InvalidArgument,
+        };
+        set_global(vm, name, value)
+    })
+}
+
+/// Defines a global for the `alox_set_global_*` functions, reporting a full global table as
+/// a runtime error
+///
+/// # Safety
+/// `vm` must be null or a live VM, `name` null or a NUL terminated string
+unsafe fn set_global(vm: *mut AloxVm, name: *const c_char, value: Value) -> AloxStatus {
+    match global_args(vm, name) {
+        Ok((handle, name)) => {
+            let vm = handle.vm.as_mut().expect("checked by global_args");
+            match vm.set_global(name, value) {
+                Ok(()) => AloxStatus::Ok,
+                Err(err) => handle.fail(AloxStatus::RuntimeError, err.to_string()),
+            }
+        }
+        Err(status) => status,
+    }
+}
+
+/// Calls the global function `name` with `arg_count` numbers from `args`, storing the number
+/// it returns in `result`. The script has to have run first
+///
+/// # Safety
+/// `vm` must be a live VM, `name` a NUL terminated string, `args` readable for `arg_count`
+/// numbers, or null when there are none, and `result` writable
+#[no_mangle]
+pub unsafe extern "C" fn alox_call_number(
+    vm: *mut AloxVm,
+    name: *const c_char,
+    args: *const f64,
+    arg_count: usize,
+    result: *mut f64,
+) -> AloxStatus {
+    catch_panic(AloxStatus::Panicked, || {
+        let (handle, name) = match global_args(vm, name) {
+            Ok(args) => args,
+            Err(status) => return status,
+        };
+        if result.is_null() || (args.is_null() && arg_count > 0) {
+            return AloxStatus::InvalidArgument;
+        }
+        let args: Vec<Value> = match arg_count {
+            0 => Vec::new(),
+            _ => std::slice::from_raw_parts(args, arg_count)
+                .iter()
+                .map(|&arg| Value::Number(arg))
+                .collect(),
+        };
+        let vm = handle.vm.as_mut().expect("checked by global_args");
+        match vm.call_function(name, &args) {
+            Ok(value) => match value.as_number() {
+                Some(number) => {
+                    *result = number;
+                    AloxStatus::Ok
+                }
+                None => {
+                    let message = format!("Expected number but got {}.", value.type_name());
+                    handle.fail(AloxStatus::TypeMismatch, message)
+                }
+            },
+            Err(err) => handle.fail(AloxStatus::RuntimeError, err.to_string()),
+        }
+    })
+}
@@ -0,0 +1,92 @@
+use std::convert::TryFrom;
+
+use ahash::AHashMap;
+
+use crate::{object::AloxString, value::Value};
+
+/// Global variables, stored densely by a slot the compiler resolves from each name
+#[derive(Default)]
+pub struct Globals {
+    slots: AHashMap<u32, u16>,
+    names: Vec<AloxString>,
+    values: Vec<Option<Value>>,
+    consts: Vec<bool>,
+}
+
+impl Globals {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Slot of the global `name`, reserving a new one the first time the name is seen.
+    /// `None` once every 16-bit slot is taken
+    pub fn resolve(&mut self, name: AloxString) -> Option<u16> {
+        if let Some(&slot) = self.slots.get(&name.0) {
+            return Some(slot);
+        }
+        let slot = u16::try_from(self.names.len()).ok()?;
+        self.slots.insert(name.0, slot);
+        self.names.push(name);
+        self.values.push(None);
+        self.consts.push(false);
+        Some(slot)
+    }
+
+    /// Slot of the global `name`, `None` if it was never resolved
+    pub fn slot(&self, name: AloxString) -> Option<u16> {
+        self.slots.get(&name.0).copied()
+    }
+
+    /// Number of slots resolved so far, defined or not
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn name(&self, slot: u16) -> Option<AloxString> {
+        self.names.get(slot as usize).copied()
+    }
+
+    /// Value of the global in `slot`, `None` if it hasn't been defined yet
+    pub fn get(&self, slot: u16) -> Option<&Value> {
+        self.values.get(slot as usize)?.as_ref()
+    }
+
+    /// Undefines the global in `slot`, keeping the slot for its name
+    pub fn remove(&mut self, slot: u16) -> Option<Value> {
+        self.consts[slot as usize] = false;
+        self.values.get_mut(slot as usize)?.take()
+    }
+
+    pub fn is_const(&self, slot: u16) -> bool {
+        self.consts.get(slot as usize).copied().unwrap_or(false)
+    }
+
+    pub fn define(&mut self, slot: u16, value: Value, is_const: bool) {
+        let slot = slot as usize;
+        self.values[slot] = Some(value);
+        self.consts[slot] = is_const;
+    }
+
+    /// Overwrites a global that's already defined, returning false if it isn't
+    pub fn set(&mut self, slot: u16, value: Value) -> bool {
+        match self.values.get_mut(slot as usize) {
+            Some(Some(current)) => {
+                *current = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Every defined global with its name, in slot order
+    pub fn iter(&self) -> impl Iterator<Item = (AloxString, &Value)> {
+        self.names
+            .iter()
+            .zip(&self.values)
+            .filter_map(|(name, value)| Some((*name, value.as_ref()?)))
+    }
+}
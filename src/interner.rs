@@ -1,30 +1,173 @@
-use ahash::AHashMap;
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, BuildHasherDefault, Hasher},
+    sync::{Arc, Mutex},
+};
 
-use typed_arena::Arena;
+use ahash::{AHashMap, RandomState};
 
-pub struct Interner<'vm> {
-    map: AHashMap<&'vm str, u32>,
-    vec: Vec<&'vm str>,
-    arena: &'vm Arena<u8>,
+/// Owns every interned string, those it interned itself stored back to back in one buffer so
+/// an index stays valid as the buffer grows
+#[derive(Default)]
+pub struct Interner {
+    /// First string with each hash, strings sharing a hash are chained through `next`
+    buckets: HashMap<u64, u32, BuildHasherDefault<PrehashedHasher>>,
+    next: Vec<Option<u32>>,
+    hashes: Vec<u64>,
+    /// Where each string is kept, `None` for indices a shared table handed to other
+    /// interners that this one hasn't seen
+    spans: Vec<Option<Span>>,
+    text: String,
+    hasher: RandomState,
+    /// Where indices come from when strings are shared with other interners
+    shared: Option<SharedInterner>,
+    /// Number of strings of the shared table already copied in by `sync`
+    synced: usize,
 }
 
-impl<'vm> Interner<'vm> {
-    pub fn new(arena: &Arena<u8>) -> Interner {
-        Interner {
-            map: AHashMap::new(),
-            vec: Vec::new(),
-            arena,
+enum Span {
+    /// Start and end in `text`
+    Local(usize, usize),
+    /// A string of the shared table, referred to rather than copied
+    Shared(Arc<str>),
+}
+
+/// Passes through hashes the interner already computed, so bucket lookups don't hash twice
+#[derive(Default)]
+struct PrehashedHasher(u64);
+
+impl Hasher for PrehashedHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, _: &[u8]) {
+        unreachable!("interner buckets are keyed by precomputed hashes")
+    }
+
+    fn write_u64(&mut self, hash: u64) {
+        self.0 = hash;
+    }
+}
+
+/// A table of strings numbered once for every `Interner` created from it, so a chunk
+/// compiled with one interner runs in any VM given another, and VMs running many scripts
+/// don't intern the same strings again. Cloning shares the table, across threads too
+#[derive(Clone, Default)]
+pub struct SharedInterner(Arc<SharedTable>);
+
+#[derive(Default)]
+struct SharedTable {
+    /// Every interner of the table hashes with it, so hashes are computed once
+    hasher: RandomState,
+    strings: Mutex<SharedStrings>,
+}
+
+#[derive(Default)]
+struct SharedStrings {
+    indices: AHashMap<Arc<str>, u32>,
+    /// Strings with their hash, never removed or replaced
+    strings: Vec<(Arc<str>, u64)>,
+}
+
+impl SharedInterner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of strings interned so far
+    pub fn len(&self) -> usize {
+        self.lock().strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn intern(&self, name: &str, hash: u64) -> (u32, Arc<str>) {
+        let mut shared = self.lock();
+        if let Some(&idx) = shared.indices.get(name) {
+            return (idx, shared.strings[idx as usize].0.clone());
         }
+        let idx = shared.strings.len() as u32;
+        let name: Arc<str> = name.into();
+        shared.indices.insert(name.clone(), idx);
+        shared.strings.push((name.clone(), hash));
+        (idx, name)
+    }
+
+    /// String `idx` of the table, read without copying it into an interner
+    fn get(&self, idx: u32) -> Option<&str> {
+        let string: *const str = &*self.lock().strings.get(idx as usize)?.0;
+        // SAFETY: the table keeps every string it was given until it is dropped, and `self`
+        // keeps the table alive. Only the `Arc`s move as the vector grows, not the strings
+        Some(unsafe { &*string })
+    }
+
+    fn hash(&self, idx: u32) -> Option<u64> {
+        self.lock().strings.get(idx as usize).map(|&(_, hash)| hash)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, SharedStrings> {
+        // the table is consistent after every statement, so a panicking holder can't break it
+        self.0
+            .strings
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An interner numbering its strings through `shared`, starting with every string the
+    /// table already holds. Lookups of strings seen here stay local, the table is locked to
+    /// intern a string this interner hasn't seen or look up one it hasn't synced
+    pub fn with_shared(shared: SharedInterner) -> Self {
+        let mut interner = Self {
+            hasher: shared.0.hasher.clone(),
+            shared: Some(shared),
+            ..Self::default()
+        };
+        interner.sync();
+        interner
+    }
+
+    /// Takes in strings other interners added to the shared table since the last sync, so
+    /// `exists` and `intern` find them without locking. Does nothing when unshared
+    pub fn sync(&mut self) {
+        let shared = match &self.shared {
+            Some(shared) => shared.clone(),
+            None => return,
+        };
+        let shared = shared.lock();
+        for (idx, (name, hash)) in shared.strings.iter().enumerate().skip(self.synced) {
+            if !self.contains(idx as u32) {
+                self.insert(idx as u32, Span::Shared(name.clone()), *hash);
+            }
+        }
+        self.synced = shared.strings.len();
     }
 
     pub fn intern(&mut self, name: &str) -> u32 {
-        if let Some(&idx) = self.map.get(name) {
+        let hash = self.hash_str(name);
+        if let Some(idx) = self.find(name, hash) {
             return idx;
         }
-        let idx = self.vec.len() as u32;
-        let name = self.arena.alloc_str(name);
-        self.map.insert(name, idx);
-        self.vec.push(name);
+        let (idx, span) = match &self.shared {
+            Some(shared) => {
+                let (idx, name) = shared.intern(name, hash);
+                (idx, Span::Shared(name))
+            }
+            None => {
+                let start = self.text.len();
+                self.text.push_str(name);
+                (self.spans.len() as u32, Span::Local(start, self.text.len()))
+            }
+        };
+        self.insert(idx, span, hash);
 
         debug_assert!(self.lookup(idx) == name);
         debug_assert!(self.intern(name) == idx);
@@ -33,14 +176,69 @@ impl<'vm> Interner<'vm> {
     }
 
     pub fn exists(&self, string: &str) -> bool {
-        self.map.contains_key(string)
+        self.find(string, self.hash_str(string)).is_some()
     }
 
     pub fn get_existing(&self, name: &str) -> u32 {
-        *self.map.get(name).expect("Interned string does not exist!")
+        self.find(name, self.hash_str(name))
+            .expect("Interned string does not exist!")
+    }
+
+    /// The string numbered `idx`, read from the shared table if this interner hasn't seen
+    /// it. Empty for an index no interner handed out
+    pub fn lookup(&self, idx: u32) -> &str {
+        match self.spans.get(idx as usize) {
+            Some(Some(Span::Local(start, end))) => &self.text[*start..*end],
+            Some(Some(Span::Shared(name))) => name,
+            _ => self
+                .shared
+                .as_ref()
+                .and_then(|shared| shared.get(idx))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Hash of an interned string, computed once when it was interned
+    pub fn hash(&self, idx: u32) -> u64 {
+        match self.hashes.get(idx as usize) {
+            Some(&hash) if self.contains(idx) => hash,
+            _ => self
+                .shared
+                .as_ref()
+                .and_then(|shared| shared.hash(idx))
+                .unwrap_or_else(|| self.hash_str("")),
+        }
+    }
+
+    fn contains(&self, idx: u32) -> bool {
+        matches!(self.spans.get(idx as usize), Some(Some(_)))
+    }
+
+    fn insert(&mut self, idx: u32, span: Span, hash: u64) {
+        let slot = idx as usize;
+        // a shared table may hand out indices past ones this interner hasn't seen
+        if slot >= self.spans.len() {
+            self.spans.resize_with(slot + 1, || None);
+            self.next.resize(slot + 1, None);
+            self.hashes.resize(slot + 1, 0);
+        }
+        self.next[slot] = self.buckets.insert(hash, idx);
+        self.hashes[slot] = hash;
+        self.spans[slot] = Some(span);
+    }
+
+    fn find(&self, name: &str, hash: u64) -> Option<u32> {
+        let mut candidate = self.buckets.get(&hash).copied();
+        while let Some(idx) = candidate {
+            if self.lookup(idx) == name {
+                return Some(idx);
+            }
+            candidate = self.next[idx as usize];
+        }
+        None
     }
 
-    pub fn lookup(&self, idx: u32) -> &'vm str {
-        self.vec[idx as usize]
+    fn hash_str(&self, string: &str) -> u64 {
+        self.hasher.hash_one(string)
     }
 }
@@ -1,38 +1,164 @@
+use std::{
+    fmt::Display,
+    io::{self, Write},
+    path::Path,
+};
+
 use chunk::Chunk;
+use globals::Globals;
 use interner::Interner;
-use parser::Parser;
+use parser::{CompileDiagnostic, Parser};
 use scanner::Scanner;
-use typed_arena::Arena;
-use vm::Vm;
+use vm::{InterpreterError, Vm};
 
+pub mod assembler;
+pub mod builder;
 pub mod chunk;
 pub mod compiler;
+pub mod convert;
+pub mod decode;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod globals;
 pub mod interner;
+pub mod natives;
 pub mod object;
 pub mod opcodes;
 pub mod parser;
+pub mod peephole;
+#[cfg(all(feature = "repl", not(target_arch = "wasm32")))]
 pub mod repl;
 pub mod scanner;
+#[cfg(feature = "serde")]
+pub mod serde_value;
+pub mod serialize;
+#[cfg(feature = "vm-stats")]
+pub mod stats;
 pub mod token;
 pub mod value;
+pub mod verify;
 pub mod vm;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
-pub fn run_script(source: &str) {
-    let arena = Arena::new();
-    let mut interner = Interner::new(&arena);
-    let mut chunk = Chunk::init();
+/// Exit codes of the reference Lox implementations, from BSD's sysexits.h
+pub const EXIT_DATA_ERROR: i32 = 65;
+pub const EXIT_SOFTWARE: i32 = 70;
+pub const EXIT_IO_ERROR: i32 = 74;
 
-    let comp_result = {
-        let scanner = Scanner::new(source);
-        let mut parser = Parser::new(scanner, &mut chunk, &mut interner);
-        parser.compile()
-    };
+/// Why a script failed, either before it ran or while running
+#[derive(Debug)]
+pub enum AloxError {
+    Compile(Vec<CompileDiagnostic>),
+    Runtime {
+        error: InterpreterError,
+        /// Line of the instruction that failed, if the VM got as far as running one
+        line: Option<usize>,
+    },
+}
 
-    if comp_result.is_ok() {
-        let mut vm = Vm::new(chunk, interner);
+impl Display for AloxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AloxError::Compile(diagnostics) => {
+                let lines: Vec<String> = diagnostics.iter().map(ToString::to_string).collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            AloxError::Runtime { error, .. } => write!(f, "{}", error),
+        }
+    }
+}
 
-        if let Err(err) = vm.run() {
-            eprintln!("{}", err)
-        };
+impl AloxError {
+    /// Status a command line runner exits with: `EXIT_DATA_ERROR` for compile errors, the
+    /// code a script passed to `exit`, or else `EXIT_SOFTWARE`
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            AloxError::Compile(_) => EXIT_DATA_ERROR,
+            AloxError::Runtime {
+                error: InterpreterError::Exit(code),
+                ..
+            } => *code,
+            AloxError::Runtime { .. } => EXIT_SOFTWARE,
+        }
     }
 }
+
+/// Compiles `source` without running it. Global variables are resolved to slots in
+/// `globals`, which a `Vm` running the chunk must be given
+pub fn compile(
+    source: &str,
+    interner: &mut Interner,
+    globals: &mut Globals,
+) -> Result<Chunk, Vec<CompileDiagnostic>> {
+    let mut chunk = Chunk::init();
+    let scanner = Scanner::new(source);
+    Parser::new(scanner, &mut chunk, interner, globals).compile()?;
+    Ok(chunk)
+}
+
+/// Compiles a line typed at the prompt, printing the value of a trailing bare expression
+pub fn compile_repl_line(
+    source: &str,
+    interner: &mut Interner,
+    globals: &mut Globals,
+) -> Result<Chunk, Vec<CompileDiagnostic>> {
+    let mut chunk = Chunk::init();
+    let scanner = Scanner::new(source);
+    Parser::new(scanner, &mut chunk, interner, globals)
+        .repl_line()
+        .compile()?;
+    Ok(chunk)
+}
+
+/// Compiles `source` into a VM ready to run it
+pub fn load_script<'vm>(source: &str) -> Result<Vm<'vm>, Vec<CompileDiagnostic>> {
+    let mut interner = Interner::new();
+    let mut globals = Globals::new();
+    let chunk = compile(source, &mut interner, &mut globals)?;
+    Ok(Vm::new(chunk, interner, globals))
+}
+
+/// Compiles and runs `source`
+pub fn run_script(source: &str) -> Result<(), AloxError> {
+    run_script_with_output(source, io::stdout())
+}
+
+/// Compiles and runs `source` with the global `args` set to a list of `args`, as passed on
+/// the command line after the script
+pub fn run_script_with_args(source: &str, args: &[String]) -> Result<(), AloxError> {
+    let mut vm = load_script(source).map_err(AloxError::Compile)?;
+    vm.set_args(args)
+        .map_err(|error| AloxError::Runtime { error, line: None })?;
+    run_loaded_script(vm)
+}
+
+/// Compiles and runs `source`, the contents of the file at `path`, with the global `args` set
+/// as for `run_script_with_args`. Modules it imports are found relative to the file
+pub fn run_script_at(
+    path: impl AsRef<Path>,
+    source: &str,
+    args: &[String],
+) -> Result<(), AloxError> {
+    let mut vm = load_script(source)
+        .map_err(AloxError::Compile)?
+        .with_script_path(path);
+    vm.set_args(args)
+        .map_err(|error| AloxError::Runtime { error, line: None })?;
+    run_loaded_script(vm)
+}
+
+/// Compiles and runs `source`, sending what it prints to `output`
+pub fn run_script_with_output(source: &str, output: impl Write + Send) -> Result<(), AloxError> {
+    let vm = load_script(source)
+        .map_err(AloxError::Compile)?
+        .with_output(output);
+    run_loaded_script(vm)
+}
+
+fn run_loaded_script(mut vm: Vm) -> Result<(), AloxError> {
+    vm.run().map_err(|error| AloxError::Runtime {
+        error,
+        line: vm.line(),
+    })
+}
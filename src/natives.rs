@@ -0,0 +1,775 @@
+//! Native functions of the prelude every VM defines, see `Vm::without_prelude`
+
+use std::{
+    convert::TryFrom,
+    env,
+    f64::consts::{E, PI},
+    fs,
+    io::{self, BufRead},
+    sync::{Arc, OnceLock},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    convert::{FromAloxValue, IntoAloxValue},
+    interner::Interner,
+    object::{NativeError, NativeFn, ObjCell, Object},
+    value::Value,
+    vm::{Capabilities, InterpreterError, Vm},
+};
+
+/// What a native can reach of the VM calling it
+pub struct NativeContext<'a, 'vm> {
+    vm: &'a mut Vm<'vm>,
+    /// Why the last `call` failed, reported as it was if the native gives up with an error
+    failure: Option<InterpreterError>,
+}
+
+impl<'a, 'vm> NativeContext<'a, 'vm> {
+    pub(crate) fn new(vm: &'a mut Vm<'vm>) -> Self {
+        Self { vm, failure: None }
+    }
+
+    pub(crate) fn into_failure(self) -> Option<InterpreterError> {
+        self.failure
+    }
+
+    /// The VM's random number generator
+    pub fn rng(&mut self) -> &mut Rng {
+        self.vm.rng()
+    }
+
+    /// Strings of the calling VM, to look up string arguments
+    pub fn interner(&self) -> &Interner {
+        self.vm.interner()
+    }
+
+    /// Strings of the calling VM, to intern strings a native returns
+    pub fn interner_mut(&mut self) -> &mut Interner {
+        self.vm.interner_mut()
+    }
+
+    /// Calls a function or class the script passed in. When it fails, returning the error
+    /// from the native reports the failure as the call left it
+    pub fn call(&mut self, callee: &Value, args: &[Value]) -> Result<Value, NativeError> {
+        self.vm.apply(callee.clone(), args).map_err(|err| {
+            let message = err.to_string();
+            self.failure = Some(err);
+            NativeError::Message(message)
+        })
+    }
+
+    /// Argument `index` converted to `T`, or an error naming the type it had
+    pub fn arg<T: FromAloxValue>(&self, args: &[Value], index: usize) -> Result<T, NativeError> {
+        T::from_value(&args[index], self.interner()).map_err(|err| err.to_string().into())
+    }
+
+    /// `value` rendered the way `print` shows it
+    pub fn display(&self, value: &Value) -> String {
+        value.display(self.interner())
+    }
+
+    /// Converts a native's result, interning any strings
+    pub fn value(&mut self, value: impl IntoAloxValue) -> Value {
+        value.into_value(self.interner_mut())
+    }
+}
+
+/// A prelude native, defined only while the VM's capabilities allow it
+pub(crate) struct PreludeNative {
+    pub name: &'static str,
+    pub arity: usize,
+    /// Whether `arity` is only the least number of arguments
+    pub variadic: bool,
+    pub function: NativeFn,
+    pub allowed: fn(&Capabilities) -> bool,
+}
+
+pub(crate) const PRELUDE: &[PreludeNative] = &[
+    PreludeNative {
+        name: "exit",
+        arity: 1,
+        variadic: false,
+        function: exit,
+        allowed: |_| true,
+    },
+    PreludeNative {
+        name: "clock",
+        arity: 0,
+        variadic: false,
+        function: clock,
+        allowed: |capabilities| capabilities.clock && HAS_CLOCK,
+    },
+    PreludeNative {
+        name: "now_millis",
+        arity: 0,
+        variadic: false,
+        function: now_millis,
+        allowed: |capabilities| capabilities.clock && HAS_CLOCK,
+    },
+    pure("sqrt", 1, sqrt),
+    pure("abs", 1, abs),
+    pure("floor", 1, floor),
+    pure("ceil", 1, ceil),
+    pure("min", 2, min),
+    pure("max", 2, max),
+    pure("pow", 2, pow),
+    pure("sin", 1, sin),
+    pure("cos", 1, cos),
+    pure("len", 1, len),
+    pure("push", 2, push),
+    pure("pop", 1, pop),
+    pure("insert", 3, insert),
+    pure("remove", 2, remove),
+    pure("sort", 1, sort),
+    pure("map", 2, map),
+    pure("filter", 2, filter),
+    pure("upper", 1, upper),
+    pure("lower", 1, lower),
+    pure("substr", 3, substr),
+    pure("index_of", 2, index_of),
+    pure("split", 2, split),
+    pure("type", 1, type_of),
+    pure("disassemble", 1, disassemble),
+    pure("num", 1, num),
+    pure("str", 1, str),
+    PreludeNative {
+        name: "format",
+        arity: 1,
+        variadic: true,
+        function: format,
+        allowed: |_| true,
+    },
+    pure("random", 0, random),
+    pure("random_range", 2, random_range),
+    pure("seed", 1, seed),
+    io("read_line", 0, read_line),
+    io("read_file", 1, read_file),
+    io("write_file", 2, write_file),
+    #[cfg(feature = "json")]
+    pure("json_parse", 1, json_parse),
+    #[cfg(feature = "json")]
+    pure("json_stringify", 1, json_stringify),
+    PreludeNative {
+        name: "env",
+        arity: 1,
+        variadic: false,
+        function: env,
+        allowed: |capabilities| capabilities.environment,
+    },
+];
+
+/// Numeric globals of the prelude, which scripts can redefine like the natives
+pub(crate) const CONSTANTS: &[(&str, f64)] = &[("PI", PI), ("E", E)];
+
+/// A native reaching the console or files, needing the io capability
+const fn io(name: &'static str, arity: usize, function: NativeFn) -> PreludeNative {
+    PreludeNative {
+        name,
+        arity,
+        variadic: false,
+        function,
+        allowed: |capabilities| capabilities.io,
+    }
+}
+
+/// A native needing no capability, for computing alone
+const fn pure(name: &'static str, arity: usize, function: NativeFn) -> PreludeNative {
+    PreludeNative {
+        name,
+        arity,
+        variadic: false,
+        function,
+        allowed: |_| true,
+    }
+}
+
+/// A small generator, splitmix64, giving the same numbers for the same seed on every
+/// platform. Not suitable for cryptography
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// Seeded from the current time, different on every run where the platform has a clock
+    pub fn from_time() -> Self {
+        if !HAS_CLOCK {
+            return Self(0);
+        }
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+        Self(nanos)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0.0..1.0`
+    pub fn next_f64(&mut self) -> f64 {
+        // the top 53 bits fill a float's mantissa exactly
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform in `0..bound`, `bound` must not be 0
+    pub fn below(&mut self, bound: u64) -> u64 {
+        ((self.next_u64() as u128 * bound as u128) >> 64) as u64
+    }
+}
+
+/// Whether the platform can tell the time. Browsers can't without JavaScript's help, asking
+/// there panics
+const HAS_CLOCK: bool = !cfg!(all(target_arch = "wasm32", target_os = "unknown"));
+
+/// When the first VM was created, what `clock` counts from
+static START: OnceLock<Instant> = OnceLock::new();
+
+pub(crate) fn start_clock() {
+    if HAS_CLOCK {
+        START.get_or_init(Instant::now);
+    }
+}
+
+/// `exit(code)` stops the script, handing `code` to the host as `InterpreterError::Exit`
+pub fn exit(context: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    let code = args[0]
+        .as_integer()
+        .and_then(|code| i32::try_from(code).ok())
+        .ok_or_else(|| {
+            let found = match &args[0] {
+                Value::Number(_) | Value::Int(_) => context.display(&args[0]),
+                other => other.type_name().to_string(),
+            };
+            format!("Exit code must be a whole number but got {}.", found)
+        })?;
+    Err(NativeError::Exit(code))
+}
+
+/// `clock()` is the seconds since the program started, for timing scripts
+pub fn clock(_: &mut NativeContext, _: &[Value]) -> Result<Value, NativeError> {
+    let start = START.get_or_init(Instant::now);
+    Ok(Value::Number(start.elapsed().as_secs_f64()))
+}
+
+/// `now_millis()` is the milliseconds since the Unix epoch, as an integer
+pub fn now_millis(_: &mut NativeContext, _: &[Value]) -> Result<Value, NativeError> {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| "The system clock is set before 1970.")?
+        .as_millis();
+    Ok(Value::Int(i64::try_from(millis).unwrap_or(i64::MAX)))
+}
+
+/// Argument `index` as a float, integers are converted
+fn number(args: &[Value], index: usize) -> Result<f64, NativeError> {
+    args[index].as_number().ok_or_else(|| {
+        let message = format!("Expected number but got {}.", args[index].type_name());
+        NativeError::Message(message)
+    })
+}
+
+/// Rounds a float with `round`, integers are already whole
+fn round(args: &[Value], round: fn(f64) -> f64) -> Result<Value, NativeError> {
+    match args[0] {
+        Value::Int(int) => Ok(Value::Int(int)),
+        _ => Ok(Value::Number(round(number(args, 0)?))),
+    }
+}
+
+/// Whichever argument `pick_first` prefers, keeping it an integer if it was one
+fn pick(args: &[Value], pick_first: fn(f64, f64) -> bool) -> Result<Value, NativeError> {
+    let (a, b) = (number(args, 0)?, number(args, 1)?);
+    Ok(args[if pick_first(a, b) { 0 } else { 1 }].clone())
+}
+
+pub fn sqrt(_: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    Ok(Value::Number(number(args, 0)?.sqrt()))
+}
+
+pub fn abs(_: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    match args[0] {
+        // the smallest integer has no positive counterpart, so becomes a float
+        Value::Int(int) => Ok(int
+            .checked_abs()
+            .map_or(Value::Number((int as f64).abs()), Value::Int)),
+        _ => Ok(Value::Number(number(args, 0)?.abs())),
+    }
+}
+
+pub fn floor(_: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    round(args, f64::floor)
+}
+
+pub fn ceil(_: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    round(args, f64::ceil)
+}
+
+pub fn min(_: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    pick(args, |a, b| a <= b)
+}
+
+pub fn max(_: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    pick(args, |a, b| a >= b)
+}
+
+/// Integers raised to a small non-negative integer power stay integers unless they overflow
+pub fn pow(_: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    if let (Value::Int(base), Value::Int(exponent)) = (&args[0], &args[1]) {
+        let power = u32::try_from(*exponent)
+            .ok()
+            .and_then(|exponent| base.checked_pow(exponent));
+        if let Some(power) = power {
+            return Ok(Value::Int(power));
+        }
+    }
+    Ok(Value::Number(number(args, 0)?.powf(number(args, 1)?)))
+}
+
+pub fn sin(_: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    Ok(Value::Number(number(args, 0)?.sin()))
+}
+
+pub fn cos(_: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    Ok(Value::Number(number(args, 0)?.cos()))
+}
+
+/// `len(value)` counts the characters of a string or the items of a list, tuple or map
+pub fn len(context: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    let len = match &args[0] {
+        Value::Obj(Object::List(list)) => list.borrow().len(),
+        Value::Obj(Object::Tuple(items)) => items.len(),
+        Value::Obj(Object::Map(map)) => map.borrow().len(),
+        _ => match context.arg::<String>(args, 0) {
+            Ok(string) => string.chars().count(),
+            Err(_) => {
+                let found = args[0].type_name();
+                return Err(format!("Expected string or collection but got {}.", found).into());
+            }
+        },
+    };
+    Ok(Value::Int(len as i64))
+}
+
+/// Argument `index` as a list, to change in place
+fn list(args: &[Value], index: usize) -> Result<&Arc<ObjCell<Vec<Value>>>, NativeError> {
+    match &args[index] {
+        Value::Obj(Object::List(list)) => Ok(list),
+        value => Err(format!("Expected list but got {}.", value.type_name()).into()),
+    }
+}
+
+/// Argument `index` as a position in a list of `len` items, the end included when `end`
+fn position(args: &[Value], index: usize, len: usize, end: bool) -> Result<usize, NativeError> {
+    let position = args[index]
+        .as_integer()
+        .ok_or("Index must be an integer.")?;
+    let last = if end { len as i64 } else { len as i64 - 1 };
+    if position < 0 || position > last {
+        let message = format!("Index {} out of bounds for length {}.", position, len);
+        return Err(message.into());
+    }
+    Ok(position as usize)
+}
+
+/// `push(list, value)` adds `value` to the end of `list`
+pub fn push(_: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    list(args, 0)?.borrow_mut().push(args[1].clone());
+    Ok(Value::Nil)
+}
+
+/// `pop(list)` takes the last item off `list`
+pub fn pop(_: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    let popped = list(args, 0)?.borrow_mut().pop();
+    popped.ok_or_else(|| "Can't pop from an empty list.".into())
+}
+
+/// `insert(list, index, value)` puts `value` at `index`, moving the items after it along
+pub fn insert(_: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    let mut items = list(args, 0)?.borrow_mut();
+    let index = position(args, 1, items.len(), true)?;
+    items.insert(index, args[2].clone());
+    Ok(Value::Nil)
+}
+
+/// `remove(list, index)` takes out the item at `index` and returns it
+pub fn remove(_: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    let mut items = list(args, 0)?.borrow_mut();
+    let index = position(args, 1, items.len(), false)?;
+    Ok(items.remove(index))
+}
+
+/// `sort(list)` sorts a list of numbers or of strings in place, in ascending order
+pub fn sort(context: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    let mut items = list(args, 0)?.borrow_mut();
+    if items.iter().all(|item| item.as_number().is_some()) {
+        items.sort_by(|a, b| match (a, b) {
+            (Value::Int(a), Value::Int(b)) => a.cmp(b),
+            _ => a
+                .as_number()
+                .unwrap_or_default()
+                .total_cmp(&b.as_number().unwrap_or_default()),
+        });
+        return Ok(Value::Nil);
+    }
+    let keys = items
+        .iter()
+        .map(|item| String::from_value(item, context.interner()).ok())
+        .collect::<Option<Vec<_>>>()
+        .ok_or("Can only sort a list of numbers or a list of strings.")?;
+    let mut keyed: Vec<_> = keys.into_iter().zip(items.drain(..)).collect();
+    keyed.sort_by(|(a, _), (b, _)| a.cmp(b));
+    items.extend(keyed.into_iter().map(|(_, item)| item));
+    Ok(Value::Nil)
+}
+
+/// `map(list, function)` is a new list of what `function` returns for each item
+pub fn map(context: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    // copied, so the function can change the list without holding up the loop
+    let items = list(args, 0)?.borrow().clone();
+    let mapped = items
+        .iter()
+        .map(|item| context.call(&args[1], std::slice::from_ref(item)))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Value::from_list(mapped))
+}
+
+/// `filter(list, function)` is a new list of the items `function` returns a truthy value for
+pub fn filter(context: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    let items = list(args, 0)?.borrow().clone();
+    let mut kept = Vec::new();
+    for item in items {
+        let keep = context.call(&args[1], std::slice::from_ref(&item))?;
+        if !matches!(keep, Value::Nil | Value::Bool(false)) {
+            kept.push(item);
+        }
+    }
+    Ok(Value::from_list(kept))
+}
+
+/// `disassemble(function)` is the bytecode of a script function, listed as the compiler
+/// prints it
+pub fn disassemble(context: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    let function = match &args[0] {
+        Value::Obj(Object::Closure(closure)) => &closure.function,
+        Value::Obj(Object::BoundMethod(bound)) => &bound.method.function,
+        _ => return Err("Can only disassemble functions written in Lox.".into()),
+    };
+    let name = match function.name {
+        Some(name) => context.interner().lookup(name.0),
+        None => "<script>",
+    };
+    let listing = function.chunk.disassemble(name, context.interner());
+    Ok(context.value(listing.trim_end()))
+}
+
+pub fn upper(context: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    let string: String = context.arg(args, 0)?;
+    Ok(context.value(string.to_uppercase()))
+}
+
+pub fn lower(context: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    let string: String = context.arg(args, 0)?;
+    Ok(context.value(string.to_lowercase()))
+}
+
+/// `substr(s, start, len)` takes up to `len` characters from `start`, both counted in
+/// characters. Running past the end stops at the end
+pub fn substr(context: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    let string: String = context.arg(args, 0)?;
+    let (start, len): (i64, i64) = (context.arg(args, 1)?, context.arg(args, 2)?);
+    if start < 0 || len < 0 {
+        return Err("Substring start and length can't be negative.".into());
+    }
+    let substring: String = string
+        .chars()
+        .skip(start as usize)
+        .take(len as usize)
+        .collect();
+    Ok(context.value(substring))
+}
+
+/// `index_of(s, needle)` is the character position of the first `needle` in `s`, or -1
+pub fn index_of(context: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    let string: String = context.arg(args, 0)?;
+    let needle: String = context.arg(args, 1)?;
+    let index = match string.find(&needle) {
+        Some(byte) => string[..byte].chars().count() as i64,
+        None => -1,
+    };
+    Ok(Value::Int(index))
+}
+
+/// `split(s, sep)` is a list of the parts of `s` between each `sep`, or of its characters
+/// when `sep` is empty
+pub fn split(context: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    let string: String = context.arg(args, 0)?;
+    let separator: String = context.arg(args, 1)?;
+    let parts: Vec<String> = if separator.is_empty() {
+        string.chars().map(String::from).collect()
+    } else {
+        string.split(separator.as_str()).map(String::from).collect()
+    };
+    Ok(context.value(parts))
+}
+
+/// `type(value)` names the type of `value`, as `"number"` or `"string"`, the names runtime
+/// errors use
+pub fn type_of(context: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    Ok(context.value(args[0].type_name()))
+}
+
+/// `num(s)` parses a string as an integer or a number, `nil` if it isn't one. Numbers are
+/// returned as they are
+pub fn num(context: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    if args[0].as_number().is_some() {
+        return Ok(args[0].clone());
+    }
+    let string: String = context.arg(args, 0)?;
+    let string = string.trim();
+    if let Ok(int) = string.parse::<i64>() {
+        return Ok(Value::Int(int));
+    }
+    // Rust also reads "inf" and "NaN", which Lox can't write
+    match string.parse::<f64>() {
+        Ok(number) if number.is_finite() => Ok(Value::Number(number)),
+        _ => Ok(Value::Nil),
+    }
+}
+
+/// `str(value)` is the text `print` shows for `value`
+pub fn str(context: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    let string = context.display(&args[0]);
+    Ok(context.value(string))
+}
+
+/// `random()` is a number from 0 up to but not including 1
+pub fn random(context: &mut NativeContext, _: &[Value]) -> Result<Value, NativeError> {
+    Ok(Value::Number(context.rng().next_f64()))
+}
+
+/// `random_range(lo, hi)` is from `lo` up to but not including `hi`, an integer when both
+/// bounds are
+pub fn random_range(context: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    if let (Value::Int(low), Value::Int(high)) = (&args[0], &args[1]) {
+        if low >= high {
+            return Err("Random range must not be empty.".into());
+        }
+        let span = high.wrapping_sub(*low) as u64;
+        let offset = context.rng().below(span);
+        return Ok(Value::Int(low.wrapping_add(offset as i64)));
+    }
+    let (low, high): (f64, f64) = (context.arg(args, 0)?, context.arg(args, 1)?);
+    if low >= high {
+        return Err("Random range must not be empty.".into());
+    }
+    let number = low + (high - low) * context.rng().next_f64();
+    // rounding can land on `high` itself
+    Ok(Value::Number(if number < high { number } else { low }))
+}
+
+/// `seed(n)` restarts the generator, so the numbers that follow repeat between runs
+pub fn seed(context: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    let seed: i64 = context.arg(args, 0)?;
+    *context.rng() = Rng::new(seed as u64);
+    Ok(Value::Nil)
+}
+
+/// `read_line()` is the next line of standard input without its line ending, `nil` once the
+/// input has ended
+pub fn read_line(context: &mut NativeContext, _: &[Value]) -> Result<Value, NativeError> {
+    let mut line = String::new();
+    let read = io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .map_err(|err| format!("Could not read a line: {}.", err))?;
+    if read == 0 {
+        return Ok(Value::Nil);
+    }
+    let trimmed = line.trim_end_matches(&['\n', '\r'][..]);
+    Ok(context.value(trimmed))
+}
+
+/// `read_file(path)` is the whole text of the file at `path`
+pub fn read_file(context: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    let path: String = context.arg(args, 0)?;
+    let contents = fs::read_to_string(&path)
+        .map_err(|err| format!("Could not read file '{}': {}.", path, err))?;
+    Ok(context.value(contents))
+}
+
+/// `write_file(path, contents)` replaces the file at `path` with the string `contents`,
+/// creating it if needed
+pub fn write_file(context: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    let path: String = context.arg(args, 0)?;
+    let contents: String = context.arg(args, 1)?;
+    fs::write(&path, contents)
+        .map_err(|err| format!("Could not write file '{}': {}.", path, err))?;
+    Ok(Value::Nil)
+}
+
+/// `env(name)` is the environment variable `name`, `nil` if it isn't set or isn't UTF-8
+pub fn env(context: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    let name: String = context.arg(args, 0)?;
+    match env::var(&name) {
+        Ok(value) => Ok(context.value(value)),
+        Err(_) => Ok(Value::Nil),
+    }
+}
+
+/// `json_parse(s)` converts JSON text to values: arrays become lists, objects maps and
+/// `null` nil
+#[cfg(feature = "json")]
+pub fn json_parse(context: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    use serde::de::DeserializeSeed;
+
+    let text: String = context.arg(args, 0)?;
+    let mut deserializer = serde_json::Deserializer::from_str(&text);
+    let seed = crate::serde_value::ValueSeed {
+        interner: context.interner_mut(),
+    };
+    let value = seed
+        .deserialize(&mut deserializer)
+        .and_then(|value| deserializer.end().map(|_| value))
+        .map_err(|err| format!("Invalid JSON: {}.", err))?;
+    Ok(value)
+}
+
+/// `json_stringify(value)` is the JSON text of a value made of lists, tuples, maps with string
+/// keys, strings, numbers, booleans and nil
+#[cfg(feature = "json")]
+pub fn json_stringify(context: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    let value = crate::serde_value::SerializeValue::new(&args[0], context.interner());
+    let text =
+        serde_json::to_string(&value).map_err(|err| format!("Can't convert to JSON: {}.", err))?;
+    Ok(context.value(text))
+}
+
+/// `format(template, values...)` replaces each `{}` in `template` with the next value as
+/// `print` shows it. A placeholder can give a width and a precision, as `{:8}`, `{:.2}` or
+/// `{:>8.2}`, aligned with `<`, `>` or `^`. `{{` and `}}` stand for braces
+pub fn format(context: &mut NativeContext, args: &[Value]) -> Result<Value, NativeError> {
+    let template: String = context.arg(args, 0)?;
+    let mut values = args[1..].iter();
+    let mut formatted = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                formatted.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                formatted.push('}');
+            }
+            '{' => {
+                let mut spec = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => spec.push(c),
+                        None => return Err("Unclosed '{' in format string.".into()),
+                    }
+                }
+                let placeholder = Placeholder::parse(&spec)?;
+                let value = values
+                    .next()
+                    .ok_or("Not enough values for the format string.")?;
+                placeholder.write(&mut formatted, value, context);
+            }
+            '}' => return Err("Unmatched '}' in format string.".into()),
+            c => formatted.push(c),
+        }
+    }
+    if values.next().is_some() {
+        return Err("Too many values for the format string.".into());
+    }
+    Ok(context.value(formatted))
+}
+
+/// Largest width or precision a `format` placeholder can ask for
+const MAX_FORMAT_WIDTH: usize = 1024;
+
+/// Width, precision and alignment of a `format` placeholder
+struct Placeholder {
+    align: Option<char>,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Placeholder {
+    /// Reads what's between the braces, empty or `:` followed by the options
+    fn parse(spec: &str) -> Result<Self, NativeError> {
+        let invalid = || NativeError::from(format!("Invalid placeholder '{{{}}}'.", spec));
+        let mut placeholder = Placeholder {
+            align: None,
+            width: 0,
+            precision: None,
+        };
+        if spec.is_empty() {
+            return Ok(placeholder);
+        }
+        let mut options = spec.strip_prefix(':').ok_or_else(invalid)?;
+        if let Some(align) = options
+            .chars()
+            .next()
+            .filter(|c| matches!(c, '<' | '>' | '^'))
+        {
+            placeholder.align = Some(align);
+            options = &options[1..];
+        }
+        let (width, precision) = match options.split_once('.') {
+            Some((width, precision)) => (width, Some(precision)),
+            None => (options, None),
+        };
+        let bounded = |digits: &str| match digits.parse::<u64>() {
+            Ok(n) if n <= MAX_FORMAT_WIDTH as u64 => Ok(n as usize),
+            // all digits, just too many of them
+            _ if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) => {
+                Err(NativeError::from(format!(
+                    "Width and precision in a format string can't be over {}.",
+                    MAX_FORMAT_WIDTH
+                )))
+            }
+            _ => Err(invalid()),
+        };
+        if !width.is_empty() {
+            placeholder.width = bounded(width)?;
+        }
+        if let Some(precision) = precision {
+            placeholder.precision = Some(bounded(precision)?);
+        }
+        Ok(placeholder)
+    }
+
+    /// Numbers are rounded to the precision and aligned right, anything else is cut to the
+    /// precision in characters and aligned left
+    fn write(&self, formatted: &mut String, value: &Value, context: &NativeContext) {
+        let number = value.as_number();
+        let text = match (self.precision, number) {
+            (Some(precision), Some(number)) => format!("{:.*}", precision, number),
+            (Some(precision), None) => context.display(value).chars().take(precision).collect(),
+            (None, _) => context.display(value),
+        };
+        let padding = self.width.saturating_sub(text.chars().count());
+        let align = self
+            .align
+            .unwrap_or(if number.is_some() { '>' } else { '<' });
+        let (before, after) = match align {
+            '>' => (padding, 0),
+            '^' => (padding / 2, padding - padding / 2),
+            _ => (0, padding),
+        };
+        formatted.extend(std::iter::repeat_n(' ', before));
+        formatted.push_str(&text);
+        formatted.extend(std::iter::repeat_n(' ', after));
+    }
+}
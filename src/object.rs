@@ -1,16 +1,307 @@
-use std::fmt::Display;
+use std::{
+    cell::RefCell,
+    fmt::{Debug, Display},
+    ops::Deref,
+    sync::{Arc, OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
 
-use crate::interner::Interner;
+use ahash::AHashMap;
 
-#[derive(Debug, Clone, PartialEq)]
+use crate::{chunk::Chunk, interner::Interner, natives::NativeContext, value::Value};
+
+#[derive(Debug, Clone)]
 pub enum Object {
     String(AloxString),
+    /// A string built by `+` that hasn't been interned yet, scripts see it as a string
+    StringBuffer(Arc<ObjStringBuffer>),
+    Function(Arc<ObjFunction>),
+    Native(Arc<ObjNative>),
+    Closure(Arc<ObjClosure>),
+    Class(Arc<ObjCell<ObjClass>>),
+    Instance(Arc<ObjCell<ObjInstance>>),
+    BoundMethod(Arc<ObjBoundMethod>),
+    List(Arc<ObjCell<Vec<Value>>>),
+    Map(Arc<ObjCell<AHashMap<MapKey, Value>>>),
+    Iterator(Arc<ObjCell<ObjIterator>>),
+    Coroutine(Arc<ObjCell<ObjCoroutine>>),
+    Range(ObjRange),
+    /// Fixed-size immutable sequence, compared by value
+    Tuple(Arc<[Value]>),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+#[derive(Debug, Clone, PartialEq, Eq, Copy, Hash)]
 pub struct AloxString(pub u32);
 
+/// The kind of heap object behind an `Object`, the part of its identity every kind shares.
+/// New kinds of object get a variant here alongside their `Object` variant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ObjKind {
+    String,
+    Function,
+    Native,
+    Closure,
+    Class,
+    Instance,
+    BoundMethod,
+    List,
+    Map,
+    Iterator,
+    Coroutine,
+    Range,
+    Tuple,
+}
+
+/// The subset of values that can be used as map keys, compared by value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Nil,
+    Bool(bool),
+    /// Bit pattern of a number that isn't whole
+    Number(u64),
+    /// Integers, and whole numbers so that `1` and `1.0` are the same key
+    Int(i64),
+    String(AloxString),
+}
+
+/// Interior mutability for objects values share. A lock rather than a `RefCell`, so values
+/// can move to another thread along with their VM
+#[derive(Default)]
+pub struct ObjCell<T>(RwLock<T>);
+
+impl<T> ObjCell<T> {
+    pub fn new(value: T) -> Self {
+        Self(RwLock::new(value))
+    }
+
+    // a panic while the lock is held aborts the VM's run, the object isn't left half updated
+    pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+        self.0
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, T> {
+        self.0
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+thread_local! {
+    /// Addresses of the cells this thread is formatting with `Debug`
+    static DEBUGGING: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+impl<T: Debug> Debug for ObjCell<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // a list holding itself would be formatted forever
+        let address = self as *const Self as usize;
+        if DEBUGGING.with(|cells| cells.borrow().contains(&address)) {
+            return write!(f, "...");
+        }
+        DEBUGGING.with(|cells| cells.borrow_mut().push(address));
+        let result = self.borrow().fmt(f);
+        DEBUGGING.with(|cells| cells.borrow_mut().pop());
+        result
+    }
+}
+
+/// The contents of an `ObjStringBuffer`, borrowed from the buffer it shares
+pub struct BufferContents<'a> {
+    buffer: RwLockReadGuard<'a, String>,
+    len: usize,
+}
+
+impl Deref for BufferContents<'_> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.buffer[..self.len]
+    }
+}
+
+/// Concatenation result that defers interning until the string is compared, hashed or stored,
+/// so `s = s + x` in a loop appends to one growing buffer instead of interning every step
+pub struct ObjStringBuffer {
+    /// Shared with the values appended from this one, each sees its first `len` bytes
+    buffer: Arc<ObjCell<String>>,
+    len: usize,
+    /// The interned string, once the contents have been observed
+    pub interned: OnceLock<AloxString>,
+}
+
+#[derive(Clone)]
+pub struct ObjFunction {
+    pub arity: usize,
+    /// How many of the trailing parameters have default values
+    pub optional: usize,
+    /// Whether a `...rest` parameter after the `arity` others collects extra arguments
+    pub variadic: bool,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+    pub name: Option<AloxString>,
+    /// Whether the body contains `yield`, calling it then creates a coroutine
+    pub is_generator: bool,
+}
+
+/// Signature of host functions callable from scripts, given the VM's strings and the arguments
+pub type NativeFn = fn(&mut NativeContext, &[Value]) -> Result<Value, NativeError>;
+
+/// Why a native function failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NativeError {
+    /// Becomes a runtime error with this message
+    Message(String),
+    /// Stops the script with `InterpreterError::Exit`, which `catch` can't stop
+    Exit(i32),
+}
+
+impl From<String> for NativeError {
+    fn from(message: String) -> Self {
+        NativeError::Message(message)
+    }
+}
+
+impl From<&str> for NativeError {
+    fn from(message: &str) -> Self {
+        NativeError::Message(message.to_string())
+    }
+}
+
+/// A Rust function exposed to scripts
+pub struct ObjNative {
+    pub name: AloxString,
+    pub arity: usize,
+    /// Whether it also takes any number of arguments past `arity`
+    pub variadic: bool,
+    pub function: NativeFn,
+}
+
+/// A function together with the variables it captured from enclosing scopes
+pub struct ObjClosure {
+    pub function: Arc<ObjFunction>,
+    pub upvalues: Vec<Arc<ObjCell<ObjUpvalue>>>,
+}
+
+/// A captured variable, living on the stack until its scope ends
+#[derive(Debug)]
+pub enum ObjUpvalue {
+    /// Absolute index of the captured slot in the value stack
+    Open(usize),
+    Closed(Value),
+}
+
+pub struct ObjClass {
+    pub name: AloxString,
+    pub methods: AHashMap<u32, Value>,
+    /// Accessors run by `instance.name`, taking precedence over fields
+    pub getters: AHashMap<u32, Value>,
+    /// Accessors run by `instance.name = value`, taking precedence over fields
+    pub setters: AHashMap<u32, Value>,
+}
+
+pub struct ObjInstance {
+    pub class: Arc<ObjCell<ObjClass>>,
+    pub fields: AHashMap<u32, Value>,
+}
+
+/// Integer interval produced by `start..end` or `start..=end`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ObjRange {
+    pub start: f64,
+    pub end: f64,
+    pub inclusive: bool,
+}
+
+/// Cursor driving a `for-in` loop over a list, range or string
+pub struct ObjIterator {
+    /// The list, range or string being walked
+    pub source: Value,
+    /// Element index for lists and ranges, byte offset for strings
+    pub position: usize,
+}
+
+/// A call of a generator function that runs until each `yield` and can be resumed after it,
+/// by calling the coroutine or looping over it with `for-in`
+pub struct ObjCoroutine {
+    pub closure: Arc<ObjClosure>,
+    pub state: CoroutineState,
+    /// Where execution continues in the closure's chunk
+    pub ip: usize,
+    /// The call's stack window, from the callee slot up, while it isn't running
+    pub stack: Vec<Value>,
+    /// Upvalues of locals in `stack` while it is suspended, with the offset of each local.
+    /// They hold the values meanwhile, and open onto the stack again on resuming
+    pub upvalues: Vec<(Arc<ObjCell<ObjUpvalue>>, usize)>,
+    /// Arguments passed when the coroutine was created, the rest take defaults
+    pub arg_count: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoroutineState {
+    /// Created but never resumed, it starts at the top of the function
+    Fresh,
+    /// Stopped at a `yield`, the value it's resumed with becomes the result of the `yield`
+    Suspended,
+    Running,
+    /// Returned or unwound by an exception, it can't be resumed again
+    Done,
+}
+
+/// A method closed over the instance it was accessed from
+pub struct ObjBoundMethod {
+    pub receiver: Value,
+    pub method: Arc<ObjClosure>,
+}
+
+impl ObjKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ObjKind::String => "string",
+            ObjKind::Function => "function",
+            ObjKind::Native => "native function",
+            ObjKind::Closure => "closure",
+            ObjKind::Class => "class",
+            ObjKind::Instance => "instance",
+            ObjKind::BoundMethod => "bound method",
+            ObjKind::List => "list",
+            ObjKind::Map => "map",
+            ObjKind::Iterator => "iterator",
+            ObjKind::Coroutine => "coroutine",
+            ObjKind::Range => "range",
+            ObjKind::Tuple => "tuple",
+        }
+    }
+
+    /// Whether objects of this kind are functions to scripts, classes and coroutines are
+    /// callable but aren't functions
+    pub fn is_function(self) -> bool {
+        matches!(
+            self,
+            ObjKind::Function | ObjKind::Native | ObjKind::Closure | ObjKind::BoundMethod
+        )
+    }
+}
+
 impl Object {
+    pub fn kind(&self) -> ObjKind {
+        match self {
+            Object::String(_) | Object::StringBuffer(_) => ObjKind::String,
+            Object::Function(_) => ObjKind::Function,
+            Object::Native(_) => ObjKind::Native,
+            Object::Closure(_) => ObjKind::Closure,
+            Object::Class(_) => ObjKind::Class,
+            Object::Instance(_) => ObjKind::Instance,
+            Object::BoundMethod(_) => ObjKind::BoundMethod,
+            Object::List(_) => ObjKind::List,
+            Object::Map(_) => ObjKind::Map,
+            Object::Iterator(_) => ObjKind::Iterator,
+            Object::Coroutine(_) => ObjKind::Coroutine,
+            Object::Range(_) => ObjKind::Range,
+            Object::Tuple(_) => ObjKind::Tuple,
+        }
+    }
+
     pub fn from_str(contents: &str, interner: &mut Interner) -> Self {
         Self::String(AloxString(interner.intern(contents)))
     }
@@ -20,10 +311,241 @@ impl Object {
     }
 }
 
+impl MapKey {
+    pub fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Nil => Some(MapKey::Nil),
+            Value::Bool(b) => Some(MapKey::Bool(*b)),
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => {
+                Some(MapKey::Int(*n as i64))
+            }
+            Value::Number(n) => Some(MapKey::Number(n.to_bits())),
+            Value::Int(n) => Some(MapKey::Int(*n)),
+            Value::Obj(Object::String(s)) => Some(MapKey::String(*s)),
+            Value::Obj(_) => None,
+        }
+    }
+
+    pub fn to_value(self) -> Value {
+        match self {
+            MapKey::Nil => Value::Nil,
+            MapKey::Bool(b) => Value::Bool(b),
+            MapKey::Number(bits) => Value::Number(f64::from_bits(bits)),
+            MapKey::Int(n) => Value::Int(n),
+            MapKey::String(s) => Value::Obj(Object::String(s)),
+        }
+    }
+}
+
+impl ObjRange {
+    /// Exclusive upper bound, one past `end` for inclusive ranges
+    pub fn end_exclusive(&self) -> f64 {
+        if self.inclusive {
+            self.end + 1.0
+        } else {
+            self.end
+        }
+    }
+
+    /// The `position`th number of the range, if it has that many
+    pub fn nth(&self, position: usize) -> Option<f64> {
+        let n = self.start + position as f64;
+        (n < self.end_exclusive()).then_some(n)
+    }
+}
+
+impl ObjStringBuffer {
+    pub fn new(contents: String) -> Self {
+        Self {
+            len: contents.len(),
+            buffer: Arc::new(ObjCell::new(contents)),
+            interned: OnceLock::new(),
+        }
+    }
+
+    pub fn contents(&self) -> BufferContents<'_> {
+        BufferContents {
+            buffer: self.buffer.borrow(),
+            len: self.len,
+        }
+    }
+
+    /// These contents followed by `suffix`. The buffer is extended in place unless another
+    /// value was already appended from this one, in which case the contents are copied
+    pub fn append(&self, suffix: &str) -> Self {
+        if self.buffer.borrow().len() != self.len {
+            return Self::new(self.contents().to_string() + suffix);
+        }
+        self.buffer.borrow_mut().push_str(suffix);
+        Self {
+            buffer: Arc::clone(&self.buffer),
+            len: self.len + suffix.len(),
+            interned: OnceLock::new(),
+        }
+    }
+}
+
+impl ObjFunction {
+    pub fn new(name: Option<AloxString>) -> Self {
+        Self {
+            arity: 0,
+            optional: 0,
+            variadic: false,
+            upvalue_count: 0,
+            chunk: Chunk::init(),
+            name,
+            is_generator: false,
+        }
+    }
+}
+
+impl ObjClosure {
+    pub fn new(function: Arc<ObjFunction>) -> Self {
+        Self {
+            upvalues: Vec::with_capacity(function.upvalue_count),
+            function,
+        }
+    }
+}
+
+impl ObjCoroutine {
+    pub fn new(closure: Arc<ObjClosure>, stack: Vec<Value>, arg_count: usize) -> Self {
+        Self {
+            closure,
+            state: CoroutineState::Fresh,
+            ip: 0,
+            stack,
+            upvalues: Vec::new(),
+            arg_count,
+        }
+    }
+}
+
+impl ObjClass {
+    pub fn new(name: AloxString) -> Self {
+        Self {
+            name,
+            methods: AHashMap::new(),
+            getters: AHashMap::new(),
+            setters: AHashMap::new(),
+        }
+    }
+}
+
+impl ObjInstance {
+    pub fn new(class: Arc<ObjCell<ObjClass>>) -> Self {
+        Self {
+            class,
+            fields: AHashMap::new(),
+        }
+    }
+}
+
+impl PartialEq for Object {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Object::String(a), Object::String(b)) => a == b,
+            // a buffer and an interned string can't be compared without the interner, the VM
+            // interns buffers before comparing them
+            (Object::StringBuffer(a), Object::StringBuffer(b)) => *a.contents() == *b.contents(),
+            (Object::Function(a), Object::Function(b)) => Arc::ptr_eq(a, b),
+            (Object::Native(a), Object::Native(b)) => Arc::ptr_eq(a, b),
+            (Object::Closure(a), Object::Closure(b)) => Arc::ptr_eq(a, b),
+            (Object::Class(a), Object::Class(b)) => Arc::ptr_eq(a, b),
+            (Object::Instance(a), Object::Instance(b)) => Arc::ptr_eq(a, b),
+            (Object::BoundMethod(a), Object::BoundMethod(b)) => Arc::ptr_eq(a, b),
+            (Object::List(a), Object::List(b)) => Arc::ptr_eq(a, b),
+            (Object::Map(a), Object::Map(b)) => Arc::ptr_eq(a, b),
+            (Object::Iterator(a), Object::Iterator(b)) => Arc::ptr_eq(a, b),
+            (Object::Coroutine(a), Object::Coroutine(b)) => Arc::ptr_eq(a, b),
+            (Object::Range(a), Object::Range(b)) => a == b,
+            (Object::Tuple(a), Object::Tuple(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
 impl Display for Object {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Object::String(s) => write!(f, "{}", s.0),
+            Object::StringBuffer(buffer) => write!(f, "{}", &*buffer.contents()),
+            Object::Function(fun) => write!(f, "{:?}", fun),
+            Object::Native(native) => write!(f, "{:?}", native),
+            Object::Closure(closure) => write!(f, "{:?}", closure.function),
+            Object::Class(class) => write!(f, "{:?}", class.borrow()),
+            Object::Instance(instance) => write!(f, "{:?}", instance.borrow()),
+            Object::BoundMethod(bound) => write!(f, "{:?}", bound.method),
+            Object::List(list) => write!(f, "<list {}>", list.borrow().len()),
+            Object::Tuple(items) => write!(f, "<tuple {}>", items.len()),
+            Object::Map(map) => write!(f, "<map {}>", map.borrow().len()),
+            Object::Iterator(_) => write!(f, "<iterator>"),
+            Object::Coroutine(coroutine) => write!(f, "{:?}", coroutine.borrow()),
+            Object::Range(range) => {
+                let dots = if range.inclusive { "..=" } else { ".." };
+                write!(f, "{}{}{}", range.start, dots, range.end)
+            }
+        }
+    }
+}
+
+// Debug output stays shallow: instances can hold themselves in a field.
+impl std::fmt::Debug for ObjFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.name {
+            Some(name) => write!(f, "<fn {}>", name.0),
+            None => write!(f, "<script>"),
         }
     }
 }
+
+impl std::fmt::Debug for ObjStringBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", &*self.contents())
+    }
+}
+
+impl std::fmt::Debug for ObjNative {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name.0)
+    }
+}
+
+impl std::fmt::Debug for ObjClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<class {}>", self.name.0)
+    }
+}
+
+impl std::fmt::Debug for ObjClosure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.function)
+    }
+}
+
+impl std::fmt::Debug for ObjBoundMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.method.function)
+    }
+}
+
+impl std::fmt::Debug for ObjIterator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<iterator>")
+    }
+}
+
+impl std::fmt::Debug for ObjCoroutine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.closure.function.name {
+            Some(name) => write!(f, "<coroutine {}>", name.0),
+            None => write!(f, "<coroutine>"),
+        }
+    }
+}
+
+impl std::fmt::Debug for ObjInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<instance {}>", self.class.borrow().name.0)
+    }
+}
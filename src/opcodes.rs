@@ -10,41 +10,302 @@ pub enum Op {
     True,
     False,
     Pop,
+    PopN,
+    Dup,
+    Swap,
     GetLocal,
     SetLocal,
     GetGlobal,
     DefineGlobal,
+    DefineGlobalConst,
     SetGlobal,
     Equal,
     Greater,
     Less,
+    Is,
+    IsType,
     Add,
     Subtract,
     Multiply,
     Divide,
+    Modulo,
+    Power,
+    BitAnd,
+    BitOr,
+    BitXor,
+    BitNot,
+    ShiftLeft,
+    ShiftRight,
     Not,
     Negate,
     Print,
+    Assert,
+    Import,
+    BuildString,
+    BuildList,
+    BuildMap,
+    BuildTuple,
+    Unpack,
+    Range,
+    RangeInclusive,
+    Index,
+    SetIndex,
+    Call,
+    TailCall,
+    Yield,
+    Class,
+    Method,
+    Getter,
+    Setter,
+    GetProperty,
+    SetProperty,
+    Jump,
+    JumpIfFalse,
+    JumpIfNil,
+    Loop,
+    LoopIfTrue,
+    JumpIfPassed,
+    PushHandler,
+    PopHandler,
+    Throw,
+    Closure,
+    GetUpvalue,
+    SetUpvalue,
+    CloseUpvalue,
+    IterNew,
+    IterNext,
+    GetLocalConstantAdd,
+    GetLocalConstantSubtract,
+    GetLocalConstantLess,
+    GetLocalGetLocalAdd,
+    GetLocalGetLocalLess,
+    ClassLong,
+    MethodLong,
+    GetterLong,
+    SetterLong,
+    GetPropertyLong,
+    SetPropertyLong,
+    ClosureLong,
+    ImportLong,
+    AssertLong,
+}
+
+/// Where the second operand of a superinstruction comes from, the first is always a local
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FusedOperand {
+    Constant,
+    Local,
 }
 
 impl Op {
+    /// Number of opcodes
+    pub const COUNT: usize = Op::AssertLong as usize + 1;
+
     pub const fn u8(self) -> u8 {
         self as u8
     }
 
-    pub fn from_u8(byte: u8) -> Self {
-        byte.try_into().expect("unexpected opcode!")
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        byte.try_into().ok()
+    }
+
+    /// Number of operand bytes after the opcode. `Closure` is followed by a further pair of
+    /// bytes for each upvalue of its function
+    pub fn operand_len(self) -> usize {
+        match self {
+            Op::Return
+            | Op::Nil
+            | Op::True
+            | Op::False
+            | Op::Pop
+            | Op::Dup
+            | Op::Swap
+            | Op::Equal
+            | Op::Greater
+            | Op::Less
+            | Op::Is
+            | Op::Add
+            | Op::Subtract
+            | Op::Multiply
+            | Op::Divide
+            | Op::Modulo
+            | Op::Power
+            | Op::BitAnd
+            | Op::BitOr
+            | Op::BitXor
+            | Op::BitNot
+            | Op::ShiftLeft
+            | Op::ShiftRight
+            | Op::Not
+            | Op::Negate
+            | Op::Print
+            | Op::Range
+            | Op::RangeInclusive
+            | Op::Index
+            | Op::SetIndex
+            | Op::PopHandler
+            | Op::Throw
+            | Op::Yield
+            | Op::CloseUpvalue
+            | Op::IterNew => 0,
+            Op::Constant
+            | Op::PopN
+            | Op::GetLocal
+            | Op::SetLocal
+            | Op::IsType
+            | Op::Assert
+            | Op::Import
+            | Op::BuildString
+            | Op::BuildList
+            | Op::BuildMap
+            | Op::BuildTuple
+            | Op::Unpack
+            | Op::Call
+            | Op::TailCall
+            | Op::Class
+            | Op::Method
+            | Op::Getter
+            | Op::Setter
+            | Op::GetProperty
+            | Op::SetProperty
+            | Op::Closure
+            | Op::GetUpvalue
+            | Op::SetUpvalue => 1,
+            Op::GetGlobal
+            | Op::DefineGlobal
+            | Op::DefineGlobalConst
+            | Op::SetGlobal
+            | Op::Jump
+            | Op::JumpIfFalse
+            | Op::JumpIfNil
+            | Op::Loop
+            | Op::LoopIfTrue
+            | Op::PushHandler
+            | Op::GetLocalConstantAdd
+            | Op::GetLocalConstantSubtract
+            | Op::GetLocalConstantLess
+            | Op::GetLocalGetLocalAdd
+            | Op::GetLocalGetLocalLess => 2,
+            Op::ConstantLong
+            | Op::ClassLong
+            | Op::MethodLong
+            | Op::GetterLong
+            | Op::SetterLong
+            | Op::GetPropertyLong
+            | Op::SetPropertyLong
+            | Op::ClosureLong
+            | Op::ImportLong
+            | Op::AssertLong
+            | Op::JumpIfPassed
+            | Op::IterNext => 3,
+        }
+    }
+
+    /// The form of an instruction with a one byte constant operand that takes a 24-bit
+    /// little endian index instead, for constants past the first 256
+    pub fn long_form(self) -> Option<Op> {
+        match self {
+            Op::Constant => Some(Op::ConstantLong),
+            Op::Class => Some(Op::ClassLong),
+            Op::Method => Some(Op::MethodLong),
+            Op::Getter => Some(Op::GetterLong),
+            Op::Setter => Some(Op::SetterLong),
+            Op::GetProperty => Some(Op::GetPropertyLong),
+            Op::SetProperty => Some(Op::SetPropertyLong),
+            Op::Closure => Some(Op::ClosureLong),
+            Op::Import => Some(Op::ImportLong),
+            Op::Assert => Some(Op::AssertLong),
+            _ => None,
+        }
+    }
+
+    /// The instruction a long form stands for, with its one byte constant operand
+    pub fn short_form(self) -> Option<Op> {
+        match self {
+            Op::ConstantLong => Some(Op::Constant),
+            Op::ClassLong => Some(Op::Class),
+            Op::MethodLong => Some(Op::Method),
+            Op::GetterLong => Some(Op::Getter),
+            Op::SetterLong => Some(Op::Setter),
+            Op::GetPropertyLong => Some(Op::GetProperty),
+            Op::SetPropertyLong => Some(Op::SetProperty),
+            Op::ClosureLong => Some(Op::Closure),
+            Op::ImportLong => Some(Op::Import),
+            Op::AssertLong => Some(Op::Assert),
+            _ => None,
+        }
+    }
+
+    /// Direction of a jump instruction, `1` forward and `-1` backward. The 16-bit distance
+    /// is always the last two operand bytes, measured from the end of the instruction
+    pub fn jump_sign(self) -> Option<i64> {
+        match self {
+            Op::Jump | Op::JumpIfFalse | Op::JumpIfNil | Op::PushHandler => Some(1),
+            Op::JumpIfPassed | Op::IterNext => Some(1),
+            Op::Loop | Op::LoopIfTrue => Some(-1),
+            _ => None,
+        }
+    }
+
+    /// Operand source and plain binary op a `GetLocal` led superinstruction stands for
+    pub fn superinstruction(self) -> Option<(FusedOperand, Op)> {
+        match self {
+            Op::GetLocalConstantAdd => Some((FusedOperand::Constant, Op::Add)),
+            Op::GetLocalConstantSubtract => Some((FusedOperand::Constant, Op::Subtract)),
+            Op::GetLocalConstantLess => Some((FusedOperand::Constant, Op::Less)),
+            Op::GetLocalGetLocalAdd => Some((FusedOperand::Local, Op::Add)),
+            Op::GetLocalGetLocalLess => Some((FusedOperand::Local, Op::Less)),
+            _ => None,
+        }
+    }
+
+    /// The superinstruction replacing `GetLocal`, `second`, `op`, if there is one
+    pub fn fuse(second: Op, op: Op) -> Option<Op> {
+        match (second, op) {
+            (Op::Constant, Op::Add) => Some(Op::GetLocalConstantAdd),
+            (Op::Constant, Op::Subtract) => Some(Op::GetLocalConstantSubtract),
+            (Op::Constant, Op::Less) => Some(Op::GetLocalConstantLess),
+            (Op::GetLocal, Op::Add) => Some(Op::GetLocalGetLocalAdd),
+            (Op::GetLocal, Op::Less) => Some(Op::GetLocalGetLocalLess),
+            _ => None,
+        }
+    }
+
+    /// For a binary operator classes can overload, where in `OPERATOR_METHODS` the name of
+    /// the method looked up on the left operand is, and that of the one looked up on the
+    /// right operand when the left has none
+    pub fn operator_methods(self) -> Option<(usize, usize)> {
+        match self {
+            Op::Add => Some((0, 9)),
+            Op::Subtract => Some((1, 10)),
+            Op::Multiply => Some((2, 11)),
+            Op::Divide => Some((3, 12)),
+            Op::Modulo => Some((4, 13)),
+            Op::Power => Some((5, 14)),
+            // comparisons mirror, `a < b` is `b > a`
+            Op::Equal => Some((6, 6)),
+            Op::Greater => Some((7, 8)),
+            Op::Less => Some((8, 7)),
+            _ => None,
+        }
     }
 }
 
+/// Methods a class defines to overload binary operators. The `__r` ones are called on the
+/// right operand, with the left one as the argument
+pub const OPERATOR_METHODS: [&str; 15] = [
+    "__add__", "__sub__", "__mul__", "__div__", "__mod__", "__pow__", "__eq__", "__gt__", "__lt__",
+    "__radd__", "__rsub__", "__rmul__", "__rdiv__", "__rmod__", "__rpow__",
+];
+
 impl TryFrom<u8> for Op {
     type Error = ();
 
     fn try_from(value: u8) -> Result<Self, Self::Error> {
-        if value > Op::Print as u8 {
+        if value > Op::AssertLong as u8 {
             Err(())
         } else {
-            unsafe { Ok(core::mem::transmute(value)) }
+            unsafe { Ok(core::mem::transmute::<u8, Op>(value)) }
         }
     }
 }
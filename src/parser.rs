@@ -1,32 +1,41 @@
-use std::{convert::TryInto, u8};
+use std::{convert::TryFrom, fmt::Display, sync::Arc};
 
 use crate::{
     chunk::Chunk,
-    compiler::{Compiler, Local, U8_COUNT},
+    compiler::{Compiler, FunctionKind, Local, LoopContext, UpvalueRef, U8_COUNT},
+    globals::Globals,
     interner::Interner,
+    object::{AloxString, ObjFunction, Object},
     opcodes::Op,
     scanner::Scanner,
     token::{Token, TokenKind},
-    value::Value,
+    value::{Value, ValueType},
 };
 
-pub type CompilationResult = Result<(), CompilationError>;
-pub struct Parser<'source, 'chunk, 'interner> {
+pub type CompilationResult = Result<(), Vec<CompileDiagnostic>>;
+pub struct Parser<'source, 'chunk> {
     scanner: Scanner<'source>,
     current: Option<Token<'source>>,
     previous: Option<Token<'source>>,
-    current_chunk: &'chunk mut Chunk,
-    interner: &'chunk mut Interner<'interner>,
+    script: &'chunk mut Chunk,
+    interner: &'chunk mut Interner,
+    globals: &'chunk mut Globals,
     had_error: bool,
     panic_mode: bool,
+    diagnostics: Vec<CompileDiagnostic>,
     current_compiler: Compiler<'source>,
+    /// Number of class bodies enclosing the code being compiled
+    class_depth: usize,
+    /// Whether a trailing expression without ';' prints its value, as typed at the prompt
+    repl: bool,
 }
 
-impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
+impl<'source, 'chunk> Parser<'source, 'chunk> {
     pub fn new(
         scanner: Scanner<'source>,
         chunk: &'chunk mut Chunk,
-        interner: &'chunk mut Interner<'interner>,
+        interner: &'chunk mut Interner,
+        globals: &'chunk mut Globals,
     ) -> Self {
         Self {
             scanner,
@@ -34,21 +43,33 @@ impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
             previous: None,
             had_error: false,
             panic_mode: false,
-            current_chunk: chunk,
-            current_compiler: Compiler::new(),
+            diagnostics: Vec::new(),
+            script: chunk,
+            current_compiler: Compiler::new(FunctionKind::Script, None),
+            class_depth: 0,
+            repl: false,
             interner,
+            globals,
         }
     }
 
+    /// Compiles a line typed at the prompt, where a bare expression ending the input prints
+    /// its value instead of needing `print` and `;`
+    pub fn repl_line(mut self) -> Self {
+        self.repl = true;
+        self
+    }
+
     pub fn compile(&mut self) -> CompilationResult {
         self.advance();
         while !self.match_current(TokenKind::Eof) {
             self.declaration();
         }
         if self.had_error {
-            Err(CompilationError::Error)
+            Err(std::mem::take(&mut self.diagnostics))
         } else {
-            self.end_compiler();
+            let (script, _) = self.end_compiler();
+            *self.script = script.chunk;
             Ok(())
         }
     }
@@ -73,11 +94,13 @@ impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
             if self.current.as_ref().unwrap().kind != TokenKind::Error {
                 break;
             }
-            self.error_at_current("")
+            // error tokens carry their message as the lexeme
+            let message = self.current_token().lexeme;
+            self.error_at_current(message)
         }
     }
 
-    fn previous_token(&self) -> &Token {
+    fn previous_token(&self) -> &Token<'source> {
         if let Some(previous) = &self.previous {
             previous
         } else {
@@ -85,7 +108,7 @@ impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
         }
     }
 
-    fn current_token(&self) -> &Token {
+    fn current_token(&self) -> &Token<'source> {
         if let Some(current) = &self.current {
             current
         } else {
@@ -94,8 +117,16 @@ impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
     }
 
     fn declaration(&mut self) {
-        if self.match_current(TokenKind::Var) {
-            self.var_declaration();
+        if self.match_current(TokenKind::Class) {
+            self.class_declaration();
+        } else if self.match_current(TokenKind::Fun) {
+            self.fun_declaration();
+        } else if self.match_current(TokenKind::Var) {
+            self.var_declaration(false);
+        } else if self.match_current(TokenKind::Const) {
+            self.var_declaration(true);
+        } else if self.match_current(TokenKind::Import) {
+            self.import_declaration();
         } else {
             self.statement();
         }
@@ -104,26 +135,316 @@ impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
         }
     }
 
-    fn var_declaration(&mut self) {
-        let global = self.parse_variable("Expect variable name.");
+    fn class_declaration(&mut self) {
+        self.consume(TokenKind::Identifier, "Expect class name.");
+        let class_name = self.previous_token().lexeme;
+        let name_constant = self.identifier_constant(class_name);
+        self.declare_variable();
 
-        if self.match_current(TokenKind::Equal) {
+        self.emit_constant_op(Op::Class, name_constant);
+        // keep a copy of the class on the stack so methods can be bound to it
+        self.emit_byte(Op::Dup.u8());
+        let global = if self.current_compiler.scope_depth > 0 {
+            0
+        } else {
+            self.global_slot(class_name)
+        };
+        self.define_variable(global);
+
+        self.class_depth += 1;
+        self.consume(TokenKind::LeftBrace, "Expect '{' before class body.");
+        while !self.check(TokenKind::RightBrace) && !self.check(TokenKind::Eof) {
+            self.method();
+        }
+        self.consume(TokenKind::RightBrace, "Expect '}' after class body.");
+        self.emit_byte(Op::Pop.u8());
+        self.class_depth -= 1;
+    }
+
+    fn method(&mut self) {
+        // `get` and `set` are only accessor prefixes when a property name follows them
+        let accessor = if self.check(TokenKind::Identifier)
+            && self.scanner.clone().scan_token().kind == TokenKind::Identifier
+        {
+            match self.current_token().lexeme {
+                "get" => Some((FunctionKind::Getter, Op::Getter)),
+                "set" => Some((FunctionKind::Setter, Op::Setter)),
+                _ => None,
+            }
+        } else {
+            None
+        };
+        if accessor.is_some() {
+            self.advance();
+        }
+
+        self.consume(TokenKind::Identifier, "Expect method name.");
+        let name = self.previous_token().lexeme;
+        let constant = self.identifier_constant(name);
+        let (kind, op) = accessor.unwrap_or(if name == "init" {
+            (FunctionKind::Initializer, Op::Method)
+        } else {
+            (FunctionKind::Method, Op::Method)
+        });
+        self.function(kind, name);
+        self.emit_constant_op(op, constant);
+    }
+
+    fn fun_declaration(&mut self) {
+        let global = self.parse_variable("Expect function name.");
+        self.mark_initialized();
+        self.function(FunctionKind::Function, self.previous_token().lexeme);
+        self.define_variable(global);
+    }
+
+    fn function(&mut self, kind: FunctionKind, name: &str) {
+        let name = self.interner.intern(name);
+        let compiler = Compiler::new(kind, Some(AloxString(name)));
+        let enclosing = std::mem::replace(&mut self.current_compiler, compiler);
+        self.current_compiler.enclosing = Some(Box::new(enclosing));
+        self.begin_scope();
+
+        // getters are declared without a parameter list
+        if kind != FunctionKind::Getter {
+            self.parameters();
+        }
+        if kind == FunctionKind::Setter && self.current_compiler.function.arity != 1 {
+            self.error("Setter must take exactly one parameter.");
+        }
+        self.consume(TokenKind::LeftBrace, "Expect '{' before function body.");
+        self.block();
+
+        let (function, upvalues) = self.end_compiler();
+        let constant = self.make_constant(Value::Obj(Object::Function(Arc::new(function))));
+        self.emit_constant_op(Op::Closure, constant);
+        for upvalue in upvalues {
+            self.emit_bytes(upvalue.is_local as u8, upvalue.index);
+        }
+    }
+
+    fn parameters(&mut self) {
+        self.consume(TokenKind::LeftParen, "Expect '(' after function name.");
+        if !self.check(TokenKind::RightParen) {
+            loop {
+                if self.match_current(TokenKind::DotDotDot) {
+                    self.rest_parameter();
+                    break;
+                }
+                self.current_compiler.function.arity += 1;
+                if self.current_compiler.function.arity > 255 {
+                    self.error_at_current("Can't have more than 255 parameters.");
+                }
+                let constant = self.parse_variable("Expect parameter name.");
+                self.define_variable(constant);
+                if self.match_current(TokenKind::Equal) {
+                    self.default_argument();
+                } else if self.current_compiler.function.optional > 0 {
+                    self.error("Can't have a required parameter after an optional one.");
+                }
+                if !self.match_current(TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenKind::RightParen, "Expect ')' after parameters.");
+    }
+
+    /// Declares the `...rest` parameter, which takes the slot after every fixed one
+    fn rest_parameter(&mut self) {
+        let constant = self.parse_variable("Expect rest parameter name.");
+        self.define_variable(constant);
+        self.current_compiler.function.variadic = true;
+        if self.check(TokenKind::Comma) || self.check(TokenKind::Equal) {
+            self.error_at_current("Rest parameter must be the last parameter.");
+        }
+    }
+
+    /// Emits the prologue code evaluating a parameter's default value when the caller
+    /// left that argument out
+    fn default_argument(&mut self) {
+        let slot = (self.current_compiler.count - 1) as u8;
+        self.current_compiler.function.optional += 1;
+        self.emit_bytes(Op::JumpIfPassed.u8(), slot);
+        let skip = self.current_chunk().code.len();
+        self.emit_bytes(0xff, 0xff);
+        self.expression();
+        self.emit_bytes(Op::SetLocal.u8(), slot);
+        self.emit_byte(Op::Pop.u8());
+        self.patch_jump(skip);
+    }
+
+    fn lambda(&mut self, _can_assign: bool) {
+        self.function(FunctionKind::Function, "lambda");
+    }
+
+    /// Compiles `yield value`, which suspends the enclosing function and evaluates to the
+    /// value it's resumed with. Its presence makes the function a generator
+    fn yield_(&mut self, _can_assign: bool) {
+        match self.current_compiler.kind {
+            FunctionKind::Script => self.error("Can't yield from top-level code."),
+            FunctionKind::Initializer => self.error("Can't yield from an initializer."),
+            FunctionKind::Setter => self.error("Can't yield from a setter."),
+            _ => {}
+        }
+        if self.current_compiler.try_depth > 0 {
+            // handlers are tied to stack positions that don't survive a suspension
+            self.error("Can't yield inside a try block.");
+        }
+        self.current_compiler.function.is_generator = true;
+        if matches!(
+            self.current_token().kind,
+            TokenKind::Semicolon
+                | TokenKind::RightParen
+                | TokenKind::RightBracket
+                | TokenKind::RightBrace
+                | TokenKind::Comma
+        ) {
+            self.emit_byte(Op::Nil.u8());
+        } else {
             self.expression();
+        }
+        self.emit_byte(Op::Yield.u8());
+    }
+
+    /// Compiles `import "path";` or `import name from "path";`, the latter binding one of
+    /// the module's globals to `name` in the current scope
+    fn import_declaration(&mut self) {
+        let name = if self.match_current(TokenKind::Identifier) {
+            let name = *self.previous_token();
+            if self.check(TokenKind::Identifier) && self.current_token().lexeme == "from" {
+                self.advance();
+            } else {
+                self.error_at_current("Expect 'from' after imported name.");
+            }
+            Some(name)
         } else {
-            self.emit_byte(Op::Nil.u8())
+            None
+        };
+        self.consume(TokenKind::String, "Expect module path.");
+        let lexeme = self.previous_token().lexeme;
+        let path = self.identifier_constant(&lexeme[1..lexeme.len() - 1]);
+        self.consume(TokenKind::Semicolon, "Expect ';' after import.");
+
+        self.emit_constant_op(Op::Import, path);
+        self.emit_byte(Op::Pop.u8());
+        if let Some(name) = name {
+            let global = self.global_slot(name.lexeme);
+            self.emit_global(Op::GetGlobal, global);
+            if self.current_compiler.scope_depth > 0 {
+                self.add_local(name);
+                self.mark_initialized();
+            } else {
+                // module globals are shared already, this only checks the name exists
+                self.emit_byte(Op::Pop.u8());
+            }
+        }
+    }
+
+    fn var_declaration(&mut self, is_const: bool) {
+        // each name is defined before the next initializer, so `var a = 1, b = a;` works
+        loop {
+            self.single_var_declaration(is_const);
+            if !self.match_current(TokenKind::Comma) {
+                break;
+            }
         }
 
         self.consume(
             TokenKind::Semicolon,
             "Expect ';' after variable declaration.",
         );
+    }
 
-        self.define_variable(global);
+    fn single_var_declaration(&mut self, is_const: bool) {
+        if self.match_current(TokenKind::LeftParen) {
+            self.destructuring_declaration(is_const);
+            return;
+        }
+        let global = self.parse_variable("Expect variable name.");
+        if is_const && self.current_compiler.scope_depth > 0 {
+            let count = self.current_compiler.count;
+            self.current_compiler.locals[count - 1].is_const = true;
+        }
+
+        if self.match_current(TokenKind::Equal) {
+            self.expression();
+        } else if is_const {
+            self.error_at_current("Expect '=' after constant name.");
+        } else {
+            self.emit_byte(Op::Nil.u8())
+        }
+
+        if is_const && self.current_compiler.scope_depth == 0 {
+            // global constants can only be enforced once the VM sees every definition
+            self.emit_global(Op::DefineGlobalConst, global);
+        } else {
+            self.define_variable(global);
+        }
+    }
+
+    /// Compiles `var (a, b) = expr;`, binding each item of a tuple or list in order
+    fn destructuring_declaration(&mut self, is_const: bool) {
+        let mut globals = Vec::new();
+        loop {
+            globals.push(self.parse_variable("Expect variable name."));
+            if is_const && self.current_compiler.scope_depth > 0 {
+                let count = self.current_compiler.count;
+                self.current_compiler.locals[count - 1].is_const = true;
+            }
+            if !self.match_current(TokenKind::Comma) {
+                break;
+            }
+        }
+        if globals.len() > 255 {
+            self.error("Can't destructure more than 255 variables.");
+        }
+        self.consume(TokenKind::RightParen, "Expect ')' after variable names.");
+        self.consume(TokenKind::Equal, "Expect '=' after destructuring pattern.");
+        self.expression();
+        self.emit_bytes(Op::Unpack.u8(), globals.len() as u8);
+
+        if self.current_compiler.scope_depth > 0 {
+            // the unpacked items already sit in the new locals' slots
+            let count = self.current_compiler.count;
+            for local in &mut self.current_compiler.locals[count - globals.len()..count] {
+                local.depth = self.current_compiler.scope_depth;
+            }
+            return;
+        }
+        let define = if is_const {
+            Op::DefineGlobalConst
+        } else {
+            Op::DefineGlobal
+        };
+        // the last item is on top of the stack
+        for global in globals.into_iter().rev() {
+            self.emit_global(define, global);
+        }
     }
 
     fn statement(&mut self) {
         if self.match_current(TokenKind::Print) {
             self.print_statement();
+        } else if self.match_current(TokenKind::Assert) {
+            self.assert_statement();
+        } else if self.match_current(TokenKind::Return) {
+            self.return_statement();
+        } else if self.match_current(TokenKind::If) {
+            self.if_statement();
+        } else if self.match_current(TokenKind::While) {
+            self.while_statement();
+        } else if self.match_current(TokenKind::Do) {
+            self.do_while_statement();
+        } else if self.match_current(TokenKind::For) {
+            self.for_statement();
+        } else if self.match_current(TokenKind::Break) {
+            self.break_statement();
+        } else if self.match_current(TokenKind::Throw) {
+            self.throw_statement();
+        } else if self.match_current(TokenKind::Try) {
+            self.try_statement();
+        } else if self.match_current(TokenKind::Continue) {
+            self.continue_statement();
         } else if self.match_current(TokenKind::LeftBrace) {
             self.begin_scope();
             self.block();
@@ -139,13 +460,8 @@ impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
 
     fn end_scope(&mut self) {
         self.current_compiler.decrease_scope();
-        while self.current_compiler.count > 0
-            && self.current_compiler.locals[self.current_compiler.count - 1].depth
-                > self.current_compiler.scope_depth
-        {
-            self.emit_byte(Op::Pop.u8());
-            self.current_compiler.count -= 1;
-        }
+        let discarded = self.discard_locals(self.current_compiler.scope_depth);
+        self.current_compiler.count -= discarded;
     }
 
     fn block(&mut self) {
@@ -157,23 +473,391 @@ impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
 
     fn expression_statement(&mut self) {
         self.expression();
+        if self.repl && self.current_compiler.scope_depth == 0 && self.check(TokenKind::Eof) {
+            self.emit_byte(Op::Print.u8());
+            return;
+        }
         self.consume(TokenKind::Semicolon, "Expected ';' after expression.");
         self.emit_byte(Op::Pop.u8());
     }
 
+    /// Compiles `assert cond;` or `assert cond, message;`, keeping the condition's source
+    /// text for the failure report
+    fn assert_statement(&mut self) {
+        let first = self.current_token().lexeme;
+        self.expression();
+        let last = self.previous_token().lexeme;
+        let source = self.scanner.source();
+        let start = (first.as_ptr() as usize).wrapping_sub(source.as_ptr() as usize);
+        let end = (last.as_ptr() as usize + last.len()).wrapping_sub(source.as_ptr() as usize);
+        let text = source.get(start..end).unwrap_or_default();
+        let text = self.identifier_constant(text);
+
+        if self.match_current(TokenKind::Comma) {
+            self.expression();
+        } else {
+            self.emit_byte(Op::Nil.u8());
+        }
+        self.consume(TokenKind::Semicolon, "Expect ';' after assertion.");
+        self.emit_constant_op(Op::Assert, text);
+    }
+
     fn print_statement(&mut self) {
         self.expression();
         self.consume(TokenKind::Semicolon, "Expected ';' after value.");
         self.emit_byte(Op::Print.u8())
     }
 
+    fn if_statement(&mut self) {
+        self.consume(TokenKind::LeftParen, "Expect '(' after 'if'.");
+        self.expression();
+        self.consume(TokenKind::RightParen, "Expect ')' after condition.");
+
+        let then_jump = self.emit_jump(Op::JumpIfFalse.u8());
+        self.emit_byte(Op::Pop.u8());
+        self.statement();
+
+        let else_jump = self.emit_jump(Op::Jump.u8());
+        self.patch_jump(then_jump);
+        self.emit_byte(Op::Pop.u8());
+
+        if self.match_current(TokenKind::Else) {
+            self.statement();
+        }
+        self.patch_jump(else_jump);
+    }
+
+    fn while_statement(&mut self) {
+        let loop_start = self.current_chunk().code.len();
+        self.consume(TokenKind::LeftParen, "Expect '(' after 'while'.");
+        self.expression();
+        self.consume(TokenKind::RightParen, "Expect ')' after condition.");
+
+        let exit_jump = self.emit_jump(Op::JumpIfFalse.u8());
+        self.emit_byte(Op::Pop.u8());
+        self.begin_loop(Some(loop_start));
+        self.statement();
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_byte(Op::Pop.u8());
+        self.end_loop();
+    }
+
+    fn do_while_statement(&mut self) {
+        let loop_start = self.current_chunk().code.len();
+        self.begin_loop(None);
+        self.statement();
+
+        // `continue` skips the rest of the body but still checks the condition
+        let continues = match self.current_compiler.loops.last_mut() {
+            Some(context) => std::mem::take(&mut context.continues),
+            None => Vec::new(),
+        };
+        for jump in continues {
+            self.patch_jump(jump);
+        }
+
+        self.consume(TokenKind::While, "Expect 'while' after do-while body.");
+        self.consume(TokenKind::LeftParen, "Expect '(' after 'while'.");
+        self.expression();
+        self.consume(TokenKind::RightParen, "Expect ')' after condition.");
+        self.consume(TokenKind::Semicolon, "Expect ';' after do-while condition.");
+
+        self.emit_backward_jump(Op::LoopIfTrue.u8(), loop_start);
+        self.end_loop();
+    }
+
+    fn for_statement(&mut self) {
+        self.begin_scope();
+        self.consume(TokenKind::LeftParen, "Expect '(' after 'for'.");
+        if self.at_for_in() {
+            self.for_in_statement();
+            self.end_scope();
+            return;
+        }
+        if self.match_current(TokenKind::Semicolon) {
+            // no initializer
+        } else if self.match_current(TokenKind::Var) {
+            self.var_declaration(false);
+        } else {
+            self.expression_statement();
+        }
+
+        let mut loop_start = self.current_chunk().code.len();
+        let mut exit_jump = None;
+        if !self.match_current(TokenKind::Semicolon) {
+            self.expression();
+            self.consume(TokenKind::Semicolon, "Expect ';' after loop condition.");
+            exit_jump = Some(self.emit_jump(Op::JumpIfFalse.u8()));
+            self.emit_byte(Op::Pop.u8());
+        }
+
+        if !self.match_current(TokenKind::RightParen) {
+            let body_jump = self.emit_jump(Op::Jump.u8());
+            let increment_start = self.current_chunk().code.len();
+            self.expression();
+            self.emit_byte(Op::Pop.u8());
+            self.consume(TokenKind::RightParen, "Expect ')' after for clauses.");
+
+            self.emit_loop(loop_start);
+            loop_start = increment_start;
+            self.patch_jump(body_jump);
+        }
+
+        self.begin_loop(Some(loop_start));
+        self.statement();
+        self.emit_loop(loop_start);
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump);
+            self.emit_byte(Op::Pop.u8());
+        }
+        self.end_loop();
+        self.end_scope();
+    }
+
+    /// Looks past the current token to spot `name in` or `var name in`
+    fn at_for_in(&self) -> bool {
+        let mut probe = self.scanner.clone();
+        match self.current_token().kind {
+            TokenKind::Identifier => probe.scan_token().kind == TokenKind::In,
+            TokenKind::Var => {
+                probe.scan_token().kind == TokenKind::Identifier
+                    && probe.scan_token().kind == TokenKind::In
+            }
+            _ => false,
+        }
+    }
+
+    /// Compiles the rest of `for (item in iterable) body`, inside the scope opened by the
+    /// `for` statement
+    fn for_in_statement(&mut self) {
+        self.match_current(TokenKind::Var);
+        self.consume(TokenKind::Identifier, "Expect loop variable name.");
+        let item = *self.previous_token();
+        self.consume(TokenKind::In, "Expect 'in' after loop variable.");
+        self.expression();
+        self.consume(TokenKind::RightParen, "Expect ')' after for-in clauses.");
+
+        // the iterator lives in a hidden local for the duration of the loop
+        self.emit_byte(Op::IterNew.u8());
+        let iterator = Token {
+            kind: TokenKind::Identifier,
+            line: item.line,
+            column: item.column,
+            lexeme: "(iterator)",
+        };
+        self.add_local(iterator);
+        self.mark_initialized();
+        let slot = (self.current_compiler.count - 1) as u8;
+
+        let loop_start = self.current_chunk().code.len();
+        self.emit_bytes(Op::IterNext.u8(), slot);
+        let exit_jump = self.current_chunk().code.len();
+        self.emit_bytes(0xff, 0xff);
+
+        self.begin_loop(Some(loop_start));
+        // each iteration gets a fresh binding so closures capture the current item
+        self.begin_scope();
+        self.add_local(item);
+        self.mark_initialized();
+        self.statement();
+        self.end_scope();
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.end_loop();
+    }
+
+    fn throw_statement(&mut self) {
+        self.expression();
+        self.consume(TokenKind::Semicolon, "Expect ';' after thrown value.");
+        self.emit_byte(Op::Throw.u8());
+    }
+
+    fn try_statement(&mut self) {
+        let handler = self.emit_jump(Op::PushHandler.u8());
+        self.current_compiler.try_depth += 1;
+        self.consume(TokenKind::LeftBrace, "Expect '{' after 'try'.");
+        self.begin_scope();
+        self.block();
+        self.end_scope();
+        self.current_compiler.try_depth -= 1;
+        self.emit_byte(Op::PopHandler.u8());
+        let exit_jump = self.emit_jump(Op::Jump.u8());
+
+        // a throw resumes here with the stack unwound and the thrown value on top
+        self.patch_jump(handler);
+        self.consume(TokenKind::Catch, "Expect 'catch' after try block.");
+        self.consume(TokenKind::LeftParen, "Expect '(' after 'catch'.");
+        self.begin_scope();
+        self.consume(TokenKind::Identifier, "Expect exception variable name.");
+        self.add_local(*self.previous_token());
+        self.mark_initialized();
+        self.consume(
+            TokenKind::RightParen,
+            "Expect ')' after exception variable.",
+        );
+        self.consume(TokenKind::LeftBrace, "Expect '{' before catch body.");
+        self.block();
+        self.end_scope();
+        self.patch_jump(exit_jump);
+    }
+
+    fn break_statement(&mut self) {
+        if let Some(context) = self.current_compiler.loops.last() {
+            let (depth, try_depth) = (context.scope_depth, context.try_depth);
+            self.discard_handlers(try_depth);
+            self.discard_locals(depth);
+            let jump = self.emit_jump(Op::Jump.u8());
+            if let Some(context) = self.current_compiler.loops.last_mut() {
+                context.breaks.push(jump);
+            }
+        } else {
+            self.error("Can't use 'break' outside of a loop.");
+        }
+        self.consume(TokenKind::Semicolon, "Expect ';' after 'break'.");
+    }
+
+    fn continue_statement(&mut self) {
+        if let Some(context) = self.current_compiler.loops.last() {
+            let (depth, start, try_depth) = (context.scope_depth, context.start, context.try_depth);
+            self.discard_handlers(try_depth);
+            self.discard_locals(depth);
+            match start {
+                Some(start) => self.emit_loop(start),
+                None => {
+                    let jump = self.emit_jump(Op::Jump.u8());
+                    if let Some(context) = self.current_compiler.loops.last_mut() {
+                        context.continues.push(jump);
+                    }
+                }
+            }
+        } else {
+            self.error("Can't use 'continue' outside of a loop.");
+        }
+        self.consume(TokenKind::Semicolon, "Expect ';' after 'continue'.");
+    }
+
+    fn begin_loop(&mut self, start: Option<usize>) {
+        let context = LoopContext {
+            start,
+            continues: Vec::new(),
+            scope_depth: self.current_compiler.scope_depth,
+            breaks: Vec::new(),
+            try_depth: self.current_compiler.try_depth,
+        };
+        self.current_compiler.loops.push(context);
+    }
+
+    /// Patches pending `break` jumps to land on the next emitted instruction
+    fn end_loop(&mut self) {
+        if let Some(context) = self.current_compiler.loops.pop() {
+            for jump in context.breaks {
+                self.patch_jump(jump);
+            }
+        }
+    }
+
+    /// Pops the handlers of `try` blocks a jump leaves, down to `try_depth`
+    fn discard_handlers(&mut self, try_depth: usize) {
+        for _ in try_depth..self.current_compiler.try_depth {
+            self.emit_byte(Op::PopHandler.u8());
+        }
+    }
+
+    /// Emits code dropping the locals deeper than `depth`, returning how many there are
+    fn discard_locals(&mut self, depth: i32) -> usize {
+        let count = self.current_compiler.count;
+        let discarded: Vec<bool> = self.current_compiler.locals[..count]
+            .iter()
+            .rev()
+            .take_while(|local| local.depth > depth)
+            .map(|local| local.is_captured)
+            .collect();
+        // runs of plain locals go in one instruction, captured ones must be closed singly
+        let mut pops = 0;
+        for &is_captured in &discarded {
+            if is_captured {
+                self.emit_pops(pops);
+                pops = 0;
+                self.emit_byte(Op::CloseUpvalue.u8());
+            } else {
+                pops += 1;
+            }
+        }
+        self.emit_pops(pops);
+        discarded.len()
+    }
+
+    fn emit_pops(&mut self, mut count: usize) {
+        while count > 1 {
+            let n = count.min(u8::MAX as usize);
+            self.emit_bytes(Op::PopN.u8(), n as u8);
+            count -= n;
+        }
+        if count == 1 {
+            self.emit_byte(Op::Pop.u8());
+        }
+    }
+
+    fn return_statement(&mut self) {
+        if self.current_compiler.kind == FunctionKind::Script {
+            self.error("Can't return from top-level code.");
+        }
+        if self.match_current(TokenKind::Semicolon) {
+            self.emit_return();
+        } else {
+            match self.current_compiler.kind {
+                FunctionKind::Initializer => {
+                    self.error("Can't return a value from an initializer.")
+                }
+                FunctionKind::Setter => self.error("Can't return a value from a setter."),
+                _ => {}
+            }
+            self.expression();
+            self.consume(TokenKind::Semicolon, "Expect ';' after return value.");
+            // a handler would have to outlive the frame, so calls inside `try` stay plain
+            let end = self.current_chunk().code.len();
+            if self.current_compiler.try_depth == 0
+                && self.current_compiler.last_call == Some(end - 2)
+            {
+                self.current_chunk().code[end - 2] = Op::TailCall.u8();
+            }
+            self.emit_byte(Op::Return.u8());
+        }
+    }
+
     fn expression(&mut self) {
         self.parse_precedence(Precedence::Assignment);
     }
 
     fn number(&mut self, _can_assign: bool) {
-        let value = self.previous_token().lexeme.parse::<f64>().unwrap();
-        self.emit_constant(Value::Number(value));
+        let lexeme = self.previous_token().lexeme.replace('_', "");
+        match lexeme.parse::<f64>() {
+            Ok(value) => self.emit_constant(Value::Number(value)),
+            Err(_) => self.error("Invalid number literal."),
+        }
+    }
+
+    fn integer(&mut self, _can_assign: bool) {
+        let lexeme = self.previous_token().lexeme.replace('_', "");
+        // hex and binary literals are bit patterns, all 64 bits of them can be set
+        let bits = match lexeme.get(..2) {
+            Some("0x") | Some("0X") => Some(u64::from_str_radix(&lexeme[2..], 16)),
+            Some("0b") | Some("0B") => Some(u64::from_str_radix(&lexeme[2..], 2)),
+            _ => None,
+        };
+        match bits {
+            Some(Ok(bits)) => self.emit_constant(Value::Int(bits as i64)),
+            Some(Err(_)) => self.error("Integer literal too large."),
+            // decimal literals too large for an integer are numbers, as before integers existed
+            None => match lexeme.parse::<i64>() {
+                Ok(value) => self.emit_constant(Value::Int(value)),
+                Err(_) => self.number(false),
+            },
+        }
     }
 
     fn unary(&mut self, _can_assign: bool) {
@@ -186,6 +870,7 @@ impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
         match op_kind {
             TokenKind::Minus => self.emit_byte(Op::Negate.u8()),
             TokenKind::Bang => self.emit_byte(Op::Not.u8()),
+            TokenKind::Tilde => self.emit_byte(Op::BitNot.u8()),
             _ => unreachable!(),
         }
     }
@@ -193,13 +878,25 @@ impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
     fn binary(&mut self, _can_assign: bool) {
         let op_kind = self.previous_token().kind;
         let rule = self.find_rule(op_kind);
-        self.parse_precedence((rule.precedence as u8 + 1).into());
+        if op_kind == TokenKind::StarStar {
+            // right associative: `2 ** 3 ** 2` is `2 ** (3 ** 2)`
+            self.parse_precedence(rule.precedence);
+        } else {
+            self.parse_precedence((rule.precedence as u8 + 1).into());
+        }
 
         match op_kind {
             TokenKind::Plus => self.emit_byte(Op::Add.u8()),
             TokenKind::Minus => self.emit_byte(Op::Subtract.u8()),
             TokenKind::Star => self.emit_byte(Op::Multiply.u8()),
             TokenKind::Slash => self.emit_byte(Op::Divide.u8()),
+            TokenKind::Percent => self.emit_byte(Op::Modulo.u8()),
+            TokenKind::StarStar => self.emit_byte(Op::Power.u8()),
+            TokenKind::Ampersand => self.emit_byte(Op::BitAnd.u8()),
+            TokenKind::Pipe => self.emit_byte(Op::BitOr.u8()),
+            TokenKind::Caret => self.emit_byte(Op::BitXor.u8()),
+            TokenKind::LessLess => self.emit_byte(Op::ShiftLeft.u8()),
+            TokenKind::GreaterGreater => self.emit_byte(Op::ShiftRight.u8()),
             TokenKind::BangEqual => self.emit_bytes(Op::Equal.u8(), Op::Not.u8()),
             TokenKind::EqualEqual => self.emit_byte(Op::Equal.u8()),
             TokenKind::Greater => self.emit_byte(Op::Greater.u8()),
@@ -210,6 +907,194 @@ impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
         }
     }
 
+    fn range(&mut self, _can_assign: bool) {
+        let op_kind = self.previous_token().kind;
+        // non-associative, `1..2..3` is an error
+        self.parse_precedence(Precedence::BitOr);
+        if op_kind == TokenKind::DotDotEqual {
+            self.emit_byte(Op::RangeInclusive.u8());
+        } else {
+            self.emit_byte(Op::Range.u8());
+        }
+    }
+
+    /// Compiles `value is Type`, where built-in type names are checked without a lookup
+    fn is(&mut self, _can_assign: bool) {
+        let name = self.current_token().lexeme;
+        match ValueType::from_name(name) {
+            Some(kind) if self.check(TokenKind::Identifier) => {
+                self.advance();
+                self.emit_bytes(Op::IsType.u8(), kind as u8);
+            }
+            _ => {
+                self.parse_precedence(Precedence::Range);
+                self.emit_byte(Op::Is.u8());
+            }
+        }
+    }
+
+    fn and(&mut self, _can_assign: bool) {
+        let end_jump = self.emit_jump(Op::JumpIfFalse.u8());
+        self.emit_byte(Op::Pop.u8());
+        self.parse_precedence(Precedence::And);
+        self.patch_jump(end_jump);
+    }
+
+    fn or(&mut self, _can_assign: bool) {
+        let else_jump = self.emit_jump(Op::JumpIfFalse.u8());
+        let end_jump = self.emit_jump(Op::Jump.u8());
+        self.patch_jump(else_jump);
+        self.emit_byte(Op::Pop.u8());
+        self.parse_precedence(Precedence::Or);
+        self.patch_jump(end_jump);
+    }
+
+    fn coalesce(&mut self, _can_assign: bool) {
+        let nil_jump = self.emit_jump(Op::JumpIfNil.u8());
+        let end_jump = self.emit_jump(Op::Jump.u8());
+        self.patch_jump(nil_jump);
+        self.emit_byte(Op::Pop.u8());
+        // right associative: `a ?? b ?? c` is `a ?? (b ?? c)`
+        self.parse_precedence(Precedence::Coalesce);
+        self.patch_jump(end_jump);
+    }
+
+    fn call(&mut self, _can_assign: bool) {
+        let arg_count = self.argument_list();
+        self.current_compiler.last_call = Some(self.current_chunk().code.len());
+        self.emit_bytes(Op::Call.u8(), arg_count);
+    }
+
+    fn argument_list(&mut self) -> u8 {
+        let mut arg_count: usize = 0;
+        if !self.check(TokenKind::RightParen) {
+            loop {
+                self.expression();
+                if arg_count == 255 {
+                    self.error("Can't have more than 255 arguments.");
+                }
+                arg_count += 1;
+                if !self.match_current(TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenKind::RightParen, "Expect ')' after arguments.");
+        arg_count as u8
+    }
+
+    fn list(&mut self, _can_assign: bool) {
+        let mut item_count: usize = 0;
+        if !self.check(TokenKind::RightBracket) {
+            loop {
+                if self.check(TokenKind::RightBracket) {
+                    // trailing comma
+                    break;
+                }
+                self.expression();
+                if item_count == 255 {
+                    self.error("Can't have more than 255 items in a list literal.");
+                }
+                item_count += 1;
+                if !self.match_current(TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenKind::RightBracket, "Expect ']' after list items.");
+        self.emit_bytes(Op::BuildList.u8(), item_count as u8);
+    }
+
+    fn map(&mut self, _can_assign: bool) {
+        let mut entry_count: usize = 0;
+        if !self.check(TokenKind::RightBrace) {
+            loop {
+                if self.check(TokenKind::RightBrace) {
+                    // trailing comma
+                    break;
+                }
+                self.expression();
+                self.consume(TokenKind::Colon, "Expect ':' after map key.");
+                self.expression();
+                if entry_count == 255 {
+                    self.error("Can't have more than 255 entries in a map literal.");
+                }
+                entry_count += 1;
+                if !self.match_current(TokenKind::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenKind::RightBrace, "Expect '}' after map entries.");
+        self.emit_bytes(Op::BuildMap.u8(), entry_count as u8);
+    }
+
+    fn index(&mut self, can_assign: bool) {
+        let start = self.current_chunk().code.len();
+        let receiver = self
+            .current_compiler
+            .last_read
+            .filter(|&(end, ..)| can_assign && end == start);
+        self.expression();
+        self.consume(TokenKind::RightBracket, "Expect ']' after index.");
+
+        if can_assign && self.match_current(TokenKind::Equal) {
+            self.expression();
+            self.emit_byte(Op::SetIndex.u8());
+        } else if let Some((op, (_, get_op, arg))) =
+            receiver.and_then(|receiver| Some((self.match_compound_assignment()?, receiver)))
+        {
+            // `a[i] += e` evaluates `i` once, the variable `a` is read again to index it
+            self.emit_byte(Op::Dup.u8());
+            self.emit_variable(get_op, arg);
+            self.emit_byte(Op::Swap.u8());
+            self.emit_byte(Op::Index.u8());
+            self.expression();
+            self.emit_byte(op.u8());
+            self.emit_byte(Op::SetIndex.u8());
+        } else {
+            self.emit_byte(Op::Index.u8());
+        }
+    }
+
+    fn dot(&mut self, can_assign: bool) {
+        self.consume(TokenKind::Identifier, "Expect property name after '.'.");
+        let name = self.identifier_constant(self.previous_token().lexeme);
+
+        if can_assign && self.match_current(TokenKind::Equal) {
+            self.expression();
+            self.emit_constant_op(Op::SetProperty, name);
+        } else if let Some(op) = can_assign
+            .then(|| self.match_compound_assignment())
+            .flatten()
+        {
+            // `a.b += e` reads the property off a copy of the receiver, evaluating `a` once
+            self.emit_byte(Op::Dup.u8());
+            self.emit_constant_op(Op::GetProperty, name);
+            self.expression();
+            self.emit_byte(op.u8());
+            self.emit_constant_op(Op::SetProperty, name);
+        } else {
+            self.emit_constant_op(Op::GetProperty, name);
+        }
+    }
+
+    fn optional_dot(&mut self, _can_assign: bool) {
+        let nil_jump = self.emit_jump(Op::JumpIfNil.u8());
+        self.consume(TokenKind::Identifier, "Expect property name after '?.'.");
+        let name = self.identifier_constant(self.previous_token().lexeme);
+        self.emit_constant_op(Op::GetProperty, name);
+
+        // a nil receiver skips the rest of the chain too, so `a?.b.c()` is nil as a whole
+        while Precedence::Call as u8 <= self.find_rule(self.current_token().kind).precedence as u8 {
+            self.advance();
+            if let Some(infix) = self.find_rule(self.previous_token().kind).infix {
+                infix(self, false);
+            }
+        }
+        self.patch_jump(nil_jump);
+    }
+
     fn parse_precedence(&mut self, precedence: Precedence) {
         self.advance();
         let prefix_rule = self.find_rule(self.previous_token().kind).prefix;
@@ -218,7 +1103,7 @@ impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
         if let Some(rule) = prefix_rule {
             rule(self, can_assign);
         } else {
-            self.error_mut("Expected expression.");
+            self.error("Expected expression.");
             return;
         }
 
@@ -230,22 +1115,49 @@ impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
             }
         }
 
-        if can_assign && self.match_current(TokenKind::Equal) {
-            self.error_mut("Invalid assignment target.")
+        if can_assign
+            && (self.match_current(TokenKind::Equal) || self.match_compound_assignment().is_some())
+        {
+            self.error("Invalid assignment target.")
         }
     }
 
-    fn parse_variable(&mut self, error_msg: &str) -> u8 {
+    /// Consumes a compound assignment token, returning the arithmetic op it applies
+    fn match_compound_assignment(&mut self) -> Option<Op> {
+        let op = match self.current_token().kind {
+            TokenKind::PlusEqual => Op::Add,
+            TokenKind::MinusEqual => Op::Subtract,
+            TokenKind::StarEqual => Op::Multiply,
+            TokenKind::SlashEqual => Op::Divide,
+            TokenKind::PercentEqual => Op::Modulo,
+            _ => return None,
+        };
+        self.advance();
+        Some(op)
+    }
+
+    fn parse_variable(&mut self, error_msg: &str) -> u16 {
         self.consume(TokenKind::Identifier, error_msg);
         let name = self.previous.expect("No previous token!").lexeme;
         self.declare_variable();
         if self.current_compiler.scope_depth > 0 {
             return 0;
         }
-        self.identifier_constant(name)
+        self.global_slot(name)
+    }
+
+    fn global_slot(&mut self, name: &str) -> u16 {
+        let idx = self.interner.intern(name);
+        match self.globals.resolve(AloxString(idx)) {
+            Some(slot) => slot,
+            None => {
+                self.error("Too many global variables.");
+                0
+            }
+        }
     }
 
-    fn identifier_constant(&mut self, name: &str) -> u8 {
+    fn identifier_constant(&mut self, name: &str) -> usize {
         let idx = self.interner.intern(name);
         self.make_constant(Value::from_str_index(idx))
     }
@@ -257,16 +1169,16 @@ impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
 
         let name = self.previous.expect("No previous token!");
 
-        for local in self.current_compiler.locals.iter().rev() {
-            if local.depth != -1 && local.depth < self.current_compiler.scope_depth {
-                break;
-            }
-
-            if local.name.lexeme == name.lexeme {
-                let msg = format!("Already a variable with the name {}", name.lexeme);
-                self.had_error = true;
-                self.error(&msg);
-            }
+        let count = self.current_compiler.count;
+        let scope_depth = self.current_compiler.scope_depth;
+        let redeclared = self.current_compiler.locals[..count]
+            .iter()
+            .rev()
+            .take_while(|local| local.depth == -1 || local.depth >= scope_depth)
+            .any(|local| local.name.lexeme == name.lexeme);
+        if redeclared {
+            let msg = format!("Already a variable with the name {}", name.lexeme);
+            self.error(&msg);
         }
 
         self.add_local(name)
@@ -274,33 +1186,55 @@ impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
 
     fn add_local(&mut self, name: Token<'source>) {
         if self.current_compiler.count == U8_COUNT {
-            self.error_mut("Too many local variables in function!");
+            self.error("Too many local variables in function!");
             return;
         }
-        let local = Local { name, depth: -1 };
+        let local = Local {
+            name,
+            depth: -1,
+            is_captured: false,
+            is_const: false,
+        };
         let count = self.current_compiler.count;
         self.current_compiler.locals[count] = local;
         self.current_compiler.count += 1;
     }
 
-    fn define_variable(&mut self, global: u8) {
+    fn define_variable(&mut self, global: u16) {
         if self.current_compiler.scope_depth > 0 {
             self.mark_initialized();
             return;
         }
-        self.emit_bytes(Op::DefineGlobal.u8(), global)
+        self.emit_global(Op::DefineGlobal, global)
     }
 
     fn mark_initialized(&mut self) {
+        if self.current_compiler.scope_depth == 0 {
+            return;
+        }
         self.current_compiler.locals[self.current_compiler.count - 1].depth =
             self.current_compiler.scope_depth;
     }
 
     fn find_rule(&mut self, op_kind: TokenKind) -> ParseRule {
         match op_kind {
-            TokenKind::LeftParen => {
-                ParseRule::new(Some(|this, b| this.grouping(b)), None, Precedence::None)
+            TokenKind::LeftParen => ParseRule::new(
+                Some(|this, b| this.grouping(b)),
+                Some(|this, b| this.call(b)),
+                Precedence::Call,
+            ),
+            TokenKind::Dot => ParseRule::new(None, Some(|this, b| this.dot(b)), Precedence::Call),
+            TokenKind::QuestionDot => {
+                ParseRule::new(None, Some(|this, b| this.optional_dot(b)), Precedence::Call)
+            }
+            TokenKind::LeftBrace => {
+                ParseRule::new(Some(|this, b| this.map(b)), None, Precedence::None)
             }
+            TokenKind::LeftBracket => ParseRule::new(
+                Some(|this, b| this.list(b)),
+                Some(|this, b| this.index(b)),
+                Precedence::Call,
+            ),
             TokenKind::Minus => ParseRule::new(
                 Some(|this, b| this.unary(b)),
                 Some(|this, b| this.binary(b)),
@@ -309,12 +1243,37 @@ impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
             TokenKind::Plus => {
                 ParseRule::new(None, Some(|this, b| this.binary(b)), Precedence::Term)
             }
-            TokenKind::Slash => {
+            TokenKind::Slash | TokenKind::Percent => {
                 ParseRule::new(None, Some(|this, b| this.binary(b)), Precedence::Factor)
             }
             TokenKind::Star => {
                 ParseRule::new(None, Some(|this, b| this.binary(b)), Precedence::Factor)
             }
+            TokenKind::StarStar => {
+                ParseRule::new(None, Some(|this, b| this.binary(b)), Precedence::Power)
+            }
+            TokenKind::Pipe => {
+                ParseRule::new(None, Some(|this, b| this.binary(b)), Precedence::BitOr)
+            }
+            TokenKind::Caret => {
+                ParseRule::new(None, Some(|this, b| this.binary(b)), Precedence::BitXor)
+            }
+            TokenKind::Ampersand => {
+                ParseRule::new(None, Some(|this, b| this.binary(b)), Precedence::BitAnd)
+            }
+            TokenKind::DotDotDot => ParseRule::none(),
+            TokenKind::Is => {
+                ParseRule::new(None, Some(|this, b| this.is(b)), Precedence::Comparison)
+            }
+            TokenKind::DotDot | TokenKind::DotDotEqual => {
+                ParseRule::new(None, Some(|this, b| this.range(b)), Precedence::Range)
+            }
+            TokenKind::LessLess | TokenKind::GreaterGreater => {
+                ParseRule::new(None, Some(|this, b| this.binary(b)), Precedence::Shift)
+            }
+            TokenKind::Tilde => {
+                ParseRule::new(Some(|this, b| this.unary(b)), None, Precedence::None)
+            }
             TokenKind::Bang => {
                 ParseRule::new(Some(|this, b| this.unary(b)), None, Precedence::None)
             }
@@ -342,9 +1301,17 @@ impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
             TokenKind::String => {
                 ParseRule::new(Some(|this, b| this.string(b)), None, Precedence::None)
             }
+            TokenKind::Interpolation => ParseRule::new(
+                Some(|this, b| this.interpolation(b)),
+                None,
+                Precedence::None,
+            ),
             TokenKind::Number => {
                 ParseRule::new(Some(|this, b| this.number(b)), None, Precedence::None)
             }
+            TokenKind::Integer => {
+                ParseRule::new(Some(|this, b| this.integer(b)), None, Precedence::None)
+            }
             TokenKind::False => {
                 ParseRule::new(Some(|this, b| this.literal(b)), None, Precedence::None)
             }
@@ -354,31 +1321,62 @@ impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
             TokenKind::True => {
                 ParseRule::new(Some(|this, b| this.literal(b)), None, Precedence::None)
             }
+            TokenKind::This => ParseRule::new(Some(|this, b| this.this(b)), None, Precedence::None),
+            TokenKind::Fun => {
+                ParseRule::new(Some(|this, b| this.lambda(b)), None, Precedence::None)
+            }
+            TokenKind::Yield => {
+                ParseRule::new(Some(|this, b| this.yield_(b)), None, Precedence::None)
+            }
+            TokenKind::And => ParseRule::new(None, Some(|this, b| this.and(b)), Precedence::And),
+            TokenKind::Or => ParseRule::new(None, Some(|this, b| this.or(b)), Precedence::Or),
+            TokenKind::QuestionQuestion => {
+                ParseRule::new(None, Some(|this, b| this.coalesce(b)), Precedence::Coalesce)
+            }
             TokenKind::RightParen
-            | TokenKind::LeftBrace
             | TokenKind::RightBrace
+            | TokenKind::Colon
+            | TokenKind::RightBracket
             | TokenKind::Comma
-            | TokenKind::Dot
             | TokenKind::Semicolon
             | TokenKind::Equal
+            | TokenKind::PlusEqual
+            | TokenKind::MinusEqual
+            | TokenKind::StarEqual
+            | TokenKind::SlashEqual
+            | TokenKind::PercentEqual
             | TokenKind::Var
             | TokenKind::While
             | TokenKind::Print
             | TokenKind::Eof
             | TokenKind::Error
-            | TokenKind::And
+            | TokenKind::Assert
+            | TokenKind::Break
+            | TokenKind::Catch
+            | TokenKind::Throw
+            | TokenKind::Try
+            | TokenKind::Continue
+            | TokenKind::Do
             | TokenKind::Class
+            | TokenKind::Const
             | TokenKind::Else
-            | TokenKind::Fun
             | TokenKind::For
             | TokenKind::If
-            | TokenKind::Or
+            | TokenKind::Import
+            | TokenKind::In
             | TokenKind::Return
-            | TokenKind::Super
-            | TokenKind::This => ParseRule::none(),
+            | TokenKind::Super => ParseRule::none(),
         }
     }
 
+    fn this(&mut self, _can_assign: bool) {
+        if self.class_depth == 0 {
+            self.error("Can't use 'this' outside of a class.");
+            return;
+        }
+        self.variable(false);
+    }
+
     fn variable(&mut self, can_assign: bool) {
         let previous = self.previous.expect("No previous token!").lexeme;
         self.named_variable(previous, can_assign);
@@ -387,38 +1385,88 @@ impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
     fn named_variable(&mut self, name: &str, can_assign: bool) {
         let set_op;
         let get_op;
+        let mut is_const = false;
         let mut arg = self.resolve_local(name);
         if arg != -1 {
-            set_op = Op::SetLocal.u8();
-            get_op = Op::GetLocal.u8();
+            is_const = self.current_compiler.locals[arg as usize].is_const;
+            set_op = Op::SetLocal;
+            get_op = Op::GetLocal;
+        } else if let Some(upvalue) = self.resolve_upvalue(name) {
+            is_const = self.current_compiler.upvalues[upvalue as usize].is_const;
+            arg = upvalue as i32;
+            set_op = Op::SetUpvalue;
+            get_op = Op::GetUpvalue;
         } else {
-            arg = self.identifier_constant(name) as i32;
-            set_op = Op::SetGlobal.u8();
-            get_op = Op::GetGlobal.u8();
+            arg = self.global_slot(name) as i32;
+            set_op = Op::SetGlobal;
+            get_op = Op::GetGlobal;
+        }
+        let assigns = can_assign
+            && matches!(
+                self.current_token().kind,
+                TokenKind::Equal
+                    | TokenKind::PlusEqual
+                    | TokenKind::MinusEqual
+                    | TokenKind::StarEqual
+                    | TokenKind::SlashEqual
+                    | TokenKind::PercentEqual
+            );
+        if assigns && is_const {
+            let msg = format!("Can't assign to constant '{}'.", name);
+            self.error_at_current(&msg);
         }
+
         if can_assign && self.match_current(TokenKind::Equal) {
             self.expression();
-            self.emit_bytes(set_op, arg as u8);
+            self.emit_variable(set_op, arg);
+        } else if let Some(op) = can_assign
+            .then(|| self.match_compound_assignment())
+            .flatten()
+        {
+            // `x += e` compiles as `x = x + e`
+            self.emit_variable(get_op, arg);
+            self.expression();
+            self.emit_byte(op.u8());
+            self.emit_variable(set_op, arg);
         } else {
-            self.emit_bytes(get_op, arg as u8);
+            self.emit_variable(get_op, arg);
+            let end = self.current_chunk().code.len();
+            self.current_compiler.last_read = Some((end, get_op, arg));
+        }
+    }
+
+    /// Emits a variable access, globals take a 16-bit slot where locals and upvalues take a byte
+    fn emit_variable(&mut self, op: Op, arg: i32) {
+        match op {
+            Op::GetGlobal | Op::SetGlobal => self.emit_global(op, arg as u16),
+            _ => self.emit_bytes(op.u8(), arg as u8),
         }
     }
 
     fn resolve_local(&mut self, name: &str) -> i32 {
-        for (i, local) in self.current_compiler.locals.iter().enumerate().rev() {
-            if name == local.name.lexeme {
-                if local.depth == -1 {
-                    self.had_error = true;
+        match self.current_compiler.resolve_local(name) {
+            Some((slot, initialized)) => {
+                if !initialized {
                     let msg = format!(
                         "Can't read local variable '{}' in its own initializer!",
                         &name
                     );
                     self.error(&msg);
                 }
-                return i as i32;
+                slot as i32
+            }
+            None => -1,
+        }
+    }
+
+    fn resolve_upvalue(&mut self, name: &str) -> Option<u8> {
+        match self.current_compiler.resolve_upvalue(name) {
+            Ok(upvalue) => upvalue,
+            Err(msg) => {
+                self.error(msg);
+                None
             }
         }
-        -1
     }
 
     fn literal(&mut self, _can_assign: bool) {
@@ -431,28 +1479,48 @@ impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
     }
 
     fn string(&mut self, _can_assign: bool) {
-        let string = {
-            let string = self.previous_token();
-            let string_len = string.lexeme.len();
-            let string = &string.lexeme[1..string_len - 1];
-            if self.interner.exists(string) {
-                Ok(string)
-            } else {
-                Err(String::from(string))
-            }
-        };
+        // the leading character is either the opening quote or the `}` closing an interpolation
+        let lexeme = self.previous_token().lexeme;
+        self.string_segment(&lexeme[1..lexeme.len() - 1]);
+    }
 
-        let val = match string {
-            Ok(existing) => {
-                let idx = self.interner.get_existing(existing);
-                Value::from_str_index(idx)
+    fn interpolation(&mut self, _can_assign: bool) {
+        let mut parts: usize = 0;
+        loop {
+            let lexeme = self.previous_token().lexeme;
+            self.string_segment(&lexeme[1..lexeme.len() - 2]);
+            self.expression();
+            parts += 2;
+            if !self.match_current(TokenKind::Interpolation) {
+                break;
             }
-            Err(new_string) => {
-                let idx = self.interner.intern(&new_string);
-                Value::from_str_index(idx)
+        }
+        if self.match_current(TokenKind::String) {
+            self.string(false);
+        } else {
+            self.error_at_current("Expect end of string interpolation.");
+        }
+        parts += 1;
+
+        if parts > u8::MAX as usize {
+            self.error("Too many interpolated expressions in one string.");
+        }
+        self.emit_bytes(Op::BuildString.u8(), parts as u8);
+    }
+
+    fn string_segment(&mut self, string: &str) {
+        let idx = if string.contains('\\') {
+            match unescape(string) {
+                Ok(unescaped) => self.interner.intern(&unescaped),
+                Err(msg) => {
+                    self.error(&msg);
+                    return;
+                }
             }
+        } else {
+            self.interner.intern(string)
         };
-        self.emit_constant(val);
+        self.emit_constant(Value::from_str_index(idx));
     }
 
     fn consume(&mut self, token_kind: TokenKind, error_msg: &str) {
@@ -465,14 +1533,40 @@ impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
         self.error_at_current(error_msg);
     }
 
+    /// Compiles a parenthesized expression, or a tuple when the parentheses hold a comma
+    /// or nothing at all
     fn grouping(&mut self, _can_assign: bool) {
+        if self.match_current(TokenKind::RightParen) {
+            self.emit_bytes(Op::BuildTuple.u8(), 0);
+            return;
+        }
         self.expression();
-        self.consume(TokenKind::RightParen, "Expect ')' after expression.")
+        if !self.match_current(TokenKind::Comma) {
+            self.consume(TokenKind::RightParen, "Expect ')' after expression.");
+            return;
+        }
+        let mut item_count: usize = 1;
+        while !self.check(TokenKind::RightParen) {
+            self.expression();
+            if item_count == 255 {
+                self.error("Can't have more than 255 items in a tuple.");
+            }
+            item_count += 1;
+            if !self.match_current(TokenKind::Comma) {
+                break;
+            }
+        }
+        self.consume(TokenKind::RightParen, "Expect ')' after tuple items.");
+        self.emit_bytes(Op::BuildTuple.u8(), item_count as u8);
+    }
+
+    fn current_chunk(&mut self) -> &mut Chunk {
+        &mut self.current_compiler.function.chunk
     }
 
     fn emit_byte(&mut self, byte: u8) {
-        self.current_chunk
-            .write(byte, self.previous.as_ref().unwrap().line)
+        let line = self.previous.as_ref().unwrap().line;
+        self.current_chunk().write(byte, line)
     }
 
     fn emit_bytes(&mut self, byte1: u8, byte2: u8) {
@@ -480,27 +1574,101 @@ impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
         self.emit_byte(byte2)
     }
 
+    fn emit_global(&mut self, op: Op, slot: u16) {
+        let [high, low] = slot.to_be_bytes();
+        self.emit_byte(op.u8());
+        self.emit_bytes(high, low);
+    }
+
+    fn emit_jump(&mut self, instruction: u8) -> usize {
+        self.emit_byte(instruction);
+        self.emit_bytes(0xff, 0xff);
+        self.current_chunk().code.len() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize) {
+        // -2 to adjust for the bytecode of the jump offset itself
+        let jump = self.current_chunk().code.len() - offset - 2;
+        if jump > u16::MAX as usize {
+            self.error("Too much code to jump over.");
+        }
+        let [hi, lo] = (jump as u16).to_be_bytes();
+        self.current_chunk().code[offset] = hi;
+        self.current_chunk().code[offset + 1] = lo;
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        self.emit_backward_jump(Op::Loop.u8(), loop_start);
+    }
+
+    fn emit_backward_jump(&mut self, instruction: u8, loop_start: usize) {
+        self.emit_byte(instruction);
+        let offset = self.current_chunk().code.len() - loop_start + 2;
+        if offset > u16::MAX as usize {
+            self.error("Loop body too large.");
+        }
+        let [hi, lo] = (offset as u16).to_be_bytes();
+        self.emit_bytes(hi, lo);
+    }
+
     fn emit_return(&mut self) {
+        match self.current_compiler.kind {
+            FunctionKind::Initializer => self.emit_bytes(Op::GetLocal.u8(), 0),
+            // assigning through a setter still evaluates to the assigned value
+            FunctionKind::Setter => self.emit_bytes(Op::GetLocal.u8(), 1),
+            _ => self.emit_byte(Op::Nil.u8()),
+        }
         self.emit_byte(Op::Return.u8())
     }
 
-    fn end_compiler(&mut self) {
+    fn end_compiler(&mut self) -> (ObjFunction, Vec<UpvalueRef>) {
         self.emit_return();
+        self.current_chunk().fuse_superinstructions();
+        #[cfg(feature = "print-code")]
         if !self.had_error {
-            self.current_chunk.disassemble("code", self.interner)
+            let name = match self.current_compiler.function.name {
+                Some(name) => self.interner.lookup(name.0),
+                None => "<script>",
+            };
+            print!(
+                "{}",
+                self.current_compiler
+                    .function
+                    .chunk
+                    .disassemble(name, self.interner)
+            );
         }
+        let enclosing = self.current_compiler.enclosing.take();
+        let compiler = match enclosing {
+            Some(enclosing) => std::mem::replace(&mut self.current_compiler, *enclosing),
+            None => std::mem::take(&mut self.current_compiler),
+        };
+        (compiler.function, compiler.upvalues)
     }
 
+    /// Pushes a constant
     fn emit_constant(&mut self, val: Value) {
-        let konst = self.make_constant(val);
-        self.emit_bytes(Op::Constant.u8(), konst)
+        let index = self.make_constant(val);
+        self.emit_constant_op(Op::Constant, index);
     }
 
-    fn make_constant(&mut self, val: Value) -> u8 {
-        let constant_idx = self.current_chunk.add_constant(val);
-        constant_idx
-            .try_into()
-            .expect("too many constants in one chunk")
+    fn make_constant(&mut self, val: Value) -> usize {
+        self.current_chunk().add_constant(val)
+    }
+
+    /// Emits `op` taking constant `index`, switching to its long form and a 24-bit index past
+    /// the first 256 constants
+    fn emit_constant_op(&mut self, op: Op, index: usize) {
+        if let Ok(index) = u8::try_from(index) {
+            self.emit_bytes(op.u8(), index);
+        } else if let (Some(long), true) = (op.long_form(), index < 1 << 24) {
+            self.emit_byte(long.u8());
+            for byte in &index.to_le_bytes()[..3] {
+                self.emit_byte(*byte);
+            }
+        } else {
+            self.error("Too many constants in one chunk.");
+        }
     }
 
     fn synchronize(&mut self) {
@@ -516,10 +1684,15 @@ impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
                     TokenKind::Class
                     | TokenKind::Fun
                     | TokenKind::Var
+                    | TokenKind::Const
                     | TokenKind::For
                     | TokenKind::If
                     | TokenKind::While
                     | TokenKind::Print
+                    | TokenKind::Assert
+                    | TokenKind::Import
+                    | TokenKind::Throw
+                    | TokenKind::Try
                     | TokenKind::Return => {
                         return;
                     }
@@ -533,59 +1706,80 @@ impl<'source, 'chunk, 'interner> Parser<'source, 'chunk, 'interner> {
         }
     }
 
-    /// Should set `self.had_error` to true before using this
-    fn error(&self, message: &str) {
-        self.error_at(self.previous, message);
-    }
-
-    fn error_mut(&mut self, message: &str) {
-        self.had_error = true;
+    fn error(&mut self, message: &str) {
         self.error_at(self.previous, message);
     }
 
     fn error_at_current(&mut self, message: &str) {
-        self.had_error = true;
         self.error_at(self.current, message);
     }
 
-    fn error_at(&self, token: Option<Token>, message: &str) {
+    /// Records an error, unless one was already reported for the statement being parsed
+    fn error_at(&mut self, token: Option<Token>, message: &str) {
+        self.had_error = true;
         if self.panic_mode {
             return;
         }
-        if let Some(token) = token {
-            eprint!("[line {}] Error", token.line);
-            match token.kind {
-                TokenKind::Eof => eprint!(" at end"),
-                TokenKind::Error => {}
-                _ => eprint!(" at '{}' ", token.lexeme),
-            }
-            if !message.is_empty() {
-                eprintln!(": {}", message);
-            } else {
-                eprintln!();
-            }
-        } else {
-            eprintln!("Parser error.");
-        }
+        // errors after the first are usually caused by it, so wait for the next statement
+        self.panic_mode = true;
+        let token = token.unwrap_or_default();
+        let lexeme = match token.kind {
+            TokenKind::Eof => Some(String::new()),
+            // error tokens carry their message rather than source text
+            TokenKind::Error => None,
+            _ => Some(token.lexeme.to_string()),
+        };
+        self.diagnostics.push(CompileDiagnostic {
+            message: message.to_string(),
+            line: token.line,
+            column: token.column,
+            lexeme,
+        });
     }
 }
 
-#[derive(Debug)]
-pub enum CompilationError {
-    Error,
+/// An error found while compiling, with where in the source it was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileDiagnostic {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+    /// Source text of the offending token, empty at the end of the input and `None` for
+    /// errors the scanner found
+    pub lexeme: Option<String>,
+}
+
+impl Display for CompileDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[line {}] Error", self.line)?;
+        match self.lexeme.as_deref() {
+            Some("") => write!(f, " at end")?,
+            Some(lexeme) => write!(f, " at '{}' ", lexeme)?,
+            None => {}
+        }
+        write!(f, ": {}", self.message)
+    }
 }
 #[repr(u8)]
 #[derive(Clone, Copy, Debug)]
+#[allow(dead_code)] // some levels are only reached through `From<u8>`
 enum Precedence {
     None = 0,
     Assignment, // =
+    Coalesce,   // ??
     Or,         // or
     And,        // and
     Equality,   // == !=
     Comparison, // < > <= >=
+    Range,      // .. ..=
+    BitOr,      // |
+    BitXor,     // ^
+    BitAnd,     // &
+    Shift,      // << >>
     Term,       // + -
     Factor,     // * /
     Unary,      // ! -
+    Power,      // **
     Call,       // . ()
     Primary,
 }
@@ -616,8 +1810,46 @@ impl ParseRule {
     }
 }
 
+/// Resolves backslash escapes in the body of a string literal
+fn unescape(raw: &str) -> Result<String, String> {
+    let mut unescaped = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('r') => unescaped.push('\r'),
+            Some('0') => unescaped.push('\0'),
+            Some('\\') => unescaped.push('\\'),
+            Some('"') => unescaped.push('"'),
+            Some('$') => unescaped.push('$'),
+            Some('u') => {
+                if chars.next() != Some('{') {
+                    return Err("Expect '{' after '\\u'.".to_string());
+                }
+                let digits: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                let code_point = u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .filter(|_| (1..=6).contains(&digits.len()))
+                    .and_then(char::from_u32);
+                match code_point {
+                    Some(c) => unescaped.push(c),
+                    None => return Err(format!("Invalid unicode escape '\\u{{{}}}'.", digits)),
+                }
+            }
+            Some(other) => return Err(format!("Invalid escape sequence '\\{}'.", other)),
+            None => return Err("Unterminated escape sequence.".to_string()),
+        }
+    }
+    Ok(unescaped)
+}
+
 impl From<u8> for Precedence {
     fn from(byte: u8) -> Self {
-        unsafe { core::mem::transmute(byte) }
+        unsafe { core::mem::transmute::<u8, Precedence>(byte) }
     }
 }
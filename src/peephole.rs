@@ -0,0 +1,82 @@
+use ahash::AHashSet;
+
+use crate::{
+    chunk::{push_line, Chunk},
+    opcodes::Op,
+};
+
+impl Chunk {
+    /// Rewrites `GetLocal` led sequences such as `GetLocal, Constant, Add` into single
+    /// superinstructions, relocating every jump around the shortened code
+    pub fn fuse_superinstructions(&mut self) {
+        let mut starts = Vec::new();
+        let mut offset = 0;
+        while offset < self.code.len() {
+            starts.push(offset);
+            offset += self.instruction_len(offset);
+        }
+        // fusing over a jump target would leave the jump landing mid-instruction
+        let targets: AHashSet<usize> = starts.iter().filter_map(|&o| self.jump_target(o)).collect();
+
+        let old_lines: Vec<usize> = self
+            .lines
+            .iter()
+            .flat_map(|&(line, count)| std::iter::repeat_n(line, count))
+            .collect();
+        let mut code = Vec::with_capacity(self.code.len());
+        let mut lines = Vec::with_capacity(self.lines.len());
+        // new offset of every old instruction start, and of the end of the chunk
+        let mut relocated = vec![0; self.code.len() + 1];
+        let mut jumps = Vec::new();
+        let mut i = 0;
+        while i < starts.len() {
+            let offset = starts[i];
+            relocated[offset] = code.len();
+            if let Some(fused) = self.fusable(&starts[i..], &targets) {
+                let second = starts[i + 1];
+                code.extend_from_slice(&[fused.u8(), self.code[offset + 1], self.code[second + 1]]);
+                push_line(&mut lines, old_lines[offset], 3);
+                i += 3;
+                continue;
+            }
+            if let Some(target) = self.jump_target(offset) {
+                jumps.push((code.len(), target));
+            }
+            let end = starts.get(i + 1).copied().unwrap_or(self.code.len());
+            code.extend_from_slice(&self.code[offset..end]);
+            for &line in &old_lines[offset..end] {
+                push_line(&mut lines, line, 1);
+            }
+            i += 1;
+        }
+        relocated[self.code.len()] = code.len();
+
+        for (offset, target) in jumps {
+            let op = Op::from_u8(code[offset]).expect("jump opcode");
+            let end = offset + 1 + op.operand_len();
+            let target = relocated[target];
+            let jump = target.abs_diff(end);
+            code[end - 2..end].copy_from_slice(&(jump as u16).to_be_bytes());
+        }
+        self.code = code;
+        self.lines = lines;
+    }
+
+    /// The superinstruction for the three instructions at `starts`, if they form one
+    fn fusable(&self, starts: &[usize], targets: &AHashSet<usize>) -> Option<Op> {
+        let (first, second, third) = match starts {
+            [first, second, third, ..] => (*first, *second, *third),
+            _ => return None,
+        };
+        if targets.contains(&second) || targets.contains(&third) {
+            return None;
+        }
+        if Op::from_u8(self.code[first])? != Op::GetLocal {
+            return None;
+        }
+        Op::fuse(
+            Op::from_u8(self.code[second])?,
+            Op::from_u8(self.code[third])?,
+        )
+    }
+}
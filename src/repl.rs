@@ -1,20 +1,111 @@
-use rustyline::{error::ReadlineError, Editor};
+use std::{borrow::Cow, env, path::PathBuf, process, time::Instant};
 
-use crate::run_script;
+use rustyline::{
+    completion::Completer, error::ReadlineError, highlight::Highlighter, hint::Hinter,
+    validate::Validator, Context, Editor, Helper,
+};
 
-pub fn run_prompt() {
-    let mut rl = Editor::<()>::new();
+use crate::{
+    chunk::Chunk,
+    globals::Globals,
+    interner::Interner,
+    scanner::{is_incomplete, Scanner, KEYWORDS},
+    token::TokenKind,
+    vm::{InterpreterError, Vm},
+    AloxError,
+};
+
+/// Name of the file in the home directory keeping entered lines between sessions
+const HISTORY_FILE: &str = ".alox_history";
+/// Prompt while the input so far leaves a string or bracket open
+const CONTINUATION_PROMPT: &str = ".. ";
+
+/// How the REPL behaves, `default` for plain evaluation
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReplOptions {
+    /// Reports how long each entry ran and how many instructions it took
+    pub timing: bool,
+    /// Leaves input and errors uncolored, as does setting `NO_COLOR`
+    pub no_color: bool,
+}
+
+const KEYWORD_COLOR: &str = "\x1b[35m";
+const NUMBER_COLOR: &str = "\x1b[36m";
+const STRING_COLOR: &str = "\x1b[32m";
+const ERROR_COLOR: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+
+/// Reads and runs lines until Ctrl-D. Ctrl-C only drops the line being typed. Every line runs
+/// in the same VM, so its globals stay defined for the following ones, and a line ending in a
+/// bare expression prints its value. Input leaving a string or bracket open continues on the
+/// next line
+pub fn run_prompt(options: ReplOptions) {
+    let mut vm = Vm::new(Chunk::init(), Interner::new(), Globals::new());
+    let color = !options.no_color && env::var_os("NO_COLOR").is_none();
+    let mut rl = Editor::<ReplHelper>::new();
+    rl.set_helper(Some(ReplHelper {
+        globals: Vec::new(),
+        color,
+    }));
+    let history = history_path();
+    if let Some(path) = &history {
+        // there's no history yet the first time
+        let _ = rl.load_history(path);
+    }
+    let mut input = String::new();
     loop {
-        let readline = rl.readline(">> ");
+        let prompt = if input.is_empty() {
+            ">> "
+        } else {
+            CONTINUATION_PROMPT
+        };
+        let readline = rl.readline(prompt);
         match readline {
             Ok(line) => {
+                if !input.is_empty() {
+                    input.push('\n');
+                }
+                input.push_str(&line);
+                if is_incomplete(&input) {
+                    continue;
+                }
+                let line = std::mem::take(&mut input);
                 rl.add_history_entry(line.as_str());
-                run_script(&line);
-            }
-            Err(ReadlineError::Interrupted) => {
-                println!("CTRL-C");
-                break;
+                if let Some(path) = &history {
+                    if let Err(err) = rl.append_history(path) {
+                        eprintln!("Could not save history: {}", err);
+                    }
+                }
+                if options.timing {
+                    // counting down from the top measures instructions without limiting them
+                    vm.set_fuel(Some(u64::MAX));
+                }
+                let start = Instant::now();
+                let result = vm.interpret_line(&line);
+                if let Err(AloxError::Runtime {
+                    error: InterpreterError::Exit(code),
+                    ..
+                }) = result
+                {
+                    process::exit(code);
+                }
+                if let Err(err) = result {
+                    if color {
+                        eprintln!("{}{}{}", ERROR_COLOR, err, RESET);
+                    } else {
+                        eprintln!("{}", err);
+                    }
+                }
+                if options.timing {
+                    let instructions = u64::MAX - vm.fuel().unwrap_or(u64::MAX);
+                    vm.set_fuel(None);
+                    eprintln!("({:.3?}, {} instructions)", start.elapsed(), instructions);
+                }
+                if let Some(helper) = rl.helper_mut() {
+                    helper.globals = vm.global_names().map(str::to_string).collect();
+                }
             }
+            Err(ReadlineError::Interrupted) => input.clear(),
             Err(ReadlineError::Eof) => {
                 println!("CTRL-D");
                 break;
@@ -26,3 +117,105 @@ pub fn run_prompt() {
         }
     }
 }
+
+/// Completes keywords and the names of globals defined so far, and colors the line being typed
+struct ReplHelper {
+    /// Refreshed after every line the VM runs
+    globals: Vec<String>,
+    color: bool,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos]
+            .rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .map_or(0, |i| i + 1);
+        let prefix = &line[start..pos];
+        if prefix.is_empty() || prefix.starts_with(|c: char| c.is_ascii_digit()) {
+            return Ok((pos, Vec::new()));
+        }
+        let mut candidates: Vec<String> = KEYWORDS
+            .iter()
+            .copied()
+            .chain(self.globals.iter().map(String::as_str))
+            .filter(|name| name.starts_with(prefix))
+            .map(str::to_string)
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {
+    /// Colors keywords, numbers and strings by the tokens the scanner finds
+    fn highlight<'l>(&self, line: &'l str, _: usize) -> Cow<'l, str> {
+        if !self.color {
+            return Cow::Borrowed(line);
+        }
+        let mut highlighted = String::with_capacity(line.len() * 2);
+        let mut scanner = Scanner::new(line);
+        // end of the text copied so far, what lies between tokens is copied as it is
+        let mut copied = 0;
+        loop {
+            let token = scanner.scan_token();
+            let color = match token.kind {
+                TokenKind::Eof => break,
+                TokenKind::Error => {
+                    // an error's lexeme is its message, so the rest of the line is left as is
+                    if token.lexeme == "Unterminated string." {
+                        let open = line[copied..].find('"').map_or(copied, |i| copied + i);
+                        highlighted.push_str(&line[copied..open]);
+                        highlighted.push_str(STRING_COLOR);
+                        highlighted.push_str(&line[open..]);
+                        highlighted.push_str(RESET);
+                        copied = line.len();
+                    }
+                    break;
+                }
+                TokenKind::Number | TokenKind::Integer => Some(NUMBER_COLOR),
+                TokenKind::String | TokenKind::Interpolation => Some(STRING_COLOR),
+                _ if KEYWORDS.binary_search(&token.lexeme).is_ok() => Some(KEYWORD_COLOR),
+                _ => None,
+            };
+            let start = token.lexeme.as_ptr() as usize - line.as_ptr() as usize;
+            let end = start + token.lexeme.len();
+            highlighted.push_str(&line[copied..start]);
+            match color {
+                Some(color) => {
+                    highlighted.push_str(color);
+                    highlighted.push_str(token.lexeme);
+                    highlighted.push_str(RESET);
+                }
+                None => highlighted.push_str(token.lexeme),
+            }
+            copied = end;
+        }
+        highlighted.push_str(&line[copied..]);
+        Cow::Owned(highlighted)
+    }
+
+    fn highlight_char(&self, _: &str, _: usize) -> bool {
+        self.color
+    }
+}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+fn history_path() -> Option<PathBuf> {
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?;
+    Some(PathBuf::from(home).join(HISTORY_FILE))
+}
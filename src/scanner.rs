@@ -1,10 +1,24 @@
 use crate::token::{Token, TokenKind};
 
+/// Every reserved word, in alphabetical order
+pub const KEYWORDS: [&str; 28] = [
+    "and", "assert", "break", "catch", "class", "const", "continue", "do", "else", "false", "for",
+    "fun", "if", "import", "in", "is", "nil", "or", "print", "return", "super", "this", "throw",
+    "true", "try", "var", "while", "yield",
+];
+
+#[derive(Clone)]
 pub struct Scanner<'a> {
     source: &'a str,
     start: usize,
     current: usize,
     line: usize,
+    /// Offset where the current line begins
+    line_start: usize,
+    /// Column the token being scanned starts at
+    column: usize,
+    /// Unmatched `{` count for each string interpolation currently open
+    interpolations: Vec<usize>,
 }
 
 impl<'source> Scanner<'source> {
@@ -14,9 +28,16 @@ impl<'source> Scanner<'source> {
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            column: 1,
+            interpolations: Vec::new(),
         }
     }
 
+    pub fn source(&self) -> &'source str {
+        self.source
+    }
+
     pub fn scan(&mut self) {
         loop {
             let line = 0;
@@ -37,6 +58,7 @@ impl<'source> Scanner<'source> {
     pub fn scan_token(&mut self) -> Token<'source> {
         self.skip_whitespace();
         self.start = self.current;
+        self.column = self.start - self.line_start + 1;
 
         if self.is_at_end() {
             return self.make_token(TokenKind::Eof);
@@ -55,21 +77,74 @@ impl<'source> Scanner<'source> {
         match char {
             b'(' => self.make_token(TokenKind::LeftParen),
             b')' => self.make_token(TokenKind::RightParen),
-            b'{' => self.make_token(TokenKind::LeftBrace),
-            b'}' => self.make_token(TokenKind::RightBrace),
+            b'{' => {
+                if let Some(depth) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                self.make_token(TokenKind::LeftBrace)
+            }
+            b'}' => match self.interpolations.last_mut() {
+                Some(0) => {
+                    // closes the interpolated expression, so resume the string
+                    self.interpolations.pop();
+                    self.string()
+                }
+                Some(depth) => {
+                    *depth -= 1;
+                    self.make_token(TokenKind::RightBrace)
+                }
+                None => self.make_token(TokenKind::RightBrace),
+            },
+            b'[' => self.make_token(TokenKind::LeftBracket),
+            b']' => self.make_token(TokenKind::RightBracket),
             b';' => self.make_token(TokenKind::Semicolon),
             b',' => self.make_token(TokenKind::Comma),
-            b'.' => self.make_token(TokenKind::Dot),
-            b'-' => self.make_token(TokenKind::Minus),
-            b'+' => self.make_token(TokenKind::Plus),
-            b'/' => self.make_token(TokenKind::Slash),
-            b'*' => self.make_token(TokenKind::Star),
+            b':' => self.make_token(TokenKind::Colon),
+            b'?' if self.match_next(b'?') => self.make_token(TokenKind::QuestionQuestion),
+            b'?' if self.match_next(b'.') => self.make_token(TokenKind::QuestionDot),
+            b'.' => {
+                if self.match_next(b'.') {
+                    if self.match_next(b'.') {
+                        return self.make_token(TokenKind::DotDotDot);
+                    }
+                    self.match_next_token(b'=', TokenKind::DotDotEqual, TokenKind::DotDot)
+                } else {
+                    self.make_token(TokenKind::Dot)
+                }
+            }
+            b'-' => self.match_next_token(b'=', TokenKind::MinusEqual, TokenKind::Minus),
+            b'+' => self.match_next_token(b'=', TokenKind::PlusEqual, TokenKind::Plus),
+            b'/' => self.match_next_token(b'=', TokenKind::SlashEqual, TokenKind::Slash),
+            b'%' => self.match_next_token(b'=', TokenKind::PercentEqual, TokenKind::Percent),
+            b'*' => {
+                if self.match_next(b'*') {
+                    self.make_token(TokenKind::StarStar)
+                } else {
+                    self.match_next_token(b'=', TokenKind::StarEqual, TokenKind::Star)
+                }
+            }
             b'!' => self.match_next_token(b'=', TokenKind::BangEqual, TokenKind::Bang),
             b'=' => self.match_next_token(b'=', TokenKind::EqualEqual, TokenKind::Equal),
-            b'<' => self.match_next_token(b'=', TokenKind::LessEqual, TokenKind::Less),
-            b'>' => self.match_next_token(b'=', TokenKind::GreaterEqual, TokenKind::Greater),
+            b'<' => {
+                if self.match_next(b'<') {
+                    self.make_token(TokenKind::LessLess)
+                } else {
+                    self.match_next_token(b'=', TokenKind::LessEqual, TokenKind::Less)
+                }
+            }
+            b'>' => {
+                if self.match_next(b'>') {
+                    self.make_token(TokenKind::GreaterGreater)
+                } else {
+                    self.match_next_token(b'=', TokenKind::GreaterEqual, TokenKind::Greater)
+                }
+            }
+            b'&' => self.make_token(TokenKind::Ampersand),
+            b'|' => self.make_token(TokenKind::Pipe),
+            b'^' => self.make_token(TokenKind::Caret),
+            b'~' => self.make_token(TokenKind::Tilde),
             b'"' => self.string(),
-            _ => Token::error("Unexpected character.", self.line),
+            _ => Token::error("Unexpected character.", self.line, self.column),
         }
     }
 
@@ -79,6 +154,7 @@ impl<'source> Scanner<'source> {
             if char == b'\n' {
                 self.line += 1;
                 self.advance();
+                self.line_start = self.current;
             } else if char == b'/' {
                 if self.peek_next() == b'/' {
                     while self.peek() != b'\n' && !self.is_at_end() {
@@ -104,10 +180,14 @@ impl<'source> Scanner<'source> {
     }
 
     fn peek_next(&self) -> u8 {
-        if self.is_at_end() || self.current + 1 >= self.source.len() {
+        self.peek_at(1)
+    }
+
+    fn peek_at(&self, distance: usize) -> u8 {
+        if self.is_at_end() || self.current + distance >= self.source.len() {
             return b'\0';
         }
-        self.source.as_bytes()[self.current + 1]
+        self.source.as_bytes()[self.current + distance]
     }
 
     fn advance(&mut self) -> u8 {
@@ -147,6 +227,7 @@ impl<'source> Scanner<'source> {
             self.start,
             self.current_token_length(),
             self.line,
+            self.column,
         )
     }
 
@@ -159,10 +240,31 @@ impl<'source> Scanner<'source> {
 
     fn identifier_kind(&self) -> TokenKind {
         match self.source.as_bytes()[self.start] {
-            b'a' => self.check_keyword(1, 2, "nd", TokenKind::And),
-            b'c' => self.check_keyword(1, 4, "lass", TokenKind::Class),
+            b'a' => {
+                if self.current_token_length() > 1 {
+                    match self.source.as_bytes()[self.start + 1] {
+                        b'n' => return self.check_keyword(2, 1, "d", TokenKind::And),
+                        b's' => return self.check_keyword(2, 4, "sert", TokenKind::Assert),
+                        _ => {}
+                    }
+                }
+                TokenKind::Identifier
+            }
+            b'b' => self.check_keyword(1, 4, "reak", TokenKind::Break),
+            b'd' => self.check_keyword(1, 1, "o", TokenKind::Do),
             b'e' => self.check_keyword(1, 3, "lse", TokenKind::Else),
-            b'i' => self.check_keyword(1, 1, "f", TokenKind::If),
+            b'i' => {
+                if self.current_token_length() > 1 {
+                    match self.source.as_bytes()[self.start + 1] {
+                        b'f' => return self.check_keyword(2, 0, "", TokenKind::If),
+                        b'm' => return self.check_keyword(2, 4, "port", TokenKind::Import),
+                        b'n' => return self.check_keyword(2, 0, "", TokenKind::In),
+                        b's' => return self.check_keyword(2, 0, "", TokenKind::Is),
+                        _ => {}
+                    }
+                }
+                TokenKind::Identifier
+            }
             b'n' => self.check_keyword(1, 2, "il", TokenKind::Nil),
             b'o' => self.check_keyword(1, 1, "r", TokenKind::Or),
             b'p' => self.check_keyword(1, 4, "rint", TokenKind::Print),
@@ -170,6 +272,7 @@ impl<'source> Scanner<'source> {
             b's' => self.check_keyword(1, 4, "uper", TokenKind::Super),
             b'v' => self.check_keyword(1, 2, "ar", TokenKind::Var),
             b'w' => self.check_keyword(1, 4, "hile", TokenKind::While),
+            b'y' => self.check_keyword(1, 4, "ield", TokenKind::Yield),
             b'f' => {
                 if self.current_token_length() > 1 {
                     match self.source.as_bytes()[self.start + 1] {
@@ -181,10 +284,30 @@ impl<'source> Scanner<'source> {
                 }
                 TokenKind::Identifier
             }
+            b'c' => {
+                if self.current_token_length() > 1 {
+                    match self.source.as_bytes()[self.start + 1] {
+                        b'a' => return self.check_keyword(2, 3, "tch", TokenKind::Catch),
+                        b'l' => return self.check_keyword(2, 3, "ass", TokenKind::Class),
+                        b'o' if self.current_token_length() == 5 => {
+                            return self.check_keyword(2, 3, "nst", TokenKind::Const)
+                        }
+                        b'o' => return self.check_keyword(2, 6, "ntinue", TokenKind::Continue),
+                        _ => {}
+                    }
+                }
+                TokenKind::Identifier
+            }
             b't' => {
                 if self.current_token_length() > 1 {
                     match self.source.as_bytes()[self.start + 1] {
+                        b'h' if self.current_token_length() == 5 => {
+                            return self.check_keyword(2, 3, "row", TokenKind::Throw)
+                        }
                         b'h' => return self.check_keyword(2, 2, "is", TokenKind::This),
+                        b'r' if self.current_token_length() == 3 => {
+                            return self.check_keyword(2, 1, "y", TokenKind::Try)
+                        }
                         b'r' => return self.check_keyword(2, 2, "ue", TokenKind::True),
                         _ => {}
                     }
@@ -207,32 +330,78 @@ impl<'source> Scanner<'source> {
     }
 
     fn number(&mut self) -> Token<'source> {
-        while self.peek().is_ascii_digit() {
-            self.advance();
+        let first = self.source.as_bytes()[self.start];
+        if first == b'0' && matches!(self.peek(), b'x' | b'X') {
+            return self.radix_number(|c| c.is_ascii_hexdigit(), "Expect hex digits after '0x'.");
         }
+        if first == b'0' && matches!(self.peek(), b'b' | b'B') {
+            return self.radix_number(
+                |c| c == b'0' || c == b'1',
+                "Expect binary digits after '0b'.",
+            );
+        }
+
+        self.digits(|c| c.is_ascii_digit());
+        // a literal without a fraction or exponent is an integer
+        let mut kind = TokenKind::Integer;
 
         if self.peek() == b'.' && self.peek_next().is_ascii_digit() {
             // consume the "."
             self.advance();
+            self.digits(|c| c.is_ascii_digit());
+            kind = TokenKind::Number;
+        }
 
-            while self.peek().is_ascii_digit() {
-                self.advance();
+        if matches!(self.peek(), b'e' | b'E') {
+            let sign = matches!(self.peek_next(), b'+' | b'-') as usize;
+            if self.peek_at(1 + sign).is_ascii_digit() {
+                // consume the "e" and its sign
+                self.current += 1 + sign;
+                self.digits(|c| c.is_ascii_digit());
+                kind = TokenKind::Number;
             }
         }
 
-        self.make_token(TokenKind::Number)
+        self.make_token(kind)
+    }
+
+    /// Consumes a run of digits, allowing single `_` separators between them
+    fn digits(&mut self, is_digit: fn(u8) -> bool) {
+        while is_digit(self.peek()) || (self.peek() == b'_' && is_digit(self.peek_next())) {
+            self.advance();
+        }
+    }
+
+    /// Scans the digits of a `0x` or `0b` literal, the prefix itself is consumed here
+    fn radix_number(&mut self, is_digit: fn(u8) -> bool, error: &'source str) -> Token<'source> {
+        self.advance();
+        if !is_digit(self.peek()) {
+            return Token::error(error, self.line, self.column);
+        }
+        self.digits(is_digit);
+        self.make_token(TokenKind::Integer)
     }
 
     fn string(&mut self) -> Token<'source> {
         while self.peek() != b'"' && !self.is_at_end() {
             if self.peek() == b'\n' {
                 self.line += 1;
+                self.line_start = self.current + 1;
+            }
+            // an escaped quote doesn't end the string
+            if self.peek() == b'\\' && matches!(self.peek_next(), b'"' | b'\\' | b'$') {
+                self.advance();
+            } else if self.peek() == b'$' && self.peek_next() == b'{' {
+                self.advance();
+                self.advance();
+                self.interpolations.push(0);
+                return self.make_token(TokenKind::Interpolation);
             }
             self.advance();
         }
 
         if self.is_at_end() {
-            return Token::error("Unterminated string.", self.line);
+            return Token::error("Unterminated string.", self.line, self.column);
         }
 
         // closing quote
@@ -250,6 +419,28 @@ impl<'source> Scanner<'source> {
     }
 }
 
+/// Whether `source` stops inside a string or an unclosed bracket, so more input could complete
+/// it. Unbalanced closing brackets don't count, the parser reports those
+pub fn is_incomplete(source: &str) -> bool {
+    let mut scanner = Scanner::new(source);
+    let mut depth = 0usize;
+    loop {
+        let token = scanner.scan_token();
+        match token.kind {
+            TokenKind::LeftParen | TokenKind::LeftBrace | TokenKind::LeftBracket => depth += 1,
+            TokenKind::RightParen | TokenKind::RightBrace | TokenKind::RightBracket => {
+                match depth.checked_sub(1) {
+                    Some(open) => depth = open,
+                    None => return false,
+                }
+            }
+            TokenKind::Error if token.lexeme == "Unterminated string." => return true,
+            TokenKind::Eof => return depth > 0 || !scanner.interpolations.is_empty(),
+            _ => {}
+        }
+    }
+}
+
 fn is_alpha(char: u8) -> bool {
     char.is_ascii_alphabetic() || char == b'_'
 }
@@ -0,0 +1,181 @@
+use std::{convert::TryFrom, fmt};
+
+use ahash::AHashMap;
+use serde::{
+    de::{self, DeserializeSeed, MapAccess, SeqAccess, Visitor},
+    ser::{self, SerializeMap, SerializeSeq},
+    Deserializer, Serialize, Serializer,
+};
+
+use crate::{
+    interner::Interner,
+    object::{MapKey, Object},
+    value::Value,
+};
+
+/// Deepest nesting serialized, past it the value is assumed to contain itself
+const MAX_DEPTH: usize = 256;
+
+/// A value with the interner its strings live in, which serializes as plain data: lists and
+/// tuples as sequences, maps as maps, strings as text and `nil` as a unit. Functions, classes
+/// and the other runtime objects can't be serialized
+pub struct SerializeValue<'a> {
+    value: &'a Value,
+    interner: &'a Interner,
+    depth: usize,
+}
+
+impl<'a> SerializeValue<'a> {
+    pub fn new(value: &'a Value, interner: &'a Interner) -> Self {
+        Self {
+            value,
+            interner,
+            depth: 0,
+        }
+    }
+
+    fn nested(&self, value: &'a Value) -> Self {
+        Self {
+            value,
+            interner: self.interner,
+            depth: self.depth + 1,
+        }
+    }
+}
+
+impl Serialize for SerializeValue<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.depth > MAX_DEPTH {
+            return Err(ser::Error::custom("value is nested too deeply"));
+        }
+        match self.value {
+            Value::Nil => serializer.serialize_unit(),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Number(n) => serializer.serialize_f64(*n),
+            Value::Int(n) => serializer.serialize_i64(*n),
+            Value::Obj(Object::String(string)) => {
+                serializer.serialize_str(self.interner.lookup(string.0))
+            }
+            Value::Obj(Object::StringBuffer(buffer)) => {
+                serializer.serialize_str(&buffer.contents())
+            }
+            Value::Obj(Object::List(list)) => self.items(&list.borrow(), serializer),
+            Value::Obj(Object::Tuple(tuple)) => self.items(tuple, serializer),
+            Value::Obj(Object::Map(map)) => {
+                let map = map.borrow();
+                // ordered by key as `print` shows them, since hash order changes between runs
+                let mut sorted: Vec<(String, Value, &Value)> = map
+                    .iter()
+                    .map(|(key, value)| {
+                        let key = key.to_value();
+                        (key.display(self.interner), key, value)
+                    })
+                    .collect();
+                sorted.sort_by(|a, b| a.0.cmp(&b.0));
+                let mut entries = serializer.serialize_map(Some(sorted.len()))?;
+                for (_, key, value) in &sorted {
+                    entries.serialize_entry(&self.nested(key), &self.nested(value))?;
+                }
+                entries.end()
+            }
+            Value::Obj(object) => Err(ser::Error::custom(format!(
+                "can't serialize a {}",
+                object.kind().name()
+            ))),
+        }
+    }
+}
+
+impl SerializeValue<'_> {
+    fn items<S: Serializer>(&self, items: &[Value], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(items.len()))?;
+        for item in items {
+            seq.serialize_element(&self.nested(item))?;
+        }
+        seq.end()
+    }
+}
+
+/// Deserializes plain data into a value, interning its strings. Sequences become lists and
+/// maps become maps, whose keys have to be numbers, strings, booleans or nil
+pub struct ValueSeed<'a> {
+    pub interner: &'a mut Interner,
+}
+
+impl<'de> DeserializeSeed<'de> for ValueSeed<'_> {
+    type Value = Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ValueSeed<'_> {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a number, string, boolean, nil, list or map")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        self.deserialize(deserializer)
+    }
+
+    fn visit_bool<E: de::Error>(self, b: bool) -> Result<Value, E> {
+        Ok(Value::Bool(b))
+    }
+
+    fn visit_i64<E: de::Error>(self, n: i64) -> Result<Value, E> {
+        Ok(Value::Int(n))
+    }
+
+    fn visit_u64<E: de::Error>(self, n: u64) -> Result<Value, E> {
+        // past the integer range the value can only be kept approximately
+        match i64::try_from(n) {
+            Ok(n) => Ok(Value::Int(n)),
+            Err(_) => Ok(Value::Number(n as f64)),
+        }
+    }
+
+    fn visit_f64<E: de::Error>(self, n: f64) -> Result<Value, E> {
+        Ok(Value::Number(n))
+    }
+
+    fn visit_str<E: de::Error>(self, string: &str) -> Result<Value, E> {
+        Ok(Value::from_str(string, self.interner))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(item) = seq.next_element_seed(ValueSeed {
+            interner: &mut *self.interner,
+        })? {
+            items.push(item);
+        }
+        Ok(Value::from_list(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut entries = AHashMap::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some(key) = map.next_key_seed(ValueSeed {
+            interner: &mut *self.interner,
+        })? {
+            let key = MapKey::from_value(&key).ok_or_else(|| {
+                de::Error::custom("map keys must be numbers, strings, booleans or nil")
+            })?;
+            let value = map.next_value_seed(ValueSeed {
+                interner: &mut *self.interner,
+            })?;
+            entries.insert(key, value);
+        }
+        Ok(Value::from_map(entries))
+    }
+}
@@ -0,0 +1,293 @@
+use std::{convert::TryInto, fmt::Display, sync::Arc};
+
+use crate::{
+    chunk::Chunk,
+    globals::Globals,
+    interner::Interner,
+    object::{AloxString, ObjFunction, Object},
+    opcodes::Op,
+    value::Value,
+    verify::VerifyError,
+};
+
+/// First bytes of every `.aloxc` file
+pub const MAGIC: &[u8; 4] = b"ALXC";
+/// Bumped whenever the layout or the instruction set changes, older files are rejected
+pub const FORMAT_VERSION: u16 = 3;
+
+const TAG_NIL: u8 = 0;
+const TAG_BOOL: u8 = 1;
+const TAG_NUMBER: u8 = 2;
+const TAG_INT: u8 = 3;
+const TAG_STRING: u8 = 4;
+const TAG_FUNCTION: u8 = 5;
+
+/// Deepest nesting of functions loaded, past it the file is assumed to be hostile. Decoding
+/// recurses once per level, this keeps it from overflowing the stack
+const MAX_DEPTH: usize = 256;
+
+/// Why bytes couldn't be loaded as a chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BytecodeError {
+    /// The bytes don't start with `MAGIC`
+    NotBytecode,
+    UnsupportedVersion(u16),
+    /// The bytes ended in the middle of a value
+    Truncated,
+    UnknownConstant(u8),
+    InvalidString,
+    /// A global instruction refers to a slot missing from the file's global table
+    BadGlobalSlot(u16),
+    /// More globals than a `Globals` table can hold
+    TooManyGlobals,
+    /// Functions nested deeper than `MAX_DEPTH`
+    TooDeep,
+    /// The chunk decoded but failed verification
+    Invalid(VerifyError),
+}
+
+impl Display for BytecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BytecodeError::NotBytecode => write!(f, "Not an alox bytecode file!"),
+            BytecodeError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported bytecode version {}!", version)
+            }
+            BytecodeError::Truncated => write!(f, "Bytecode ended unexpectedly!"),
+            BytecodeError::UnknownConstant(tag) => write!(f, "Unknown constant tag {}!", tag),
+            BytecodeError::InvalidString => write!(f, "Bytecode string is not valid UTF-8!"),
+            BytecodeError::BadGlobalSlot(slot) => write!(f, "Global slot {} has no name!", slot),
+            BytecodeError::TooManyGlobals => write!(f, "Too many global variables!"),
+            BytecodeError::TooDeep => write!(f, "Functions are nested too deeply!"),
+            BytecodeError::Invalid(error) => write!(f, "Invalid bytecode! {}", error),
+        }
+    }
+}
+
+impl Chunk {
+    /// Encodes the chunk and every function nested in its constants, after a versioned header.
+    /// Strings and global names are stored as text, so the bytes can be loaded into any VM.
+    ///
+    /// Panics if a constant is a runtime object, which the compiler never produces
+    pub fn serialize(&self, interner: &Interner, globals: &Globals) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        write_u32(&mut out, globals.len() as u32);
+        for slot in 0..globals.len() as u16 {
+            let name = globals.name(slot).expect("every global slot has a name");
+            write_str(&mut out, interner.lookup(name.0));
+        }
+        write_chunk(&mut out, self, interner);
+        out
+    }
+
+    /// Loads a chunk written by `serialize`, interning its strings and resolving its globals
+    /// in `globals`, which a `Vm` running the chunk must then be given. The chunk is verified
+    /// before it is returned
+    pub fn deserialize(
+        bytes: &[u8],
+        interner: &mut Interner,
+        globals: &mut Globals,
+    ) -> Result<Chunk, BytecodeError> {
+        let mut reader = Reader { bytes, position: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(BytecodeError::NotBytecode);
+        }
+        let version = u16::from_le_bytes(reader.array()?);
+        if version != FORMAT_VERSION {
+            return Err(BytecodeError::UnsupportedVersion(version));
+        }
+        // slots in the file, mapped to the ones the names get in `globals`
+        let count = reader.u32()?;
+        let mut slots = Vec::new();
+        for _ in 0..count {
+            let name = AloxString(interner.intern(reader.str()?));
+            slots.push(globals.resolve(name).ok_or(BytecodeError::TooManyGlobals)?);
+        }
+        let chunk = reader.chunk(interner, &slots, 0)?;
+        chunk.verify(interner).map_err(BytecodeError::Invalid)?;
+        Ok(chunk)
+    }
+}
+
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_str(out: &mut Vec<u8>, string: &str) {
+    write_u32(out, string.len() as u32);
+    out.extend_from_slice(string.as_bytes());
+}
+
+fn write_chunk(out: &mut Vec<u8>, chunk: &Chunk, interner: &Interner) {
+    write_u32(out, chunk.code.len() as u32);
+    out.extend_from_slice(&chunk.code);
+    write_u32(out, chunk.lines.len() as u32);
+    for &(line, count) in &chunk.lines {
+        write_u32(out, line as u32);
+        write_u32(out, count as u32);
+    }
+    write_u32(out, chunk.constants.len() as u32);
+    for constant in &chunk.constants {
+        match constant {
+            Value::Nil => out.push(TAG_NIL),
+            Value::Bool(b) => out.extend_from_slice(&[TAG_BOOL, *b as u8]),
+            Value::Number(n) => {
+                out.push(TAG_NUMBER);
+                out.extend_from_slice(&n.to_le_bytes());
+            }
+            Value::Int(n) => {
+                out.push(TAG_INT);
+                out.extend_from_slice(&n.to_le_bytes());
+            }
+            Value::Obj(Object::String(string)) => {
+                out.push(TAG_STRING);
+                write_str(out, interner.lookup(string.0));
+            }
+            Value::Obj(Object::Function(function)) => {
+                out.push(TAG_FUNCTION);
+                write_function(out, function, interner);
+            }
+            Value::Obj(object) => panic!("Can't serialize a {} constant", object.kind().name()),
+        }
+    }
+}
+
+fn write_function(out: &mut Vec<u8>, function: &ObjFunction, interner: &Interner) {
+    write_u32(out, function.arity as u32);
+    write_u32(out, function.optional as u32);
+    write_u32(out, function.upvalue_count as u32);
+    out.push(function.variadic as u8);
+    out.push(function.is_generator as u8);
+    match function.name {
+        Some(name) => {
+            out.push(1);
+            write_str(out, interner.lookup(name.0));
+        }
+        None => out.push(0),
+    }
+    write_chunk(out, &function.chunk, interner);
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+    position: usize,
+}
+
+impl<'b> Reader<'b> {
+    fn take(&mut self, len: usize) -> Result<&'b [u8], BytecodeError> {
+        let end = self
+            .position
+            .checked_add(len)
+            .ok_or(BytecodeError::Truncated)?;
+        let taken = self
+            .bytes
+            .get(self.position..end)
+            .ok_or(BytecodeError::Truncated)?;
+        self.position = end;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], BytecodeError> {
+        Ok(self.take(N)?.try_into().expect("took exactly N bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8, BytecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, BytecodeError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn str(&mut self) -> Result<&'b str, BytecodeError> {
+        let len = self.u32()? as usize;
+        std::str::from_utf8(self.take(len)?).map_err(|_| BytecodeError::InvalidString)
+    }
+
+    /// The chunk of the script, or of a function nested `depth` levels inside it
+    fn chunk(
+        &mut self,
+        interner: &mut Interner,
+        slots: &[u16],
+        depth: usize,
+    ) -> Result<Chunk, BytecodeError> {
+        let mut chunk = Chunk::init();
+        let len = self.u32()? as usize;
+        chunk.code = self.take(len)?.to_vec();
+        let lines = self.u32()?;
+        for _ in 0..lines {
+            let (line, count) = (self.u32()? as usize, self.u32()? as usize);
+            chunk.lines.push((line, count));
+        }
+        let constants = self.u32()?;
+        for _ in 0..constants {
+            let constant = match self.u8()? {
+                TAG_NIL => Value::Nil,
+                TAG_BOOL => Value::Bool(self.u8()? != 0),
+                TAG_NUMBER => Value::Number(f64::from_le_bytes(self.array()?)),
+                TAG_INT => Value::Int(i64::from_le_bytes(self.array()?)),
+                TAG_STRING => Value::from_str_index(interner.intern(self.str()?)),
+                TAG_FUNCTION => {
+                    let function = self.function(interner, slots, depth + 1)?;
+                    Value::Obj(Object::Function(Arc::new(function)))
+                }
+                tag => return Err(BytecodeError::UnknownConstant(tag)),
+            };
+            chunk.push_constant(constant);
+        }
+        remap_globals(&mut chunk, slots)?;
+        Ok(chunk)
+    }
+
+    fn function(
+        &mut self,
+        interner: &mut Interner,
+        slots: &[u16],
+        depth: usize,
+    ) -> Result<ObjFunction, BytecodeError> {
+        if depth > MAX_DEPTH {
+            return Err(BytecodeError::TooDeep);
+        }
+        let arity = self.u32()? as usize;
+        let optional = self.u32()? as usize;
+        let upvalue_count = self.u32()? as usize;
+        let variadic = self.u8()? != 0;
+        let is_generator = self.u8()? != 0;
+        let name = match self.u8()? {
+            0 => None,
+            _ => Some(AloxString(interner.intern(self.str()?))),
+        };
+        let mut function = ObjFunction::new(name);
+        function.arity = arity;
+        function.optional = optional;
+        function.upvalue_count = upvalue_count;
+        function.variadic = variadic;
+        function.is_generator = is_generator;
+        function.chunk = self.chunk(interner, slots, depth)?;
+        Ok(function)
+    }
+}
+
+/// Rewrites the slot operand of every global instruction from the file's numbering to the
+/// one of the table the chunk was loaded into
+fn remap_globals(chunk: &mut Chunk, slots: &[u16]) -> Result<(), BytecodeError> {
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        let op = Op::from_u8(chunk.code[offset]);
+        let len = chunk.instruction_len(offset);
+        if offset + len > chunk.code.len() {
+            return Err(BytecodeError::Truncated);
+        }
+        if let Some(Op::GetGlobal | Op::SetGlobal | Op::DefineGlobal | Op::DefineGlobalConst) = op {
+            let operand = offset + 1..offset + 3;
+            let slot = u16::from_be_bytes([chunk.code[offset + 1], chunk.code[offset + 2]]);
+            let remapped = slots
+                .get(slot as usize)
+                .ok_or(BytecodeError::BadGlobalSlot(slot))?;
+            chunk.code[operand].copy_from_slice(&remapped.to_be_bytes());
+        }
+        offset += len;
+    }
+    Ok(())
+}
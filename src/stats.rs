@@ -0,0 +1,88 @@
+use std::{
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use crate::opcodes::Op;
+
+/// How often each opcode ran and how long it took, collected with the `vm-stats` feature
+#[derive(Debug, Clone)]
+pub struct VmStats {
+    counts: [u64; Op::COUNT],
+    times: [Duration; Op::COUNT],
+    /// Instruction being executed and when it started, charged once the next one begins
+    current: Option<(Op, Instant)>,
+}
+
+impl Default for VmStats {
+    fn default() -> Self {
+        Self {
+            counts: [0; Op::COUNT],
+            times: [Duration::ZERO; Op::COUNT],
+            current: None,
+        }
+    }
+}
+
+impl VmStats {
+    /// Charges the previous instruction with the time since it started and starts timing `op`
+    pub(crate) fn record(&mut self, op: Op) {
+        let now = Instant::now();
+        self.finish(now);
+        self.counts[op as usize] += 1;
+        self.current = Some((op, now));
+    }
+
+    /// Stops timing the instruction in flight, when execution ends
+    pub(crate) fn stop(&mut self) {
+        self.finish(Instant::now());
+    }
+
+    fn finish(&mut self, now: Instant) {
+        if let Some((op, start)) = self.current.take() {
+            self.times[op as usize] += now - start;
+        }
+    }
+
+    /// Number of times `op` was executed
+    pub fn count(&self, op: Op) -> u64 {
+        self.counts[op as usize]
+    }
+
+    /// Total time spent executing `op`
+    pub fn time(&self, op: Op) -> Duration {
+        self.times[op as usize]
+    }
+
+    /// Every executed opcode with its count and total time, most frequent first
+    pub fn executed(&self) -> Vec<(Op, u64, Duration)> {
+        let mut executed: Vec<_> = (0..Op::COUNT as u8)
+            .filter_map(Op::from_u8)
+            .filter(|&op| self.count(op) > 0)
+            .map(|op| (op, self.count(op), self.time(op)))
+            .collect();
+        executed.sort_by_key(|&(_, count, _)| std::cmp::Reverse(count));
+        executed
+    }
+}
+
+impl Display for VmStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:<26} {:>12} {:>12} {:>10}",
+            "opcode", "count", "total µs", "avg ns"
+        )?;
+        for (op, count, time) in self.executed() {
+            writeln!(
+                f,
+                "{:<26} {:>12} {:>12} {:>10}",
+                format!("{:?}", op),
+                count,
+                time.as_micros(),
+                time.as_nanos() / count as u128
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -2,6 +2,8 @@
 pub struct Token<'source> {
     pub kind: TokenKind,
     pub line: usize,
+    /// Column of the first byte of the token, starting at 1
+    pub column: usize,
     pub lexeme: &'source str,
 }
 
@@ -12,38 +14,60 @@ impl<'source> Token<'source> {
         start: usize,
         length: usize,
         line: usize,
+        column: usize,
     ) -> Self {
         let end = start + length;
         Self {
             kind,
             line,
+            column,
             lexeme: &source[start..end],
         }
     }
 
-    pub fn error(msg: &'source str, line: usize) -> Self {
+    pub fn error(msg: &'source str, line: usize, column: usize) -> Self {
         Self {
             kind: TokenKind::Error,
             line,
+            column,
             lexeme: msg,
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum TokenKind {
     // single char tokens
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
+    QuestionDot,
+    QuestionQuestion,
     Comma,
     Dot,
+    DotDot,
+    DotDotDot,
+    DotDotEqual,
     Minus,
+    MinusEqual,
     Plus,
+    PlusEqual,
     Semicolon,
     Slash,
+    SlashEqual,
+    Percent,
+    PercentEqual,
     Star,
+    StarEqual,
+    StarStar,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
 
     // one or two char tokens
     Bang,
@@ -52,38 +76,50 @@ pub enum TokenKind {
     EqualEqual,
     Greater,
     GreaterEqual,
+    GreaterGreater,
     Less,
     LessEqual,
+    LessLess,
 
     // literals
     Identifier,
     String,
+    /// A string segment ending in `${`, followed by the tokens of the embedded expression
+    Interpolation,
     Number,
+    Integer,
 
     // Keywords
     And,
+    Assert,
+    Break,
+    Catch,
     Class,
+    Const,
+    Continue,
+    Do,
     Else,
     False,
     Fun,
     For,
     If,
+    Import,
+    In,
+    Is,
     Nil,
     Or,
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
+    Yield,
     Print,
 
+    #[default]
     Eof,
     Error,
 }
-
-impl Default for TokenKind {
-    fn default() -> Self {
-        TokenKind::Eof
-    }
-}
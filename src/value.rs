@@ -1,16 +1,89 @@
-use std::fmt::Display;
+use std::{fmt::Display, sync::Arc};
+
+use ahash::AHashMap;
 
 use crate::{
     interner::Interner,
-    object::{AloxString, Object},
+    object::{AloxString, MapKey, ObjCell, ObjFunction, ObjKind, Object},
 };
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
 pub enum Value {
     Obj(Object),
     Bool(bool),
     Number(f64),
+    Int(i64),
+    Nil,
+}
+
+/// Built-in type names usable on the right of `is`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ValueType {
+    Bool,
+    Function,
+    Int,
+    List,
+    Map,
     Nil,
+    Number,
+    Range,
+    String,
+    Tuple,
+}
+
+impl ValueType {
+    const ALL: [ValueType; 10] = [
+        ValueType::Bool,
+        ValueType::Function,
+        ValueType::Int,
+        ValueType::List,
+        ValueType::Map,
+        ValueType::Nil,
+        ValueType::Number,
+        ValueType::Range,
+        ValueType::String,
+        ValueType::Tuple,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|kind| kind.name() == name)
+    }
+
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        Self::ALL.get(byte as usize).copied()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ValueType::Bool => "Bool",
+            ValueType::Function => "Function",
+            ValueType::Int => "Int",
+            ValueType::List => "List",
+            ValueType::Map => "Map",
+            ValueType::Nil => "Nil",
+            ValueType::Number => "Number",
+            ValueType::Range => "Range",
+            ValueType::String => "String",
+            ValueType::Tuple => "Tuple",
+        }
+    }
+
+    /// Whether `value` is of this type, integers count as numbers too
+    pub fn matches(self, value: &Value) -> bool {
+        match (self, value) {
+            (ValueType::Bool, Value::Bool(_)) | (ValueType::Nil, Value::Nil) => true,
+            (ValueType::Number, Value::Number(_) | Value::Int(_)) => true,
+            (ValueType::Int, Value::Int(_)) => true,
+            (ValueType::Function, Value::Obj(object)) => object.kind().is_function(),
+            (ValueType::List, Value::Obj(object)) => object.kind() == ObjKind::List,
+            (ValueType::Map, Value::Obj(object)) => object.kind() == ObjKind::Map,
+            (ValueType::Range, Value::Obj(object)) => object.kind() == ObjKind::Range,
+            (ValueType::String, Value::Obj(object)) => object.kind() == ObjKind::String,
+            (ValueType::Tuple, Value::Obj(object)) => object.kind() == ObjKind::Tuple,
+            _ => false,
+        }
+    }
 }
 
 impl Value {
@@ -33,21 +106,168 @@ impl Value {
             None
         }
     }
+    /// The value as a float, integers are converted
     pub fn as_number(&self) -> Option<f64> {
-        if let Self::Number(num) = *self {
-            Some(num)
+        match *self {
+            Self::Number(num) => Some(num),
+            Self::Int(int) => Some(int as f64),
+            _ => None,
+        }
+    }
+
+    /// The value as a whole number, from an integer or a float without a fractional part
+    pub fn as_integer(&self) -> Option<i64> {
+        match *self {
+            Self::Int(int) => Some(int),
+            Self::Number(num) if num.fract() == 0.0 => Some(num as i64),
+            _ => None,
+        }
+    }
+
+    pub fn from_list(items: Vec<Value>) -> Self {
+        Self::Obj(Object::List(Arc::new(ObjCell::new(items))))
+    }
+
+    pub fn from_map(entries: AHashMap<MapKey, Value>) -> Self {
+        Self::Obj(Object::Map(Arc::new(ObjCell::new(entries))))
+    }
+
+    pub fn from_tuple(items: Vec<Value>) -> Self {
+        Self::Obj(Object::Tuple(items.into()))
+    }
+
+    /// Whether this is a string, interned or not
+    pub fn is_string(&self) -> bool {
+        matches!(self, Self::Obj(object) if object.kind() == ObjKind::String)
+    }
+
+    /// Lowercase name of the value's type, such as `number` or `list`. Every kind of function
+    /// is a `function`
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Obj(object) if object.kind().is_function() => "function",
+            Value::Obj(object) => object.kind().name(),
+            Value::Bool(_) => "bool",
+            Value::Number(_) => "number",
+            Value::Int(_) => "int",
+            Value::Nil => "nil",
+        }
+    }
+
+    pub fn as_string(&self) -> Option<AloxString> {
+        if let Self::Obj(Object::String(string)) = self {
+            Some(*string)
         } else {
             None
         }
     }
+    /// Renders the value the way `print` shows it, resolving names through `interner`
+    pub fn display(&self, interner: &Interner) -> String {
+        self.display_within(interner, &mut Vec::new())
+    }
 
-    pub fn as_string(&self) -> Option<AloxString> {
-        if let Self::Obj(obj) = &self {
-            if let Object::String(string) = *obj {
-                return Some(string);
+    /// `display` inside the lists and maps in `printing`, which show as `[...]` and `{...}`
+    /// when they hold themselves
+    fn display_within(&self, interner: &Interner, printing: &mut Vec<*const ()>) -> String {
+        let container = match self {
+            Value::Obj(Object::List(list)) => Some((Arc::as_ptr(list) as *const (), "[...]")),
+            Value::Obj(Object::Map(map)) => Some((Arc::as_ptr(map) as *const (), "{...}")),
+            _ => None,
+        };
+        if let Some((address, cycle)) = container {
+            if printing.contains(&address) {
+                return cycle.to_string();
+            }
+            printing.push(address);
+        }
+        let display = match self {
+            Value::Obj(obj) => match obj {
+                Object::String(idx) => interner.lookup(idx.0).to_string(),
+                Object::StringBuffer(buffer) => buffer.contents().to_string(),
+                Object::Function(fun) => function_name(interner, fun),
+                Object::Native(native) => {
+                    format!("<native fn {}>", interner.lookup(native.name.0))
+                }
+                Object::Closure(closure) => function_name(interner, &closure.function),
+                Object::Class(class) => interner.lookup(class.borrow().name.0).to_string(),
+                Object::Instance(instance) => format!(
+                    "{} instance",
+                    interner.lookup(instance.borrow().class.borrow().name.0)
+                ),
+                Object::BoundMethod(bound) => function_name(interner, &bound.method.function),
+                Object::List(list) => {
+                    let items: Vec<String> = list
+                        .borrow()
+                        .iter()
+                        .map(|v| v.display_within(interner, printing))
+                        .collect();
+                    format!("[{}]", items.join(", "))
+                }
+                Object::Tuple(items) => {
+                    let items: Vec<String> = items
+                        .iter()
+                        .map(|v| v.display_within(interner, printing))
+                        .collect();
+                    // a single item keeps its trailing comma, as in the literal `(1,)`
+                    let trailing = if items.len() == 1 { "," } else { "" };
+                    format!("({}{})", items.join(", "), trailing)
+                }
+                Object::Map(map) => {
+                    let mut entries: Vec<String> = map
+                        .borrow()
+                        .iter()
+                        .map(|(k, v)| {
+                            format!(
+                                "{}: {}",
+                                k.to_value().display(interner),
+                                v.display_within(interner, printing)
+                            )
+                        })
+                        .collect();
+                    // hash order is unstable between runs
+                    entries.sort();
+                    format!("{{{}}}", entries.join(", "))
+                }
+                Object::Iterator(_) => "<iterator>".to_string(),
+                Object::Coroutine(coroutine) => {
+                    let function = &coroutine.borrow().closure.function;
+                    match function.name {
+                        Some(name) => format!("<coroutine {}>", interner.lookup(name.0)),
+                        None => "<coroutine>".to_string(),
+                    }
+                }
+                Object::Range(range) => Object::Range(*range).to_string(),
+            },
+            other => other.to_string(),
+        };
+        if container.is_some() {
+            printing.pop();
+        }
+        display
+    }
+}
+
+fn function_name(interner: &Interner, function: &ObjFunction) -> String {
+    match function.name {
+        Some(name) => format!("<fn {}>", interner.lookup(name.0)),
+        None => "<script>".to_string(),
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Obj(a), Value::Obj(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            // an integer equals the float of the same value, as `1 == 1.0`
+            (Value::Int(a), Value::Number(b)) | (Value::Number(b), Value::Int(a)) => {
+                *a as f64 == *b
             }
+            (Value::Nil, Value::Nil) => true,
+            _ => false,
         }
-        None
     }
 }
 
@@ -56,6 +276,7 @@ impl Display for Value {
         match self {
             Value::Bool(bool) => write!(f, "{}", bool),
             Value::Number(n) => write!(f, "{}", n),
+            Value::Int(n) => write!(f, "{}", n),
             Value::Nil => write!(f, "Nil"),
             Value::Obj(obj) => write!(f, "{}", obj),
         }
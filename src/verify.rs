@@ -0,0 +1,426 @@
+use std::fmt::Display;
+
+use crate::{
+    chunk::Chunk,
+    interner::Interner,
+    object::{ObjFunction, Object},
+    opcodes::{FusedOperand, Op},
+    value::{Value, ValueType},
+};
+
+/// A problem found in a chunk, with the function and offset of the instruction it was found at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyError {
+    /// Name of the function whose chunk holds the instruction, `None` for the script
+    pub function: Option<String>,
+    pub offset: usize,
+    pub kind: VerifyErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyErrorKind {
+    UnknownOpcode(u8),
+    /// The instruction's operands run past the end of the code
+    Truncated,
+    BadConstantIndex(usize),
+    /// The constant is of the wrong type for the instruction, such as a name that isn't a string
+    WrongConstantType(usize),
+    /// A jump lands outside the code or in the middle of an instruction
+    BadJumpTarget(usize),
+    BadLocalSlot(u8),
+    BadUpvalue(u8),
+    BadValueType(u8),
+    /// The instruction pops more values than the stack holds on some path to it
+    StackUnderflow,
+    /// Two paths reach the instruction with different numbers of values on the stack
+    StackMismatch {
+        expected: usize,
+        found: usize,
+    },
+    /// Execution of a function can reach the end of its code without returning
+    MissingReturn,
+    /// Lines don't cover every byte of code
+    MissingLines,
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.function {
+            Some(name) => write!(f, "In function {} at offset {}: ", name, self.offset)?,
+            None => write!(f, "In script at offset {}: ", self.offset)?,
+        }
+        match &self.kind {
+            VerifyErrorKind::UnknownOpcode(byte) => write!(f, "unknown opcode {}", byte),
+            VerifyErrorKind::Truncated => write!(f, "instruction is cut off"),
+            VerifyErrorKind::BadConstantIndex(index) => {
+                write!(f, "constant index {} out of range", index)
+            }
+            VerifyErrorKind::WrongConstantType(index) => {
+                write!(f, "constant {} has the wrong type", index)
+            }
+            VerifyErrorKind::BadJumpTarget(target) => write!(f, "bad jump target {}", target),
+            VerifyErrorKind::BadLocalSlot(slot) => write!(f, "local slot {} out of range", slot),
+            VerifyErrorKind::BadUpvalue(index) => write!(f, "upvalue {} out of range", index),
+            VerifyErrorKind::BadValueType(byte) => write!(f, "unknown value type {}", byte),
+            VerifyErrorKind::StackUnderflow => write!(f, "stack underflow"),
+            VerifyErrorKind::StackMismatch { expected, found } => write!(
+                f,
+                "stack holds {} values on one path and {} on another",
+                expected, found
+            ),
+            VerifyErrorKind::MissingReturn => write!(f, "function can end without returning"),
+            VerifyErrorKind::MissingLines => write!(f, "no line information"),
+        }
+    }
+}
+
+impl Chunk {
+    /// Checks the chunk, as the script's top level, and every function nested in its
+    /// constants. A chunk that passes only ever decodes known instructions with in-range
+    /// operands, jumps to instruction boundaries, and keeps the same stack depth at each
+    /// instruction whichever path reaches it. Function names in errors come from `interner`
+    pub fn verify(&self, interner: &Interner) -> Result<(), VerifyError> {
+        let mut script = ObjFunction::new(None);
+        script.chunk = self.clone();
+        Verifier::new(&script, interner).verify()
+    }
+}
+
+/// Values an instruction pops and pushes, when it runs to the next one
+struct Effect {
+    pops: usize,
+    pushes: usize,
+}
+
+struct Verifier<'f> {
+    function: &'f ObjFunction,
+    interner: &'f Interner,
+    code: &'f [u8],
+    /// Whether each offset starts an instruction
+    starts: Vec<bool>,
+    /// Stack depth on entry to each instruction reached so far
+    depths: Vec<Option<usize>>,
+}
+
+impl<'f> Verifier<'f> {
+    fn new(function: &'f ObjFunction, interner: &'f Interner) -> Self {
+        let len = function.chunk.code.len();
+        Self {
+            function,
+            interner,
+            code: &function.chunk.code,
+            starts: vec![false; len + 1],
+            depths: vec![None; len + 1],
+        }
+    }
+
+    fn error(&self, offset: usize, kind: VerifyErrorKind) -> VerifyError {
+        VerifyError {
+            function: self
+                .function
+                .name
+                .map(|name| self.interner.lookup(name.0).to_string()),
+            offset,
+            kind,
+        }
+    }
+
+    fn verify(mut self) -> Result<(), VerifyError> {
+        let covered: usize = self
+            .function
+            .chunk
+            .lines
+            .iter()
+            .map(|&(_, count)| count)
+            .sum();
+        if covered < self.code.len() {
+            return Err(self.error(0, VerifyErrorKind::MissingLines));
+        }
+        self.decode()?;
+        for constant in &self.function.chunk.constants {
+            if let Value::Obj(Object::Function(function)) = constant {
+                Verifier::new(function, self.interner).verify()?;
+            }
+        }
+        self.check_stack()
+    }
+
+    /// Walks the code linearly, checking every instruction decodes with valid operands
+    fn decode(&mut self) -> Result<(), VerifyError> {
+        let chunk = &self.function.chunk;
+        let mut offset = 0;
+        while offset < self.code.len() {
+            self.starts[offset] = true;
+            let op = Op::from_u8(self.code[offset]).ok_or_else(|| {
+                self.error(offset, VerifyErrorKind::UnknownOpcode(self.code[offset]))
+            })?;
+            // a closure's length depends on the function its operand names
+            if let Op::Closure | Op::ClosureLong = op {
+                self.operand(offset, op.operand_len())?;
+                let index = chunk.constant_index(offset).unwrap_or_default();
+                match chunk.constants.get(index) {
+                    Some(Value::Obj(Object::Function(_))) => {}
+                    Some(_) => {
+                        return Err(self.error(offset, VerifyErrorKind::WrongConstantType(index)));
+                    }
+                    None => {
+                        return Err(self.error(offset, VerifyErrorKind::BadConstantIndex(index)));
+                    }
+                }
+            }
+            let len = chunk.instruction_len(offset);
+            if offset + len > self.code.len() {
+                return Err(self.error(offset, VerifyErrorKind::Truncated));
+            }
+            self.check_operands(op, offset)?;
+            offset += len;
+        }
+        self.starts[self.code.len()] = true;
+
+        // targets can only be checked once every instruction start is known
+        let mut offset = 0;
+        while offset < self.code.len() {
+            if let Some(target) = chunk.jump_target(offset) {
+                if target > self.code.len() || !self.starts[target] {
+                    return Err(self.error(offset, VerifyErrorKind::BadJumpTarget(target)));
+                }
+            }
+            offset += chunk.instruction_len(offset);
+        }
+        Ok(())
+    }
+
+    fn operand(&self, offset: usize, index: usize) -> Result<u8, VerifyError> {
+        self.code
+            .get(offset + index)
+            .copied()
+            .ok_or_else(|| self.error(offset, VerifyErrorKind::Truncated))
+    }
+
+    fn check_operands(&self, op: Op, offset: usize) -> Result<(), VerifyError> {
+        let constants = &self.function.chunk.constants;
+        let constant = |index: usize| -> Result<&Value, VerifyError> {
+            constants
+                .get(index)
+                .ok_or_else(|| self.error(offset, VerifyErrorKind::BadConstantIndex(index)))
+        };
+        let string = |index: usize| -> Result<(), VerifyError> {
+            match constant(index)? {
+                Value::Obj(Object::String(_)) => Ok(()),
+                _ => Err(self.error(offset, VerifyErrorKind::WrongConstantType(index))),
+            }
+        };
+        let index = || {
+            self.function
+                .chunk
+                .constant_index(offset)
+                .unwrap_or_default()
+        };
+        match op.short_form().unwrap_or(op) {
+            Op::Constant => constant(index()).map(|_| ()),
+            Op::Class
+            | Op::Method
+            | Op::Getter
+            | Op::Setter
+            | Op::GetProperty
+            | Op::SetProperty
+            | Op::Import
+            | Op::Assert => string(index()),
+            Op::IsType => match ValueType::from_u8(self.code[offset + 1]) {
+                Some(_) => Ok(()),
+                None => {
+                    Err(self.error(offset, VerifyErrorKind::BadValueType(self.code[offset + 1])))
+                }
+            },
+            Op::GetUpvalue | Op::SetUpvalue => self.upvalue(offset, self.code[offset + 1]),
+            Op::Closure => {
+                let captures = offset + 1 + op.operand_len();
+                if let Value::Obj(Object::Function(function)) = constant(index())? {
+                    for upvalue in 0..function.upvalue_count {
+                        let is_local = self.code[captures + upvalue * 2];
+                        let index = self.code[captures + 1 + upvalue * 2];
+                        if is_local == 0 {
+                            self.upvalue(offset, index)?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            _ => match op.superinstruction() {
+                Some((FusedOperand::Constant, _)) => {
+                    constant(self.code[offset + 2] as usize).map(|_| ())
+                }
+                _ => Ok(()),
+            },
+        }
+    }
+
+    fn upvalue(&self, offset: usize, index: u8) -> Result<(), VerifyError> {
+        if (index as usize) < self.function.upvalue_count {
+            Ok(())
+        } else {
+            Err(self.error(offset, VerifyErrorKind::BadUpvalue(index)))
+        }
+    }
+
+    /// Follows every path from the start, tracking how many values are on the stack
+    fn check_stack(&mut self) -> Result<(), VerifyError> {
+        let function = self.function;
+        // the callee, its parameters and the rest list when there is one
+        let entry = 1 + function.arity + function.variadic as usize;
+        let mut pending = vec![(0, entry)];
+        while let Some((offset, depth)) = pending.pop() {
+            match self.depths[offset] {
+                Some(expected) if expected != depth => {
+                    let kind = VerifyErrorKind::StackMismatch {
+                        expected,
+                        found: depth,
+                    };
+                    return Err(self.error(offset, kind));
+                }
+                Some(_) => continue,
+                None => self.depths[offset] = Some(depth),
+            }
+            if offset == self.code.len() {
+                // only the script may finish by running out of code
+                if function.name.is_some() {
+                    return Err(self.error(offset, VerifyErrorKind::MissingReturn));
+                }
+                continue;
+            }
+            let op = Op::from_u8(self.code[offset]).expect("decoded before");
+            self.check_slots(op, offset, depth)?;
+            let effect = self.effect(op, offset);
+            if depth < effect.pops {
+                return Err(self.error(offset, VerifyErrorKind::StackUnderflow));
+            }
+            let after = depth - effect.pops + effect.pushes;
+            let next = offset + self.function.chunk.instruction_len(offset);
+            match op {
+                Op::Return | Op::Throw => {}
+                Op::Jump | Op::Loop => pending.push((self.target(offset), after)),
+                // the handler resumes with the thrown value pushed where the stack was
+                Op::PushHandler => {
+                    pending.push((self.target(offset), after + 1));
+                    pending.push((next, after));
+                }
+                // the loop ends without pushing an item
+                Op::IterNext => {
+                    pending.push((self.target(offset), depth));
+                    pending.push((next, after));
+                }
+                _ if op.jump_sign().is_some() => {
+                    pending.push((self.target(offset), after));
+                    pending.push((next, after));
+                }
+                _ => pending.push((next, after)),
+            }
+        }
+        Ok(())
+    }
+
+    fn target(&self, offset: usize) -> usize {
+        self.function
+            .chunk
+            .jump_target(offset)
+            .expect("jump checked while decoding")
+    }
+
+    /// Local slot operands must name a value already on the stack. A closure may also
+    /// capture the slot it is about to be pushed to, as a local function calling itself does
+    fn check_slots(&self, op: Op, offset: usize, depth: usize) -> Result<(), VerifyError> {
+        let mut slots = match op {
+            Op::GetLocal | Op::SetLocal | Op::IterNext | Op::JumpIfPassed => {
+                vec![self.code[offset + 1]]
+            }
+            Op::Closure | Op::ClosureLong => {
+                let chunk = &self.function.chunk;
+                let captures = offset + 1 + op.operand_len();
+                let mut slots = Vec::new();
+                if let Some(Value::Obj(Object::Function(function))) = chunk
+                    .constant_index(offset)
+                    .and_then(|index| chunk.constants.get(index))
+                {
+                    for upvalue in 0..function.upvalue_count {
+                        if self.code[captures + upvalue * 2] != 0 {
+                            slots.push(self.code[captures + 1 + upvalue * 2]);
+                        }
+                    }
+                }
+                slots
+            }
+            _ => Vec::new(),
+        };
+        match op.superinstruction() {
+            Some((FusedOperand::Local, _)) => {
+                slots.extend([self.code[offset + 1], self.code[offset + 2]])
+            }
+            Some((FusedOperand::Constant, _)) => slots.push(self.code[offset + 1]),
+            None => {}
+        }
+        let bound = match op {
+            Op::Closure | Op::ClosureLong => depth + 1,
+            _ => depth,
+        };
+        match slots.into_iter().find(|&slot| slot as usize >= bound) {
+            Some(slot) => Err(self.error(offset, VerifyErrorKind::BadLocalSlot(slot))),
+            None => Ok(()),
+        }
+    }
+
+    fn effect(&self, op: Op, offset: usize) -> Effect {
+        let operand = || self.code[offset + 1] as usize;
+        let (pops, pushes) = match op {
+            Op::Return | Op::Pop | Op::Print | Op::DefineGlobal | Op::DefineGlobalConst => (1, 0),
+            Op::Throw | Op::CloseUpvalue | Op::LoopIfTrue => (1, 0),
+            Op::Method | Op::Getter | Op::Setter => (1, 0),
+            Op::MethodLong | Op::GetterLong | Op::SetterLong => (1, 0),
+            Op::Constant | Op::ConstantLong | Op::Nil | Op::True | Op::False => (0, 1),
+            Op::GetLocal | Op::GetGlobal | Op::GetUpvalue | Op::Import | Op::ImportLong => (0, 1),
+            Op::Class | Op::Closure | Op::ClassLong | Op::ClosureLong => (0, 1),
+            Op::GetLocalConstantAdd
+            | Op::GetLocalConstantSubtract
+            | Op::GetLocalConstantLess
+            | Op::GetLocalGetLocalAdd
+            | Op::GetLocalGetLocalLess => (0, 1),
+            // these look at the top value without consuming it
+            Op::SetLocal | Op::SetGlobal | Op::SetUpvalue | Op::JumpIfFalse | Op::JumpIfNil => {
+                (1, 1)
+            }
+            Op::Not | Op::Negate | Op::BitNot | Op::IsType | Op::IterNew => (1, 1),
+            Op::GetProperty | Op::GetPropertyLong | Op::Yield => (1, 1),
+            Op::Equal
+            | Op::Greater
+            | Op::Less
+            | Op::Is
+            | Op::Add
+            | Op::Subtract
+            | Op::Multiply
+            | Op::Divide
+            | Op::Modulo
+            | Op::Power
+            | Op::BitAnd
+            | Op::BitOr
+            | Op::BitXor
+            | Op::ShiftLeft
+            | Op::ShiftRight
+            | Op::Range
+            | Op::RangeInclusive
+            | Op::Index
+            | Op::SetProperty
+            | Op::SetPropertyLong => (2, 1),
+            Op::SetIndex => (3, 1),
+            Op::Assert | Op::AssertLong => (2, 0),
+            Op::Dup => (1, 2),
+            Op::Swap => (2, 2),
+            Op::PopN => (operand(), 0),
+            Op::BuildString | Op::BuildList | Op::BuildTuple => (operand(), 1),
+            Op::BuildMap => (operand() * 2, 1),
+            Op::Unpack => (1, operand()),
+            Op::Call | Op::TailCall => (operand() + 1, 1),
+            Op::IterNext => (0, 1),
+            Op::Jump | Op::Loop | Op::JumpIfPassed | Op::PushHandler | Op::PopHandler => (0, 0),
+        };
+        Effect { pops, pushes }
+    }
+}
@@ -1,202 +1,1522 @@
-use std::fmt::Display;
+use std::{
+    fmt::Display,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
-use ahash::AHashMap;
+use ahash::{AHashMap, AHashSet};
 
-use crate::{chunk::Chunk, interner::Interner, object::Object, opcodes::Op, value::Value};
+use crate::{
+    chunk::Chunk,
+    compiler::U8_COUNT,
+    convert::{ConversionError, FromAloxValue, IntoAloxValue},
+    globals::Globals,
+    interner::Interner,
+    natives::{self, NativeContext, Rng},
+    object::{
+        AloxString, CoroutineState, MapKey, NativeError, NativeFn, ObjBoundMethod, ObjCell,
+        ObjClass, ObjClosure, ObjCoroutine, ObjFunction, ObjInstance, ObjIterator, ObjNative,
+        ObjRange, ObjStringBuffer, ObjUpvalue, Object,
+    },
+    opcodes::{FusedOperand, Op, OPERATOR_METHODS},
+    value::{Value, ValueType},
+    AloxError,
+};
 
-const STACK_UNDERFLOW: &str = "Stack underflow!";
+#[cfg(feature = "vm-stats")]
+use crate::stats::VmStats;
+
+const NO_FRAME: &str = "No call frame!";
+const FRAMES_MAX: usize = 64;
+const STACK_MAX: usize = FRAMES_MAX * U8_COUNT;
 
 macro_rules! binary_op {
     ($self:ident,$operator:tt, $variant:tt) => {
         {
-            let b = $self.pop();
-            let a = $self.pop();
-            if let (Value::Number(n1), Value::Number(n2)) = (&a, &b) {
+            let b = $self.pop()?;
+            let a = $self.pop()?;
+            if let (Some(n1), Some(n2)) = (a.as_number(), b.as_number()) {
                 $self.push(Value::$variant(n1 $operator n2));
             } else {
+                let error = $self.operand_error("Operands must be numbers", &[&a, &b]);
+                $self.push(a);
+                $self.push(b);
+                return Err(error);
+            }
+        }
+    };
+    ($self:ident, fn $method:ident, $variant:tt) => {
+        {
+            let b = $self.pop()?;
+            let a = $self.pop()?;
+            if let (Some(n1), Some(n2)) = (a.as_number(), b.as_number()) {
+                $self.push(Value::$variant(n1.$method(n2)));
+            } else {
+                let error = $self.operand_error("Operands must be numbers", &[&a, &b]);
                 $self.push(a);
                 $self.push(b);
-                return Err($self.runtime_error("Operands must be numbers."))
+                return Err(error);
             }
         }
     };
 }
 
-macro_rules! read_string {
-    ($self:ident) => {{
-        let index = $self.next_byte();
-        let name = $self
-            .read_constant(index)
-            .as_string()
-            .expect("variable not a string!");
-        $self.interner.lookup(name.0)
+/// Like `binary_op!`, but two integers give an integer, overflowing as configured
+macro_rules! arithmetic_op {
+    ($self:ident, $operator:tt, $checked:ident, $wrapping:ident) => {{
+        if let (Value::Int(n1), Value::Int(n2)) = ($self.peek_by(1)?, $self.peek()?) {
+            let result = $self.integer_op(*n1, *n2, i64::$checked, i64::$wrapping)?;
+            $self.pop()?;
+            $self.pop()?;
+            $self.push(Value::Int(result));
+        } else {
+            binary_op!($self, $operator, Number)
+        }
+    }};
+}
+
+/// Orders two numbers, or two strings lexicographically
+macro_rules! comparison_op {
+    ($self:ident, $operator:tt) => {{
+        $self.flatten_top(2);
+        let b = $self.pop()?;
+        let a = $self.pop()?;
+        match (&a, &b) {
+            (Value::Int(n1), Value::Int(n2)) => $self.push(Value::Bool(n1 $operator n2)),
+            (Value::Obj(Object::String(s1)), Value::Obj(Object::String(s2))) => {
+                let result = $self.interner.lookup(s1.0) $operator $self.interner.lookup(s2.0);
+                $self.push(Value::Bool(result));
+            }
+            _ => match (a.as_number(), b.as_number()) {
+                (Some(n1), Some(n2)) => $self.push(Value::Bool(n1 $operator n2)),
+                _ => {
+                    let error = $self.operand_error("Operands must be two numbers or two strings", &[&a, &b]);
+                    $self.push(a);
+                    $self.push(b);
+                    return Err(error);
+                }
+            },
+        }
+    }};
+}
+
+/// Applies an integer operation to two numbers, floats truncated to `i64`
+macro_rules! bitwise_op {
+    ($self:ident, |$a:ident, $b:ident| $result:expr) => {{
+        let b = $self.pop()?;
+        let a = $self.pop()?;
+        if let (Some($a), Some($b)) = (truncate(&a), truncate(&b)) {
+            $self.push(Value::Int($result));
+        } else {
+            let error = $self.operand_error("Operands must be numbers", &[&a, &b]);
+            $self.push(a);
+            $self.push(b);
+            return Err(error);
+        }
     }};
 }
 
+/// An integer, or a float with its fractional part dropped
+fn truncate(value: &Value) -> Option<i64> {
+    match *value {
+        Value::Int(n) => Some(n),
+        Value::Number(n) => Some(n as i64),
+        _ => None,
+    }
+}
+
 pub type InterpreterResult = Result<(), InterpreterError>;
-pub struct Vm<'a> {
-    chunk: Chunk,
+
+struct CallFrame {
+    closure: Arc<ObjClosure>,
     ip: usize,
+    /// Index of the frame's slot zero in the value stack
+    slots: usize,
+    /// Number of arguments the caller passed, the remaining parameters take defaults
+    arg_count: usize,
+    /// The coroutine this frame runs, saved back into it by `yield`
+    coroutine: Option<Arc<ObjCell<ObjCoroutine>>>,
+    /// For a coroutine driven by `for-in`, where the loop exits once it returns
+    loop_exit: Option<usize>,
+}
+
+/// An active `try` block, where a throw resumes execution
+struct Handler {
+    /// Number of call frames live when the block was entered
+    frame_count: usize,
+    /// Stack height to unwind to, dropping temporaries of the abandoned code
+    stack_len: usize,
+    /// Offset of the catch clause in the chunk of the handler's frame
+    catch_ip: usize,
+}
+
+/// What dividing by zero, with `/` or `%`, results in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DivisionByZero {
+    /// Floating point results: infinity, or NaN for `0 / 0` and any `%` by zero
+    Ieee,
+    /// A "Division by zero." runtime error
+    Error,
+}
+
+/// Resource limits of a `Vm`, exceeding either is a "Stack overflow." runtime error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmConfig {
+    /// Most values the stack may hold
+    pub max_stack: usize,
+    /// Deepest nesting of function calls
+    pub max_frames: usize,
+    /// Whether integer arithmetic wraps around on overflow instead of raising a runtime error
+    pub wrapping_integers: bool,
+    pub division_by_zero: DivisionByZero,
+}
+
+impl VmConfig {
+    pub fn max_stack(mut self, max_stack: usize) -> Self {
+        self.max_stack = max_stack;
+        self
+    }
+
+    pub fn max_frames(mut self, max_frames: usize) -> Self {
+        self.max_frames = max_frames;
+        self
+    }
+
+    pub fn wrapping_integers(mut self, wrapping_integers: bool) -> Self {
+        self.wrapping_integers = wrapping_integers;
+        self
+    }
+
+    pub fn division_by_zero(mut self, division_by_zero: DivisionByZero) -> Self {
+        self.division_by_zero = division_by_zero;
+        self
+    }
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        Self {
+            max_stack: STACK_MAX,
+            max_frames: FRAMES_MAX,
+            wrapping_integers: false,
+            division_by_zero: DivisionByZero::Ieee,
+        }
+    }
+}
+
+/// What the VM prints as it executes, everything is off by default
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TraceOptions {
+    /// The value stack before each instruction
+    pub stack: bool,
+    /// Every defined global after each instruction is decoded
+    pub globals: bool,
+    /// Each instruction, disassembled, as it runs
+    pub instructions: bool,
+}
+
+impl TraceOptions {
+    pub fn all() -> Self {
+        Self {
+            stack: true,
+            globals: true,
+            instructions: true,
+        }
+    }
+}
+
+/// What scripts may reach outside the VM, everything is allowed by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Files and the console, including `import` reading modules
+    pub io: bool,
+    /// Natives reading the time
+    pub clock: bool,
+    /// Natives reading environment variables
+    pub environment: bool,
+}
+
+impl Capabilities {
+    pub fn all() -> Self {
+        Self {
+            io: true,
+            clock: true,
+            environment: true,
+        }
+    }
+
+    pub fn none() -> Self {
+        Self {
+            io: false,
+            clock: false,
+            environment: false,
+        }
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+/// Observes execution from outside the dispatch loop, for debuggers, tracers and coverage
+/// tools. Install one with `Vm::set_hook`
+pub trait VmHook: Send {
+    /// Called before `op` runs, `ip` being its offset in the current function's chunk
+    fn before_instruction(&mut self, op: Op, ip: usize, stack: &[Value]);
+}
+
+/// Stops a running `Vm` from another thread, see `Vm::interrupt_handle`
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    /// Makes `run` stop with `InterpreterError::Interrupted` before its next instruction
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+// keeps `Vm` movable to other threads, this stops compiling if a field isn't `Send`
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<Vm<'static>>();
+};
+
+pub struct Vm<'a> {
+    config: VmConfig,
+    capabilities: Capabilities,
+    /// Whether the prelude natives are defined, see `without_prelude`
+    prelude: bool,
+    frames: Vec<CallFrame>,
     stack: Vec<Value>,
-    interner: Interner<'a>,
-    globals: AHashMap<&'a str, Value>, // TODO: Optimize global storage
+    handlers: Vec<Handler>,
+    /// Handlers below this index belong to the code that called the innermost `apply`
+    handler_floor: usize,
+    /// An exception leaving `apply` for a handler of the code that called it, thrown again
+    /// where the native calling back into the VM was called
+    escaping: Option<Value>,
+    /// Paths of modules already imported, each runs only once
+    modules: AHashSet<u32>,
+    /// Directory of the script being run, where its imports are looked up. The working
+    /// directory when `None`
+    script_dir: Option<PathBuf>,
+    /// Upvalues still pointing into the stack, shared by every closure capturing the slot
+    open_upvalues: Vec<Arc<ObjCell<ObjUpvalue>>>,
+    interner: Interner,
+    globals: Globals,
+    init_string: u32,
+    /// `OPERATOR_METHODS`, interned
+    operator_names: [u32; OPERATOR_METHODS.len()],
+    /// Instructions left to dispatch before `run` gives up, unlimited when `None`
+    fuel: Option<u64>,
+    interrupt: InterruptHandle,
+    /// Behind the `random` natives, seeded from the time unless `seed_random` is called
+    rng: Rng,
+    hook: Option<Box<dyn VmHook + 'a>>,
+    trace: TraceOptions,
+    /// Where the trace goes, stdout unless set
+    trace_writer: Box<dyn Write + Send + 'a>,
+    /// Where `print` writes, stdout unless set
+    output: Box<dyn Write + Send + 'a>,
+    /// Where `interpret_current_chunk` reports failures, stderr unless set
+    error_output: Box<dyn Write + Send + 'a>,
+    #[cfg(feature = "vm-stats")]
+    stats: VmStats,
 }
 
 impl<'vm> Vm<'vm> {
-    pub fn new(chunk: Chunk, interner: Interner<'vm>) -> Self {
-        Vm {
-            chunk,
-            ip: 0,
+    pub fn new(chunk: Chunk, interner: Interner, globals: Globals) -> Self {
+        Self::with_config(chunk, interner, globals, VmConfig::default())
+    }
+
+    /// `globals` must be the table the chunk was compiled against, it holds the slots the
+    /// chunk's global instructions refer to
+    pub fn with_config(
+        chunk: Chunk,
+        mut interner: Interner,
+        globals: Globals,
+        config: VmConfig,
+    ) -> Self {
+        // the chunk may have been compiled by another interner sharing its strings
+        interner.sync();
+        let init_string = interner.intern("init");
+        let operator_names = OPERATOR_METHODS.map(|name| interner.intern(name));
+        let mut vm = Vm {
+            config,
+            capabilities: Capabilities::default(),
+            prelude: true,
+            frames: Vec::new(),
             stack: Vec::new(),
+            handlers: Vec::new(),
+            handler_floor: 0,
+            escaping: None,
+            modules: AHashSet::new(),
+            script_dir: None,
+            open_upvalues: Vec::new(),
             interner,
-            globals: AHashMap::new(),
+            globals,
+            init_string,
+            operator_names,
+            fuel: None,
+            interrupt: InterruptHandle::default(),
+            rng: Rng::from_time(),
+            hook: None,
+            trace: TraceOptions::default(),
+            trace_writer: Box::new(io::stdout()),
+            output: Box::new(io::stdout()),
+            error_output: Box::new(io::stderr()),
+            #[cfg(feature = "vm-stats")]
+            stats: VmStats::default(),
+        };
+        natives::start_clock();
+        vm.install_prelude();
+        vm.load(chunk);
+        vm
+    }
+
+    /// Makes `chunk` the script the next `run` executes. Globals, strings and imported
+    /// modules stay, so `chunk` can build on earlier ones compiled against the same globals.
+    /// Whatever a failed run left on the stack is dropped
+    pub fn load(&mut self, chunk: Chunk) {
+        self.close_upvalues(0);
+        self.stack.clear();
+        self.handlers.clear();
+        self.escaping = None;
+        self.frames.clear();
+        let mut script = ObjFunction::new(None);
+        script.chunk = chunk;
+        let script = Arc::new(ObjClosure::new(Arc::new(script)));
+        self.stack.push(Value::Obj(Object::Closure(script.clone())));
+        self.frames.push(CallFrame {
+            closure: script,
+            ip: 0,
+            slots: 0,
+            arg_count: 0,
+            coroutine: None,
+            loop_exit: None,
+        });
+    }
+
+    /// Compiles `source` against the VM's globals and runs it, keeping what it defines for
+    /// later calls. How the REPL runs each entry
+    pub fn interpret(&mut self, source: &str) -> Result<(), AloxError> {
+        let chunk = crate::compile(source, &mut self.interner, &mut self.globals)
+            .map_err(AloxError::Compile)?;
+        self.run_loaded(chunk)
+    }
+
+    /// Like `interpret`, for a line typed at the prompt: a bare expression ending it prints
+    /// its value, so `1 + 2` shows 3
+    pub fn interpret_line(&mut self, line: &str) -> Result<(), AloxError> {
+        let chunk = crate::compile_repl_line(line, &mut self.interner, &mut self.globals)
+            .map_err(AloxError::Compile)?;
+        self.run_loaded(chunk)
+    }
+
+    fn run_loaded(&mut self, chunk: Chunk) -> Result<(), AloxError> {
+        self.load(chunk);
+        self.run().map_err(|error| AloxError::Runtime {
+            error,
+            line: self.line(),
+        })
+    }
+
+    /// Exposes a Rust function to scripts as the global `name`, failing if every global
+    /// slot is taken
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: NativeFn,
+    ) -> Result<(), InterpreterError> {
+        self.define_native_object(name, arity, false, function)
+    }
+
+    /// Like `define_native`, for a function taking `min_arity` arguments or more
+    pub fn define_variadic_native(
+        &mut self,
+        name: &str,
+        min_arity: usize,
+        function: NativeFn,
+    ) -> Result<(), InterpreterError> {
+        self.define_native_object(name, min_arity, true, function)
+    }
+
+    fn define_native_object(
+        &mut self,
+        name: &str,
+        arity: usize,
+        variadic: bool,
+        function: NativeFn,
+    ) -> Result<(), InterpreterError> {
+        let index = self.interner.intern(name);
+        let native = ObjNative {
+            name: AloxString(index),
+            arity,
+            variadic,
+            function,
+        };
+        let slot = self
+            .globals
+            .resolve(AloxString(index))
+            .ok_or(InterpreterError::TooManyGlobals)?;
+        let native = Value::Obj(Object::Native(Arc::new(native)));
+        self.globals.define(slot, native, false);
+        Ok(())
+    }
+
+    /// Defines the global `name`, or overwrites its value, so a host can seed a script before
+    /// `run`. A constant stays constant to the script. Fails if every global slot is taken
+    pub fn set_global(&mut self, name: &str, value: Value) -> Result<(), InterpreterError> {
+        let name = AloxString(self.interner.intern(name));
+        let slot = self
+            .globals
+            .resolve(name)
+            .ok_or(InterpreterError::TooManyGlobals)?;
+        let is_const = self.globals.is_const(slot);
+        self.globals.define(slot, value, is_const);
+        Ok(())
+    }
+
+    /// Value of the global `name`, `None` if the script hasn't defined it. A string built by
+    /// concatenation is interned first, so it equals the same text written as a literal
+    pub fn get_global(&mut self, name: &str) -> Option<Value> {
+        if !self.interner.exists(name) {
+            return None;
+        }
+        let name = AloxString(self.interner.get_existing(name));
+        let slot = self.globals.slot(name)?;
+        let value = self.globals.get(slot).cloned()?;
+        Some(self.flatten(value))
+    }
+
+    /// Defines the global `args` as a list of `args`, the command line arguments of a script
+    pub fn set_args(&mut self, args: &[String]) -> Result<(), InterpreterError> {
+        let args = self.to_value(args.to_vec());
+        self.set_global("args", args)
+    }
+
+    /// Names of every defined global, natives included, in the order they were declared
+    pub fn global_names(&self) -> impl Iterator<Item = &str> {
+        self.globals
+            .iter()
+            .map(move |(name, _)| self.interner.lookup(name.0))
+    }
+
+    /// Tells the VM the script was read from `path`, so `import` finds modules next to it
+    /// rather than in the working directory
+    pub fn with_script_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.script_dir = path.as_ref().parent().map(Path::to_path_buf);
+        self
+    }
+
+    /// Limits execution to `fuel` instructions, after which `run` stops with
+    /// `InterpreterError::FuelExhausted`. Guards hosts against scripts that never finish
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    /// Restricts what scripts may reach outside the VM, removing prelude natives that need a
+    /// capability it withholds
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self.install_prelude();
+        self
+    }
+
+    /// Removes every prelude native, such as `clock` and `exit`, leaving a host to define only
+    /// the natives it wants
+    pub fn without_prelude(mut self) -> Self {
+        self.prelude = false;
+        self.install_prelude();
+        self
+    }
+
+    /// Defines the prelude natives the capabilities allow and removes the others. A script
+    /// that took every global slot has a slot for each name it uses, so only natives it never
+    /// names are left out
+    fn install_prelude(&mut self) {
+        for native in natives::PRELUDE {
+            if self.prelude && (native.allowed)(&self.capabilities) {
+                let _ = self.define_native_object(
+                    native.name,
+                    native.arity,
+                    native.variadic,
+                    native.function,
+                );
+            } else {
+                self.remove_native(native.name);
+            }
+        }
+        for &(name, value) in natives::CONSTANTS {
+            let value = Value::Number(value);
+            if self.prelude {
+                let _ = self.set_global(name, value);
+            } else if let Some(slot) = self.existing_slot(name) {
+                // unless something else took the name
+                if self.globals.get(slot) == Some(&value) {
+                    self.globals.remove(slot);
+                }
+            }
         }
     }
 
+    /// Undefines the native `name`, returning false if no native has that name
+    pub fn remove_native(&mut self, name: &str) -> bool {
+        match self.existing_slot(name) {
+            Some(slot) if matches!(self.globals.get(slot), Some(Value::Obj(Object::Native(_)))) => {
+                self.globals.remove(slot);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Slot of the global `name`, without interning it
+    fn existing_slot(&self, name: &str) -> Option<u16> {
+        if !self.interner.exists(name) {
+            return None;
+        }
+        self.globals
+            .slot(AloxString(self.interner.get_existing(name)))
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// Sends the output of `print` to `writer` instead of stdout
+    pub fn with_output(mut self, writer: impl Write + Send + 'vm) -> Self {
+        self.output = Box::new(writer);
+        self
+    }
+
+    /// Reports failures of `interpret_current_chunk` to `writer` instead of stderr
+    pub fn with_error_output(mut self, writer: impl Write + Send + 'vm) -> Self {
+        self.error_output = Box::new(writer);
+        self
+    }
+
+    pub fn interner(&self) -> &Interner {
+        &self.interner
+    }
+
+    pub fn interner_mut(&mut self) -> &mut Interner {
+        &mut self.interner
+    }
+
+    /// Converts `value` for scripts, interning its strings
+    pub fn to_value(&mut self, value: impl IntoAloxValue) -> Value {
+        value.into_value(&mut self.interner)
+    }
+
+    /// Converts a value from a script, such as the result of `call_function`
+    pub fn from_value<T: FromAloxValue>(&self, value: &Value) -> Result<T, ConversionError> {
+        T::from_value(value, &self.interner)
+    }
+
+    /// `value` ready to serialize, its strings resolved through the VM's interner
+    #[cfg(feature = "serde")]
+    pub fn serializable<'s>(&'s self, value: &'s Value) -> crate::serde_value::SerializeValue<'s> {
+        crate::serde_value::SerializeValue::new(value, &self.interner)
+    }
+
+    /// A value read from `deserializer`, its strings interned in the VM's interner
+    #[cfg(feature = "serde")]
+    pub fn deserialize_value<'de, D: serde::Deserializer<'de>>(
+        &mut self,
+        deserializer: D,
+    ) -> Result<Value, D::Error> {
+        use serde::de::DeserializeSeed;
+        let seed = crate::serde_value::ValueSeed {
+            interner: &mut self.interner,
+        };
+        seed.deserialize(deserializer)
+    }
+
+    /// The generator behind `random`, for natives that draw from it
+    pub(crate) fn rng(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+
+    /// Seeds the generator behind `random`, so runs of a script draw the same numbers. Scripts
+    /// can do the same with the `seed` native
+    pub fn seed_random(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    /// Fuel left, `None` if execution is unlimited
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Refuels a VM between runs, or lifts the limit with `None`. See `with_fuel`
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    /// A handle other threads can use to stop `run`, each interrupt stops it once
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// Executions and time per opcode over every `run` so far
+    #[cfg(feature = "vm-stats")]
+    pub fn stats(&self) -> &VmStats {
+        &self.stats
+    }
+
+    /// Installs `hook` to be called before every instruction, returning the previous one
+    pub fn set_hook(&mut self, hook: Box<dyn VmHook + 'vm>) -> Option<Box<dyn VmHook + 'vm>> {
+        self.hook.replace(hook)
+    }
+
+    /// Removes the installed hook, if any
+    pub fn take_hook(&mut self) -> Option<Box<dyn VmHook + 'vm>> {
+        self.hook.take()
+    }
+
+    pub fn set_trace(&mut self, trace: TraceOptions) {
+        self.trace = trace;
+    }
+
+    /// Sends the trace to `writer` instead of stdout
+    pub fn set_trace_writer(&mut self, writer: impl Write + Send + 'vm) {
+        self.trace_writer = Box::new(writer);
+    }
+
+    /// Runs the chunk like `run`, also writing any error to the error output
     pub fn interpret_current_chunk(&mut self) -> InterpreterResult {
-        self.run()
+        let result = self.run();
+        if let Err(err) = &result {
+            writeln!(self.error_output, "{}", err)?;
+        }
+        result
+    }
+
+    /// Line of the instruction the VM last ran, where it stopped after an error
+    pub fn line(&self) -> Option<usize> {
+        let frame = self.frames.last()?;
+        frame
+            .closure
+            .function
+            .chunk
+            .line_at(frame.ip.saturating_sub(1))
     }
 
+    /// Runs the script, which can only be done once. `call_function` can be used afterwards
     pub fn run(&mut self) -> InterpreterResult {
+        // the script's frame is gone once it has returned
+        if self.frames.is_empty() {
+            return Err(InterpreterError::NoInstructions);
+        }
+        let result = self.execute(0).map(|_| ());
+        #[cfg(feature = "vm-stats")]
+        self.stats.stop();
+        result
+    }
+
+    /// Calls the global function `name` with `args`, returning what it returns. The script
+    /// has to have run first, defining the function. Exceptions the function doesn't catch
+    /// are runtime errors, they never reach handlers of the script
+    pub fn call_function(&mut self, name: &str, args: &[Value]) -> Result<Value, InterpreterError> {
+        let callee = self.get_global(name).ok_or_else(|| {
+            InterpreterError::RuntimeError(format!("Undefined variable '{}'", name))
+        })?;
+        self.apply(callee, args)
+    }
+
+    /// Calls `callee`, any value a script could call, with `args`. Like `call_function`,
+    /// exceptions it doesn't catch are runtime errors. When a native calls back into the
+    /// script, an exception the callback doesn't catch is thrown again where the native
+    /// was called, reaching the script's handlers. A string returned is interned, like one
+    /// from `get_global`
+    pub fn apply(&mut self, callee: Value, args: &[Value]) -> Result<Value, InterpreterError> {
+        let base = self.frames.len();
+        let stack_len = self.stack.len();
+        let handlers = self.handlers.len();
+        let floor = std::mem::replace(&mut self.handler_floor, handlers);
+        self.push(callee.clone());
+        self.stack.extend_from_slice(args);
+        let result = self.call_value(callee, args.len()).and_then(|_| {
+            // natives and classes without an initializer finish without a frame
+            if self.frames.len() == base {
+                self.pop()
+            } else {
+                self.execute(base)
+            }
+        });
+        self.handler_floor = floor;
+        if result.is_err() {
+            self.handlers.truncate(handlers);
+            self.frames.truncate(base);
+            self.close_upvalues(stack_len);
+            self.stack.truncate(stack_len);
+        }
+        result.map(|value| self.flatten(value))
+    }
+
+    /// Runs until the frame count drops to `base`, returning the value the last frame returned
+    fn execute(&mut self, base: usize) -> Result<Value, InterpreterError> {
         loop {
-            if self.ip >= self.chunk.code.len() {
+            if self.frame().ip >= self.frame().closure.function.chunk.code.len() {
                 break;
             }
-            #[cfg(debug_assertions)]
-            self.dbg_show_stack();
+            if let Some(fuel) = &mut self.fuel {
+                // checked before reading the instruction, so it's the next one to run
+                *fuel = fuel.checked_sub(1).ok_or(InterpreterError::FuelExhausted)?;
+            }
+            if self.interrupt.0.swap(false, Ordering::Relaxed) {
+                return Err(InterpreterError::Interrupted);
+            }
+            // no instruction pushes more than 255 values, so checking between them bounds
+            // the stack
+            if self.stack.len() > self.config.max_stack {
+                return Err(self.runtime_error("Stack overflow."));
+            }
+            if self.trace.stack {
+                self.dbg_show_stack()?;
+            }
             let next_byte = self.next_byte();
-            let instruction = Op::from_u8(next_byte);
-            #[cfg(debug_assertions)]
-            self.dbg_dissamble_instructions();
-            #[cfg(debug_assertions)]
-            self.dbg_show_globals();
+            let mut instruction =
+                Op::from_u8(next_byte).ok_or(InterpreterError::UnknownOpcode(next_byte))?;
+            self.check_operands(instruction.operand_len())?;
+            if self.trace.instructions {
+                self.dbg_dissamble_instructions()?;
+            }
+            if self.trace.globals {
+                self.dbg_show_globals()?;
+            }
+            #[cfg(feature = "vm-stats")]
+            self.stats.record(instruction);
+            if let Some(hook) = &mut self.hook {
+                let ip = self.frames.last().expect(NO_FRAME).ip - 1;
+                hook.before_instruction(instruction, ip, &self.stack);
+            }
+            if let Some((operand, op)) = instruction.superinstruction() {
+                let slot = self.next_byte();
+                let index = self.next_byte();
+                let local = &self.stack[self.local_slot(slot)?];
+                let second = match operand {
+                    FusedOperand::Constant => self.read_constant(index as usize)?,
+                    FusedOperand::Local => &self.stack[self.local_slot(index)?],
+                };
+                let fast = match (local, second) {
+                    (Value::Number(a), Value::Number(b)) => match op {
+                        Op::Add => Some(Value::Number(a + b)),
+                        Op::Subtract => Some(Value::Number(a - b)),
+                        _ => Some(Value::Bool(a < b)),
+                    },
+                    // overflow is left to the plain op, which reports or wraps it
+                    (Value::Int(a), Value::Int(b)) => match op {
+                        Op::Add => a.checked_add(*b).map(Value::Int),
+                        Op::Subtract => a.checked_sub(*b).map(Value::Int),
+                        _ => Some(Value::Bool(a < b)),
+                    },
+                    _ => None,
+                };
+                if let Some(result) = fast {
+                    self.push(result);
+                    continue;
+                }
+                // anything but two numbers finishes as the plain binary op
+                let (local, second) = (local.clone(), second.clone());
+                self.push(local);
+                self.push(second);
+                instruction = op;
+            }
+            if let Some(methods) = instruction.operator_methods() {
+                if self.invoke_operator(methods)? {
+                    continue;
+                }
+            }
             match instruction {
-                Op::Return => return Ok(()),
+                Op::Return => {
+                    let result = self.pop()?;
+                    let frame = self.frames.pop().expect(NO_FRAME);
+                    self.close_upvalues(frame.slots);
+                    // returning from inside a try block abandons its handler
+                    while matches!(self.handlers.last(), Some(h) if h.frame_count > self.frames.len())
+                    {
+                        self.handlers.pop();
+                    }
+                    if let Some(coroutine) = &frame.coroutine {
+                        coroutine.borrow_mut().state = CoroutineState::Done;
+                    }
+                    self.stack.truncate(frame.slots);
+                    if self.frames.len() == base {
+                        return Ok(result);
+                    }
+                    match frame.loop_exit {
+                        // a coroutine running out ends the loop over it, its result is unused
+                        Some(exit) => self.frame_mut().ip = exit,
+                        None => self.push(result),
+                    }
+                }
                 Op::Constant | Op::ConstantLong => {
-                    let index = self.next_byte();
-                    let constant = self.read_constant(index);
+                    let index = self.read_index(instruction);
+                    let constant = self.read_constant(index)?.clone();
                     self.push(constant);
                 }
                 Op::Negate => {
-                    let val = self.pop();
+                    let val = self.pop()?;
                     if let Value::Number(n) = val {
                         self.push(Value::Number(-n));
+                    } else if let Value::Int(n) = val {
+                        let negated = self.integer_op(
+                            n,
+                            (),
+                            |n, _| n.checked_neg(),
+                            |n, _| n.wrapping_neg(),
+                        )?;
+                        self.push(Value::Int(negated));
                     } else {
+                        let error = self.operand_error("Operand must be a number", &[&val]);
                         self.push(val);
-                        return Err(self.runtime_error("Operand must be a number."));
+                        return Err(error);
                     }
                 }
+                Op::BitNot => {
+                    let val = self.pop()?;
+                    if let Some(n) = truncate(&val) {
+                        self.push(Value::Int(!n));
+                    } else {
+                        let error = self.operand_error("Operand must be a number", &[&val]);
+                        self.push(val);
+                        return Err(error);
+                    }
+                }
+                Op::BitAnd => bitwise_op!(self, |a, b| a & b),
+                Op::BitOr => bitwise_op!(self, |a, b| a | b),
+                Op::BitXor => bitwise_op!(self, |a, b| a ^ b),
+                Op::ShiftLeft | Op::ShiftRight => self.shift(instruction)?,
                 Op::Add => {
-                    let b = self.pop();
-                    let a = self.pop();
-                    match (&b, &a) {
-                        (Value::Obj(b), Value::Obj(a)) => {
-                            if let (Object::String(a), Object::String(b)) = (b, a) {
-                                let first = {
-                                    let str = self.interner.lookup(b.0);
-                                    String::from(str)
-                                };
-                                let second = self.interner.lookup(a.0);
-                                let concatenated = first + second;
-                                let concatenated = self.interner.intern(&concatenated);
-                                self.push(Value::from_str_index(concatenated));
-                            } else {
-                                self.push(Value::Obj(a.clone()));
-                                self.push(Value::Obj(b.clone()));
-                                return Err(self.runtime_error("Operands must be two strings."));
-                            }
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    match (&a, &b) {
+                        (Value::Int(n1), Value::Int(n2)) => {
+                            let sum =
+                                self.integer_op(*n1, *n2, i64::checked_add, i64::wrapping_add)?;
+                            self.push(Value::Int(sum));
                         }
-                        (Value::Number(b), Value::Number(a)) => self.push(Value::Number(a + b)),
-                        _ => {
-                            self.push(a);
-                            self.push(b);
-                            return Err(self.runtime_error("Operands must be two numbers."));
+                        // a number next to a string is converted, as with `"count: " + 3`
+                        _ if (a.is_string() || b.is_string())
+                            && (a.is_string() || a.as_number().is_some())
+                            && (b.is_string() || b.as_number().is_some()) =>
+                        {
+                            let concatenated = self.concatenate(&a, &b);
+                            self.push(concatenated);
                         }
+                        _ => match (a.as_number(), b.as_number()) {
+                            (Some(n1), Some(n2)) => self.push(Value::Number(n1 + n2)),
+                            _ => {
+                                let error = self.operand_error(
+                                    "Operands must be two numbers or two strings",
+                                    &[&a, &b],
+                                );
+                                self.push(a);
+                                self.push(b);
+                                return Err(error);
+                            }
+                        },
                     }
                 }
-                Op::Subtract => binary_op!(self, -, Number),
-                Op::Multiply => binary_op!(self, *, Number),
-                Op::Divide => binary_op!(self, /, Number),
+                Op::Subtract => arithmetic_op!(self, -, checked_sub, wrapping_sub),
+                Op::Multiply => arithmetic_op!(self, *, checked_mul, wrapping_mul),
+                // division always gives a float, `7 / 2` is `3.5`
+                Op::Divide => {
+                    self.check_divisor()?;
+                    binary_op!(self, /, Number)
+                }
+                Op::Modulo => {
+                    self.check_divisor()?;
+                    match (self.peek_by(1)?, self.peek()?) {
+                        (Value::Int(a), Value::Int(b)) if *b != 0 => {
+                            let result =
+                                self.integer_op(*a, *b, i64::checked_rem, i64::wrapping_rem)?;
+                            self.pop()?;
+                            self.pop()?;
+                            self.push(Value::Int(result));
+                        }
+                        // an integer remainder by zero is NaN, like the float one
+                        _ => binary_op!(self, %, Number),
+                    }
+                }
+                Op::Power => match (self.peek_by(1)?, self.peek()?) {
+                    (Value::Int(base), Value::Int(exponent)) if *exponent >= 0 => {
+                        let exponent = (*exponent).min(u32::MAX as i64) as u32;
+                        let result =
+                            self.integer_op(*base, exponent, i64::checked_pow, i64::wrapping_pow)?;
+                        self.pop()?;
+                        self.pop()?;
+                        self.push(Value::Int(result));
+                    }
+                    _ => binary_op!(self, fn powf, Number),
+                },
                 Op::Nil => self.push(Value::Nil),
                 Op::True => self.push(Value::Bool(true)),
                 Op::False => self.push(Value::Bool(false)),
                 Op::Not => {
-                    let val = self.pop();
+                    let val = self.pop()?;
                     self.push(Value::Bool(Vm::is_falsey(val)))
                 }
                 Op::Equal => {
-                    let b = self.pop();
-                    let a = self.pop();
+                    self.flatten_top(2);
+                    let b = self.pop()?;
+                    let a = self.pop()?;
                     self.push(Value::Bool(a == b))
                 }
-                Op::Greater => binary_op!(self, >, Bool),
-                Op::Less => binary_op!(self, <, Bool),
+                Op::Greater => comparison_op!(self, >),
+                Op::Less => comparison_op!(self, <),
+                Op::Import | Op::ImportLong => {
+                    let index = self.read_index(instruction);
+                    let path = self.read_string(index)?;
+                    self.import(path)?;
+                }
+                Op::Assert | Op::AssertLong => {
+                    let index = self.read_index(instruction);
+                    let text = self.read_string(index)?;
+                    let message = self.pop()?;
+                    let condition = self.pop()?;
+                    if Vm::is_falsey(condition) {
+                        let text = self.interner.lookup(text.0);
+                        let msg = match message {
+                            Value::Nil => format!("Assertion failed: {}", text),
+                            message => {
+                                format!("Assertion failed: {}: {}", text, self.stringify(&message))
+                            }
+                        };
+                        return Err(self.runtime_error(&msg));
+                    }
+                }
+                Op::Is => {
+                    let class = self.pop()?;
+                    let value = self.pop()?;
+                    let class = match class {
+                        Value::Obj(Object::Class(class)) => class,
+                        other => {
+                            self.push(value);
+                            self.push(other);
+                            return Err(
+                                self.runtime_error("Right operand of 'is' must be a class.")
+                            );
+                        }
+                    };
+                    let is_instance = match value {
+                        Value::Obj(Object::Instance(instance)) => {
+                            Arc::ptr_eq(&instance.borrow().class, &class)
+                        }
+                        _ => false,
+                    };
+                    self.push(Value::Bool(is_instance));
+                }
+                Op::IsType => {
+                    let kind = self.next_byte();
+                    let kind =
+                        ValueType::from_u8(kind).ok_or(InterpreterError::BadValueType(kind))?;
+                    let value = self.pop()?;
+                    self.push(Value::Bool(kind.matches(&value)));
+                }
                 Op::Print => {
-                    let val = self.pop();
-                    self.print_val(val)
+                    let val = self.pop()?;
+                    self.print_val(val)?;
                 }
                 Op::Pop => {
-                    self.pop();
+                    self.pop()?;
+                }
+                Op::Dup => {
+                    let value = self.peek()?.clone();
+                    self.push(value);
+                }
+                Op::Swap => {
+                    let top = self.stack.len();
+                    if top < 2 {
+                        return Err(InterpreterError::StackUnderflow);
+                    }
+                    self.stack.swap(top - 1, top - 2);
                 }
-                Op::DefineGlobal => {
-                    let name = read_string!(self);
-                    let value = self.pop();
-                    self.globals.insert(name, value);
+                Op::PopN => {
+                    let count = self.next_byte() as usize;
+                    let len = self.top_start(count)?;
+                    self.stack.truncate(len);
+                }
+                Op::DefineGlobal | Op::DefineGlobalConst => {
+                    let slot = self.read_short();
+                    let name = self.global_name(slot)?;
+                    if self.globals.is_const(slot) {
+                        let msg = format!("Can't redefine constant '{}'.", name);
+                        return Err(self.runtime_error(&msg));
+                    }
+                    let value = self.pop()?;
+                    self.globals
+                        .define(slot, value, instruction == Op::DefineGlobalConst);
                 }
                 Op::GetGlobal => {
-                    let name = read_string!(self);
-                    let val = if let Some(val) = self.globals.get(name) {
+                    let slot = self.read_short();
+                    let val = if let Some(val) = self.globals.get(slot) {
                         val.clone()
                     } else {
                         return Err(InterpreterError::RuntimeError(format!(
                             "Undefined variable '{}'",
-                            name
+                            self.global_name(slot)?
                         )));
                     };
                     self.push(val);
                 }
                 Op::SetGlobal => {
-                    let name = read_string!(self);
-                    if self.globals.contains_key(name) {
-                        self.globals.insert(name, self.peek().clone())
-                    } else {
+                    let slot = self.read_short();
+                    if self.globals.is_const(slot) {
+                        let msg =
+                            format!("Can't assign to constant '{}'.", self.global_name(slot)?);
+                        return Err(self.runtime_error(&msg));
+                    }
+                    let value = self.peek()?.clone();
+                    if !self.globals.set(slot, value) {
                         return Err(InterpreterError::RuntimeError(format!(
                             "Undefined variable '{}'",
-                            name
+                            self.global_name(slot)?
                         )));
                     };
                 }
                 Op::GetLocal => {
                     let slot = self.next_byte();
-                    let local = self.stack[slot as usize].clone();
+                    let local = self.stack[self.local_slot(slot)?].clone();
                     self.push(local)
                 }
                 Op::SetLocal => {
                     let slot = self.next_byte();
-                    self.stack[slot as usize] = self.peek().clone();
+                    let slot = self.local_slot(slot)?;
+                    self.stack[slot] = self.peek()?.clone();
+                }
+                Op::BuildString => {
+                    let count = self.next_byte() as usize;
+                    let start = self.top_start(count)?;
+                    let built: String = self.stack[start..]
+                        .iter()
+                        .map(|part| self.stringify(part))
+                        .collect();
+                    self.stack.truncate(start);
+                    let built = self.interner.intern(&built);
+                    self.push(Value::from_str_index(built));
+                }
+                Op::BuildList => {
+                    let count = self.next_byte() as usize;
+                    let start = self.top_start(count)?;
+                    self.flatten_top(count);
+                    let items = self.stack.split_off(start);
+                    self.push(Value::from_list(items));
+                }
+                Op::BuildTuple => {
+                    let count = self.next_byte() as usize;
+                    let start = self.top_start(count)?;
+                    self.flatten_top(count);
+                    let items = self.stack.split_off(start);
+                    self.push(Value::from_tuple(items));
+                }
+                Op::Unpack => {
+                    let count = self.next_byte() as usize;
+                    let items = match self.pop()? {
+                        Value::Obj(Object::Tuple(items)) => items.to_vec(),
+                        Value::Obj(Object::List(list)) => list.borrow().clone(),
+                        _ => {
+                            return Err(self.runtime_error("Can only destructure tuples and lists."))
+                        }
+                    };
+                    if items.len() != count {
+                        let msg = format!(
+                            "Expected {} values to unpack but got {}.",
+                            count,
+                            items.len()
+                        );
+                        return Err(self.runtime_error(&msg));
+                    }
+                    self.stack.extend(items);
+                }
+                Op::BuildMap => {
+                    let count = self.next_byte() as usize;
+                    let start = self.top_start(count * 2)?;
+                    self.flatten_top(count * 2);
+                    let entries = self.stack.split_off(start);
+                    let mut map = AHashMap::with_capacity(count);
+                    for entry in entries.chunks(2) {
+                        let key = self.map_key(&entry[0])?;
+                        map.insert(key, entry[1].clone());
+                    }
+                    self.push(Value::from_map(map));
+                }
+                Op::Index => {
+                    self.flatten_top(2);
+                    let index = self.pop()?;
+                    let target = self.pop()?;
+                    let value = self.index(&target, &index)?;
+                    self.push(value);
+                }
+                Op::SetIndex => {
+                    self.flatten_top(3);
+                    let value = self.pop()?;
+                    let index = self.pop()?;
+                    let target = self.pop()?;
+                    match &target {
+                        Value::Obj(Object::List(list)) => {
+                            let len = list.borrow().len();
+                            let slot = self.list_slot(&index, len)?;
+                            list.borrow_mut()[slot] = value.clone();
+                        }
+                        Value::Obj(Object::Map(map)) => {
+                            let key = self.map_key(&index)?;
+                            map.borrow_mut().insert(key, value.clone());
+                        }
+                        _ => {
+                            return Err(
+                                self.runtime_error("Only lists and maps support index assignment.")
+                            )
+                        }
+                    }
+                    self.push(value);
+                }
+                Op::Jump => {
+                    let offset = self.read_short();
+                    self.frame_mut().ip += offset as usize;
+                }
+                Op::JumpIfPassed => {
+                    let slot = self.next_byte() as usize;
+                    let offset = self.read_short();
+                    if slot <= self.frame().arg_count {
+                        self.frame_mut().ip += offset as usize;
+                    }
+                }
+                Op::PushHandler => {
+                    let offset = self.read_short();
+                    let handler = Handler {
+                        frame_count: self.frames.len(),
+                        stack_len: self.stack.len(),
+                        catch_ip: self.frame().ip + offset as usize,
+                    };
+                    self.handlers.push(handler);
+                }
+                Op::PopHandler => {
+                    self.handlers.pop();
+                }
+                Op::Throw => {
+                    let exception = self.pop()?;
+                    self.throw(exception)?;
+                }
+                Op::JumpIfFalse => {
+                    let offset = self.read_short();
+                    if Vm::is_falsey(self.peek()?.clone()) {
+                        self.frame_mut().ip += offset as usize;
+                    }
+                }
+                Op::JumpIfNil => {
+                    let offset = self.read_short();
+                    if *self.peek()? == Value::Nil {
+                        self.frame_mut().ip += offset as usize;
+                    }
+                }
+                Op::Loop => {
+                    let offset = self.read_short();
+                    self.jump_back(offset)?;
+                }
+                Op::LoopIfTrue => {
+                    let offset = self.read_short();
+                    if !Vm::is_falsey(self.pop()?) {
+                        self.jump_back(offset)?;
+                    }
+                }
+                Op::Range => self.build_range(false)?,
+                Op::RangeInclusive => self.build_range(true)?,
+                Op::GetLocalConstantAdd
+                | Op::GetLocalConstantSubtract
+                | Op::GetLocalConstantLess
+                | Op::GetLocalGetLocalAdd
+                | Op::GetLocalGetLocalLess => {
+                    return Err(InterpreterError::InvalidBytecode(
+                        "superinstruction not expanded",
+                    ))
+                }
+                Op::IterNew => {
+                    self.flatten_top(1);
+                    let iterable = self.pop()?;
+                    let source = match &iterable {
+                        Value::Obj(Object::List(_))
+                        | Value::Obj(Object::String(_))
+                        | Value::Obj(Object::Range(_))
+                        | Value::Obj(Object::Tuple(_))
+                        | Value::Obj(Object::Coroutine(_)) => iterable,
+                        // maps are walked over a snapshot of their keys, sorted like `print`
+                        // shows them since hash order is unstable between runs
+                        Value::Obj(Object::Map(map)) => {
+                            let mut keys: Vec<Value> =
+                                map.borrow().keys().map(|k| k.to_value()).collect();
+                            keys.sort_by_cached_key(|k| k.display(&self.interner));
+                            Value::from_list(keys)
+                        }
+                        _ => {
+                            self.push(iterable);
+                            return Err(self.runtime_error(
+                                "Can only iterate over lists, maps, ranges, strings, tuples and coroutines.",
+                            ));
+                        }
+                    };
+                    let iterator = ObjIterator {
+                        source,
+                        position: 0,
+                    };
+                    self.push(Value::Obj(Object::Iterator(Arc::new(ObjCell::new(
+                        iterator,
+                    )))));
+                }
+                Op::IterNext => {
+                    let slot = self.next_byte();
+                    let slot = self.local_slot(slot)?;
+                    let offset = self.read_short();
+                    let iterator = match &self.stack[slot] {
+                        Value::Obj(Object::Iterator(iterator)) => iterator.clone(),
+                        _ => {
+                            let reason = "for-in slot does not hold an iterator";
+                            return Err(InterpreterError::InvalidBytecode(reason));
+                        }
+                    };
+                    let coroutine = match &iterator.borrow().source {
+                        Value::Obj(Object::Coroutine(coroutine)) => Some(coroutine.clone()),
+                        _ => None,
+                    };
+                    if let Some(coroutine) = coroutine {
+                        let exit = self.frame().ip + offset as usize;
+                        if coroutine.borrow().state == CoroutineState::Done {
+                            self.frame_mut().ip = exit;
+                        } else {
+                            // the next yielded value lands on top of the stack as the item
+                            let slots = self.stack.len();
+                            self.resume(coroutine, slots, Value::Nil, Some(exit))?;
+                        }
+                    } else {
+                        match self.advance_iterator(&iterator) {
+                            Some(item) => self.push(item),
+                            None => self.frame_mut().ip += offset as usize,
+                        }
+                    }
+                }
+                Op::Closure | Op::ClosureLong => {
+                    let index = self.read_index(instruction);
+                    let function = match self.read_constant(index)? {
+                        Value::Obj(Object::Function(function)) => function.clone(),
+                        _ => return Err(InterpreterError::WrongConstantType(index)),
+                    };
+                    let mut closure = ObjClosure::new(function);
+                    self.check_operands(closure.function.upvalue_count * 2)?;
+                    for _ in 0..closure.function.upvalue_count {
+                        let is_local = self.next_byte() == 1;
+                        let index = self.next_byte();
+                        let upvalue = if is_local {
+                            self.capture_upvalue(self.capture_slot(index)?)
+                        } else {
+                            self.upvalue(index)?
+                        };
+                        closure.upvalues.push(upvalue);
+                    }
+                    self.push(Value::Obj(Object::Closure(Arc::new(closure))));
+                }
+                Op::GetUpvalue => {
+                    let slot = self.next_byte();
+                    let upvalue = self.upvalue(slot)?;
+                    let value = match &*upvalue.borrow() {
+                        ObjUpvalue::Open(index) => self.stack[*index].clone(),
+                        ObjUpvalue::Closed(value) => value.clone(),
+                    };
+                    self.push(value);
+                }
+                Op::SetUpvalue => {
+                    let slot = self.next_byte();
+                    let upvalue = self.upvalue(slot)?;
+                    let value = self.peek()?.clone();
+                    match &mut *upvalue.borrow_mut() {
+                        ObjUpvalue::Open(index) => self.stack[*index] = value,
+                        ObjUpvalue::Closed(closed) => *closed = value,
+                    };
+                }
+                Op::CloseUpvalue => {
+                    let top = self.top_start(1)?;
+                    self.close_upvalues(top);
+                    self.pop()?;
+                }
+                Op::Call => {
+                    let arg_count = self.next_byte() as usize;
+                    let callee = self.peek_by(arg_count)?.clone();
+                    self.call_value(callee, arg_count)?;
+                }
+                Op::TailCall => {
+                    let arg_count = self.next_byte() as usize;
+                    let callee = self.peek_by(arg_count)?.clone();
+                    let recursive = matches!(&callee, Value::Obj(Object::Closure(closure))
+                        if Arc::ptr_eq(&closure.function, &self.frame().closure.function));
+                    // a coroutine's frame has to stay to be saved by `yield`
+                    if recursive && self.frame().coroutine.is_none() {
+                        // the callee and its arguments take the place of the returning frame,
+                        // so the result lands where this call's would have gone
+                        let frame = self.frames.pop().expect(NO_FRAME);
+                        self.close_upvalues(frame.slots);
+                        let callee_slot = self.stack.len() - arg_count - 1;
+                        if callee_slot < frame.slots {
+                            return Err(InterpreterError::StackUnderflow);
+                        }
+                        self.stack.drain(frame.slots..callee_slot);
+                    }
+                    self.call_value(callee, arg_count)?;
+                }
+                Op::Yield => {
+                    let value = self.pop()?;
+                    let frame = self.frames.pop().expect(NO_FRAME);
+                    let coroutine = frame.coroutine.ok_or(InterpreterError::InvalidBytecode(
+                        "generator function running outside a coroutine",
+                    ))?;
+                    let upvalues = self.suspend_upvalues(frame.slots);
+                    let mut suspended = coroutine.borrow_mut();
+                    suspended.upvalues = upvalues;
+                    suspended.stack = self.stack.split_off(frame.slots);
+                    suspended.ip = frame.ip;
+                    suspended.state = CoroutineState::Suspended;
+                    drop(suspended);
+                    if self.frames.len() == base {
+                        return Ok(value);
+                    }
+                    self.push(value);
+                }
+                Op::Class | Op::ClassLong => {
+                    let index = self.read_index(instruction);
+                    let name = self.read_string(index)?;
+                    let class = ObjClass::new(name);
+                    self.push(Value::Obj(Object::Class(Arc::new(ObjCell::new(class)))));
+                }
+                Op::Method
+                | Op::Getter
+                | Op::Setter
+                | Op::MethodLong
+                | Op::GetterLong
+                | Op::SetterLong => {
+                    let index = self.read_index(instruction);
+                    let name = self.read_string(index)?;
+                    let method = self.pop()?;
+                    if let Value::Obj(Object::Class(class)) = self.peek()? {
+                        let mut class = class.borrow_mut();
+                        let table = match instruction {
+                            Op::Getter | Op::GetterLong => &mut class.getters,
+                            Op::Setter | Op::SetterLong => &mut class.setters,
+                            _ => &mut class.methods,
+                        };
+                        table.insert(name.0, method);
+                    }
+                }
+                Op::GetProperty | Op::GetPropertyLong => {
+                    let index = self.read_index(instruction);
+                    let name = self.read_string(index)?;
+                    let instance = if let Value::Obj(Object::Instance(instance)) = self.peek()? {
+                        instance.clone()
+                    } else {
+                        return Err(self.runtime_error("Only instances have properties."));
+                    };
+                    let getter = instance
+                        .borrow()
+                        .class
+                        .borrow()
+                        .getters
+                        .get(&name.0)
+                        .cloned();
+                    if let Some(Value::Obj(Object::Closure(getter))) = getter {
+                        // the instance on the stack becomes the getter's `this`
+                        self.call(getter, 0)?;
+                        continue;
+                    }
+                    let field = instance.borrow().fields.get(&name.0).cloned();
+                    let method = instance
+                        .borrow()
+                        .class
+                        .borrow()
+                        .methods
+                        .get(&name.0)
+                        .cloned();
+                    let value = if let Some(value) = field {
+                        value
+                    } else if let Some(Value::Obj(Object::Closure(method))) = method {
+                        let bound = ObjBoundMethod {
+                            receiver: Value::Obj(Object::Instance(instance)),
+                            method,
+                        };
+                        Value::Obj(Object::BoundMethod(Arc::new(bound)))
+                    } else {
+                        let msg = format!("Undefined property '{}'.", self.interner.lookup(name.0));
+                        return Err(self.runtime_error(&msg));
+                    };
+                    self.pop()?;
+                    self.push(value);
+                }
+                Op::SetProperty | Op::SetPropertyLong => {
+                    let index = self.read_index(instruction);
+                    let name = self.read_string(index)?;
+                    let (setter, has_getter) = match self.peek_by(1)? {
+                        Value::Obj(Object::Instance(instance)) => {
+                            let instance = instance.borrow();
+                            let class = instance.class.borrow();
+                            (
+                                class.setters.get(&name.0).cloned(),
+                                class.getters.contains_key(&name.0),
+                            )
+                        }
+                        _ => (None, false),
+                    };
+                    if let Some(Value::Obj(Object::Closure(setter))) = setter {
+                        self.call(setter, 1)?;
+                        continue;
+                    }
+                    // the getter would hide a field of the same name forever
+                    if has_getter {
+                        let msg =
+                            format!("Property '{}' has no setter.", self.interner.lookup(name.0));
+                        return Err(self.runtime_error(&msg));
+                    }
+                    let value = self.pop()?;
+                    if let Value::Obj(Object::Instance(instance)) = self.pop()? {
+                        instance.borrow_mut().fields.insert(name.0, value.clone());
+                        self.push(value);
+                    } else {
+                        return Err(self.runtime_error("Only instances have fields."));
+                    }
                 }
             }
         }
-        Ok(())
+        Ok(Value::Nil)
     }
 
-    fn peek(&self) -> &Value {
-        self.stack.last().expect(STACK_UNDERFLOW)
+    fn peek(&self) -> Result<&Value, InterpreterError> {
+        self.stack.last().ok_or(InterpreterError::StackUnderflow)
     }
 
-    fn peek_mut(&mut self) -> &mut Value {
-        self.stack.last_mut().expect(STACK_UNDERFLOW)
+    /// Index of the first of the top `count` stack values
+    fn top_start(&self, count: usize) -> Result<usize, InterpreterError> {
+        self.stack
+            .len()
+            .checked_sub(count)
+            .ok_or(InterpreterError::StackUnderflow)
     }
 
-    fn peek_by(&self, distance: usize) -> &Value {
+    fn peek_by(&self, distance: usize) -> Result<&Value, InterpreterError> {
         self.stack
-            .get(self.stack.len() - 1 - distance)
-            .expect(STACK_UNDERFLOW)
+            .len()
+            .checked_sub(distance + 1)
+            .and_then(|index| self.stack.get(index))
+            .ok_or(InterpreterError::StackUnderflow)
     }
 
     #[inline]
-    fn pop(&mut self) -> Value {
-        self.stack.pop().expect(STACK_UNDERFLOW)
+    fn pop(&mut self) -> Result<Value, InterpreterError> {
+        self.stack.pop().ok_or(InterpreterError::StackUnderflow)
     }
 
     #[inline]
@@ -204,20 +1524,670 @@ impl<'vm> Vm<'vm> {
         self.stack.push(value)
     }
 
+    #[inline]
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect(NO_FRAME)
+    }
+
+    #[inline]
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect(NO_FRAME)
+    }
+
     fn next_byte(&mut self) -> u8 {
-        let byte = self.chunk.code[self.ip];
-        self.ip += 1;
+        let frame = self.frame_mut();
+        let byte = frame.closure.function.chunk.code[frame.ip];
+        frame.ip += 1;
         byte
     }
 
-    fn read_constant(&self, index: u8) -> Value {
-        self.chunk.constants[index as usize].clone()
+    /// Integer arithmetic, wrapping around or failing on overflow as configured
+    fn integer_op<T>(
+        &self,
+        a: i64,
+        b: T,
+        checked: fn(i64, T) -> Option<i64>,
+        wrapping: fn(i64, T) -> i64,
+    ) -> Result<i64, InterpreterError> {
+        if self.config.wrapping_integers {
+            Ok(wrapping(a, b))
+        } else {
+            checked(a, b).ok_or_else(|| self.runtime_error("Integer overflow."))
+        }
+    }
+
+    /// Stack index of local `slot` of the current frame, checked so corrupt bytecode can't
+    /// reach past the top of the stack
+    fn local_slot(&self, slot: u8) -> Result<usize, InterpreterError> {
+        self.slot_below(slot, self.stack.len())
+    }
+
+    /// Like `local_slot`, for a local a closure captures. A function declared in a local
+    /// scope captures its own slot, the one its closure is about to be pushed to
+    fn capture_slot(&self, slot: u8) -> Result<usize, InterpreterError> {
+        self.slot_below(slot, self.stack.len() + 1)
+    }
+
+    fn slot_below(&self, slot: u8, bound: usize) -> Result<usize, InterpreterError> {
+        let index = self.frame().slots + slot as usize;
+        if index >= bound {
+            let msg = format!(
+                "Local slot {} is outside the current frame of {} values.",
+                slot,
+                self.stack.len() - self.frame().slots
+            );
+            return Err(self.runtime_error(&msg));
+        }
+        Ok(index)
+    }
+
+    /// Fails if the divisor on top of the stack is zero and that's configured to be an error
+    fn check_divisor(&self) -> InterpreterResult {
+        if self.config.division_by_zero == DivisionByZero::Error
+            && self.peek()?.as_number() == Some(0.0)
+        {
+            return Err(self.runtime_error("Division by zero."));
+        }
+        Ok(())
+    }
+
+    fn read_short(&mut self) -> u16 {
+        u16::from_be_bytes([self.next_byte(), self.next_byte()])
+    }
+
+    /// `<<` and `>>`, failing for a count outside 0..64 rather than wrapping it around
+    fn shift(&mut self, op: Op) -> InterpreterResult {
+        if let Some(count) = truncate(self.peek()?) {
+            if !(0..64).contains(&count) {
+                let msg = format!("Shift count must be between 0 and 63 but got {}.", count);
+                return Err(self.runtime_error(&msg));
+            }
+        }
+        match op {
+            Op::ShiftLeft => bitwise_op!(self, |a, b| a << b),
+            _ => bitwise_op!(self, |a, b| a >> b),
+        }
+        Ok(())
+    }
+
+    /// Fails unless `len` more bytes of operands follow in the running chunk
+    fn check_operands(&self, len: usize) -> InterpreterResult {
+        let frame = self.frame();
+        if frame.ip + len > frame.closure.function.chunk.code.len() {
+            return Err(InterpreterError::InvalidBytecode("instruction cut off"));
+        }
+        Ok(())
+    }
+
+    fn jump_back(&mut self, offset: u16) -> InterpreterResult {
+        let frame = self.frame_mut();
+        frame.ip =
+            frame
+                .ip
+                .checked_sub(offset as usize)
+                .ok_or(InterpreterError::InvalidBytecode(
+                    "jump before the start of the chunk",
+                ))?;
+        Ok(())
+    }
+
+    /// The constant at `index`, which has to be a string
+    fn read_string(&self, index: usize) -> Result<AloxString, InterpreterError> {
+        self.read_constant(index)?
+            .as_string()
+            .ok_or(InterpreterError::WrongConstantType(index))
+    }
+
+    /// The running closure's upvalue `index`
+    fn upvalue(&self, index: u8) -> Result<Arc<ObjCell<ObjUpvalue>>, InterpreterError> {
+        self.frame()
+            .closure
+            .upvalues
+            .get(index as usize)
+            .cloned()
+            .ok_or(InterpreterError::BadUpvalue(index))
+    }
+
+    /// Reads the constant index operand of `op`, three bytes for a long form and one otherwise
+    fn read_index(&mut self, op: Op) -> usize {
+        if op.short_form().is_some() {
+            let bytes = [self.next_byte(), self.next_byte(), self.next_byte(), 0];
+            u32::from_le_bytes(bytes) as usize
+        } else {
+            self.next_byte() as usize
+        }
+    }
+
+    fn global_name(&self, slot: u16) -> Result<&str, InterpreterError> {
+        let name = self
+            .globals
+            .name(slot)
+            .ok_or(InterpreterError::BadGlobalSlot(slot))?;
+        Ok(self.interner.lookup(name.0))
+    }
+
+    /// Borrows a constant of the running function, callers clone it only to push it
+    fn read_constant(&self, index: usize) -> Result<&Value, InterpreterError> {
+        self.frame()
+            .closure
+            .function
+            .chunk
+            .constants
+            .get(index)
+            .ok_or(InterpreterError::BadConstantIndex(index))
+    }
+
+    fn index(&mut self, target: &Value, index: &Value) -> Result<Value, InterpreterError> {
+        match (target, index) {
+            (Value::Obj(Object::List(list)), Value::Obj(Object::Range(range))) => {
+                let (start, end) = self.slice_bounds(range, list.borrow().len())?;
+                Ok(Value::from_list(list.borrow()[start..end].to_vec()))
+            }
+            (Value::Obj(Object::String(string)), Value::Obj(Object::Range(range))) => {
+                let string = self.interner.lookup(string.0);
+                let (start, end) = self.slice_bounds(range, string.chars().count())?;
+                let slice: String = string.chars().skip(start).take(end - start).collect();
+                Ok(Value::from_str(&slice, &mut self.interner))
+            }
+            _ => self.index_element(target, index),
+        }
+    }
+
+    fn index_element(&mut self, target: &Value, index: &Value) -> Result<Value, InterpreterError> {
+        match target {
+            Value::Obj(Object::List(list)) => {
+                let slot = self.list_slot(index, list.borrow().len())?;
+                Ok(list.borrow()[slot].clone())
+            }
+            Value::Obj(Object::Tuple(items)) => {
+                let slot = self.list_slot(index, items.len())?;
+                Ok(items[slot].clone())
+            }
+            Value::Obj(Object::String(string)) => {
+                let string = self.interner.lookup(string.0);
+                let slot = self.list_slot(index, string.chars().count())?;
+                let c = string.chars().nth(slot).unwrap_or_default();
+                let c = self.interner.intern(c.encode_utf8(&mut [0; 4]));
+                Ok(Value::from_str_index(c))
+            }
+            Value::Obj(Object::Map(map)) => {
+                let key = self.map_key(index)?;
+                Ok(map.borrow().get(&key).cloned().unwrap_or(Value::Nil))
+            }
+            _ => Err(self.runtime_error("Only lists, maps, strings and tuples can be indexed.")),
+        }
+    }
+
+    fn map_key(&self, key: &Value) -> Result<MapKey, InterpreterError> {
+        MapKey::from_value(key).ok_or_else(|| {
+            self.runtime_error("Map keys must be numbers, strings, booleans or nil.")
+        })
+    }
+
+    /// Checks that `index` is a whole number within `0..len`
+    fn list_slot(&self, index: &Value, len: usize) -> Result<usize, InterpreterError> {
+        let index = match index.as_integer() {
+            Some(n) => n,
+            None => return Err(self.runtime_error("Index must be an integer.")),
+        };
+        if index < 0 || index >= len as i64 {
+            let msg = format!("Index {} out of bounds for length {}.", index, len);
+            return Err(self.runtime_error(&msg));
+        }
+        Ok(index as usize)
+    }
+
+    /// Checks that a slicing range is made of whole numbers within `0..=len`
+    fn slice_bounds(
+        &self,
+        range: &ObjRange,
+        len: usize,
+    ) -> Result<(usize, usize), InterpreterError> {
+        let (start, end) = (range.start, range.end_exclusive());
+        if start < 0.0 || start > end || end > len as f64 {
+            let msg = format!(
+                "Range {} out of bounds for length {}.",
+                Object::Range(*range),
+                len
+            );
+            return Err(self.runtime_error(&msg));
+        }
+        Ok((start as usize, end as usize))
+    }
+
+    fn build_range(&mut self, inclusive: bool) -> Result<(), InterpreterError> {
+        let end = self.pop()?;
+        let start = self.pop()?;
+        match (start.as_integer(), end.as_integer()) {
+            (Some(s), Some(e)) => {
+                let range = ObjRange {
+                    start: s as f64,
+                    end: e as f64,
+                    inclusive,
+                };
+                self.push(Value::Obj(Object::Range(range)));
+                Ok(())
+            }
+            _ => {
+                self.push(start);
+                self.push(end);
+                Err(self.runtime_error("Range bounds must be integers."))
+            }
+        }
+    }
+
+    /// Yields the next item of a for-in loop, list elements, numbers or single-character strings
+    fn advance_iterator(&mut self, iterator: &ObjCell<ObjIterator>) -> Option<Value> {
+        let mut iterator = iterator.borrow_mut();
+        let position = iterator.position;
+        match &iterator.source {
+            Value::Obj(Object::List(list)) => {
+                let item = list.borrow().get(position).cloned()?;
+                iterator.position += 1;
+                Some(item)
+            }
+            Value::Obj(Object::Tuple(items)) => {
+                let item = items.get(position).cloned()?;
+                iterator.position += 1;
+                Some(item)
+            }
+            Value::Obj(Object::Range(range)) => {
+                let n = range.nth(position)?;
+                iterator.position += 1;
+                Some(Value::Int(n as i64))
+            }
+            Value::Obj(Object::String(string)) => {
+                let c = self.interner.lookup(string.0)[position..].chars().next()?;
+                iterator.position += c.len_utf8();
+                let index = self.interner.intern(c.encode_utf8(&mut [0; 4]));
+                Some(Value::Obj(Object::String(AloxString(index))))
+            }
+            _ => None,
+        }
     }
 
+    /// Compiles the module at `path`, relative to the importing module or script as found by
+    /// `import_dir`, and calls its top-level code so its declarations land in the shared
+    /// globals. Each file runs once, however the paths naming it are spelled
+    fn import(&mut self, path: AloxString) -> InterpreterResult {
+        if !self.capabilities.io {
+            return Err(self.runtime_error("Importing modules is not allowed."));
+        }
+        let joined = match self.import_dir() {
+            Some(dir) => dir.join(self.interner.lookup(path.0)),
+            None => PathBuf::from(self.interner.lookup(path.0)),
+        };
+        // the module is known by its canonical path, errors show the one it was imported by
+        let canonical = std::fs::canonicalize(&joined).unwrap_or_else(|_| joined.clone());
+        let path = AloxString(self.interner.intern(&canonical.to_string_lossy()));
+        let file = joined.to_string_lossy().into_owned();
+        // registered before running, so circular imports stop instead of recursing
+        if !self.modules.insert(path.0) {
+            self.push(Value::Nil);
+            return Ok(());
+        }
+        let source = match std::fs::read_to_string(&file) {
+            Ok(source) => source,
+            Err(err) => {
+                // forgotten again, so importing it can be retried once the file is there
+                self.modules.remove(&path.0);
+                let msg = format!("Could not read module '{}': {}.", file, err);
+                return Err(self.runtime_error(&msg));
+            }
+        };
+        let chunk = match crate::compile(&source, &mut self.interner, &mut self.globals) {
+            Ok(chunk) => chunk,
+            Err(diagnostics) => {
+                self.modules.remove(&path.0);
+                let mut msg = format!("Could not compile module '{}'.", file);
+                for diagnostic in diagnostics {
+                    msg.push_str(&format!("\n  {}", diagnostic));
+                }
+                return Err(self.runtime_error(&msg));
+            }
+        };
+        let mut function = ObjFunction::new(Some(path));
+        function.chunk = chunk;
+        let closure = Arc::new(ObjClosure::new(Arc::new(function)));
+        self.push(Value::Obj(Object::Closure(closure.clone())));
+        self.call(closure, 0)
+    }
+
+    /// Directory imports are relative to, that of the innermost module being run or else of
+    /// the script
+    fn import_dir(&self) -> Option<PathBuf> {
+        let module = self.frames.iter().rev().find_map(|frame| {
+            let name = frame.closure.function.name?;
+            self.modules.contains(&name.0).then_some(name)
+        });
+        match module {
+            Some(name) => Path::new(self.interner.lookup(name.0))
+                .parent()
+                .map(Path::to_path_buf),
+            None => self.script_dir.clone(),
+        }
+    }
+
+    /// Unwinds to the innermost handler and resumes at its catch clause
+    fn throw(&mut self, exception: Value) -> InterpreterResult {
+        let handler = if self.handlers.len() > self.handler_floor {
+            self.handlers.pop()
+        } else {
+            None
+        };
+        let handler = match handler {
+            Some(handler) => handler,
+            None => {
+                let msg = format!("Uncaught exception: {}", self.stringify(&exception));
+                if !self.handlers.is_empty() {
+                    self.escaping = Some(exception);
+                }
+                return Err(self.runtime_error(&msg));
+            }
+        };
+        // coroutines unwound by the exception can't be resumed
+        for frame in &self.frames[handler.frame_count..] {
+            if let Some(coroutine) = &frame.coroutine {
+                coroutine.borrow_mut().state = CoroutineState::Done;
+            }
+        }
+        self.frames.truncate(handler.frame_count);
+        self.close_upvalues(handler.stack_len);
+        self.stack.truncate(handler.stack_len);
+        self.push(exception);
+        self.frame_mut().ip = handler.catch_ip;
+        Ok(())
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Arc<ObjCell<ObjUpvalue>> {
+        let existing = self
+            .open_upvalues
+            .iter()
+            .find(|upvalue| matches!(*upvalue.borrow(), ObjUpvalue::Open(index) if index == slot));
+        if let Some(upvalue) = existing {
+            return upvalue.clone();
+        }
+        let upvalue = Arc::new(ObjCell::new(ObjUpvalue::Open(slot)));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    /// Like `close_upvalues`, for a coroutine suspending with its window starting at `base`.
+    /// Returns the upvalues with the offset of their local, to open again on resuming
+    fn suspend_upvalues(&mut self, base: usize) -> Vec<(Arc<ObjCell<ObjUpvalue>>, usize)> {
+        let mut suspended = Vec::new();
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let index = match *upvalue.borrow() {
+                ObjUpvalue::Open(index) if index >= base => index,
+                _ => return true,
+            };
+            *upvalue.borrow_mut() = ObjUpvalue::Closed(stack[index].clone());
+            suspended.push((upvalue.clone(), index - base));
+            false
+        });
+        suspended
+    }
+
+    /// Moves every captured variable at or above `last` off the stack and into its upvalue
+    fn close_upvalues(&mut self, last: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let mut upvalue = upvalue.borrow_mut();
+            match *upvalue {
+                ObjUpvalue::Open(index) if index >= last => {
+                    *upvalue = ObjUpvalue::Closed(stack[index].clone());
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+
+    /// Calls the operator method `left` when the left operand is an instance defining it, or
+    /// else `right` when the right operand is one, returning whether the call was made
+    fn invoke_operator(&mut self, (left, right): (usize, usize)) -> Result<bool, InterpreterError> {
+        // the left operand becomes `this` and the right one the argument
+        if let Some(method) = self.operator_method(self.peek_by(1)?, left) {
+            return self.call(method, 1).map(|_| true);
+        }
+        // failing that the right operand's method, with the operands swapped
+        if let Some(method) = self.operator_method(self.peek_by(0)?, right) {
+            let len = self.stack.len();
+            self.stack.swap(len - 1, len - 2);
+            return self.call(method, 1).map(|_| true);
+        }
+        Ok(false)
+    }
+
+    /// The method `value` has named by `OPERATOR_METHODS[index]`, if it is an instance
+    fn operator_method(&self, value: &Value, index: usize) -> Option<Arc<ObjClosure>> {
+        let instance = match value {
+            Value::Obj(Object::Instance(instance)) => instance,
+            _ => return None,
+        };
+        let method = instance
+            .borrow()
+            .class
+            .borrow()
+            .methods
+            .get(&self.operator_names[index])
+            .cloned();
+        match method {
+            Some(Value::Obj(Object::Closure(method))) => Some(method),
+            _ => None,
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> InterpreterResult {
+        match callee {
+            Value::Obj(Object::Closure(closure)) => self.call(closure, arg_count),
+            Value::Obj(Object::Native(native)) => {
+                if arg_count < native.arity || (arg_count > native.arity && !native.variadic) {
+                    let msg = if native.variadic {
+                        format!(
+                            "Expected at least {} arguments but got {}.",
+                            native.arity, arg_count
+                        )
+                    } else {
+                        format!("Expected {} arguments but got {}.", native.arity, arg_count)
+                    };
+                    return Err(self.runtime_error(&msg));
+                }
+                self.flatten_top(arg_count);
+                // off the stack, which the native can use by calling back into the VM
+                let args: Vec<Value> = self.stack.drain(self.stack.len() - arg_count..).collect();
+                self.stack.pop();
+                let mut context = NativeContext::new(self);
+                let result = (native.function)(&mut context, &args);
+                let failure = context.into_failure();
+                if let (Err(_), Some(exception)) = (&result, self.escaping.take()) {
+                    return self.throw(exception);
+                }
+                let result = result.map_err(|err| match (failure, err) {
+                    (Some(failure), _) => failure,
+                    (None, NativeError::Message(msg)) => self.runtime_error(&msg),
+                    (None, NativeError::Exit(code)) => InterpreterError::Exit(code),
+                })?;
+                self.push(result);
+                Ok(())
+            }
+            Value::Obj(Object::Coroutine(coroutine)) => {
+                if arg_count > 1 {
+                    let msg = format!("Expected 0 to 1 arguments but got {}.", arg_count);
+                    return Err(self.runtime_error(&msg));
+                }
+                let sent = if arg_count == 1 {
+                    self.pop()?
+                } else {
+                    Value::Nil
+                };
+                let slot = self.stack.len() - 1;
+                self.resume(coroutine, slot, sent, None)
+            }
+            Value::Obj(Object::BoundMethod(bound)) => {
+                let slot = self.stack.len() - arg_count - 1;
+                self.stack[slot] = bound.receiver.clone();
+                self.call(bound.method.clone(), arg_count)
+            }
+            Value::Obj(Object::Class(class)) => {
+                let initializer = class.borrow().methods.get(&self.init_string).cloned();
+                let instance = ObjInstance::new(class);
+                let slot = self.stack.len() - arg_count - 1;
+                self.stack[slot] = Value::Obj(Object::Instance(Arc::new(ObjCell::new(instance))));
+                if let Some(Value::Obj(Object::Closure(initializer))) = initializer {
+                    self.call(initializer, arg_count)
+                } else if arg_count != 0 {
+                    let msg = format!("Expected 0 arguments but got {}.", arg_count);
+                    Err(self.runtime_error(&msg))
+                } else {
+                    Ok(())
+                }
+            }
+            _ => Err(self.runtime_error("Can only call functions and classes.")),
+        }
+    }
+
+    fn call(&mut self, closure: Arc<ObjClosure>, arg_count: usize) -> InterpreterResult {
+        let function = &closure.function;
+        let required = function.arity - function.optional;
+        if arg_count < required || (arg_count > function.arity && !function.variadic) {
+            let msg = if function.variadic {
+                format!(
+                    "Expected at least {} arguments but got {}.",
+                    required, arg_count
+                )
+            } else if function.optional == 0 {
+                format!(
+                    "Expected {} arguments but got {}.",
+                    function.arity, arg_count
+                )
+            } else {
+                format!(
+                    "Expected {} to {} arguments but got {}.",
+                    required, function.arity, arg_count
+                )
+            };
+            return Err(self.runtime_error(&msg));
+        }
+        // deep recursion is caught here, before the stack grows without bound
+        if self.frames.len() >= self.config.max_frames || self.stack.len() > self.config.max_stack {
+            return Err(self.runtime_error("Stack overflow."));
+        }
+        let arity = closure.function.arity;
+        let rest = if arg_count > arity {
+            self.stack.split_off(self.stack.len() - (arg_count - arity))
+        } else {
+            Vec::new()
+        };
+        // placeholders for omitted arguments, filled in by the default value prologue
+        for _ in arg_count..arity {
+            self.push(Value::Nil);
+        }
+        let mut params = arity;
+        if closure.function.variadic {
+            self.push(Value::from_list(rest));
+            params += 1;
+        }
+        let slots = self.stack.len() - params - 1;
+        if closure.function.is_generator {
+            // the arguments wait in the coroutine until it's first resumed
+            let window = self.stack.split_off(slots);
+            let coroutine = ObjCoroutine::new(closure, window, arg_count.min(arity));
+            self.push(Value::Obj(Object::Coroutine(Arc::new(ObjCell::new(
+                coroutine,
+            )))));
+            return Ok(());
+        }
+        self.frames.push(CallFrame {
+            ip: 0,
+            slots,
+            arg_count: arg_count.min(arity),
+            closure,
+            coroutine: None,
+            loop_exit: None,
+        });
+        Ok(())
+    }
+
+    /// Continues `coroutine` in a frame whose window starts at `slots`, replacing whatever is
+    /// stacked there. `sent` becomes the result of the `yield` it stopped at
+    fn resume(
+        &mut self,
+        coroutine: Arc<ObjCell<ObjCoroutine>>,
+        slots: usize,
+        sent: Value,
+        loop_exit: Option<usize>,
+    ) -> InterpreterResult {
+        let state = coroutine.borrow().state;
+        match state {
+            CoroutineState::Running => {
+                return Err(self.runtime_error("Can't resume a running coroutine."))
+            }
+            CoroutineState::Done => {
+                return Err(self.runtime_error("Can't resume a finished coroutine."))
+            }
+            CoroutineState::Fresh | CoroutineState::Suspended => {}
+        }
+        if self.frames.len() >= self.config.max_frames || self.stack.len() > self.config.max_stack {
+            return Err(self.runtime_error("Stack overflow."));
+        }
+        let (closure, ip, arg_count) = {
+            let mut suspended = coroutine.borrow_mut();
+            suspended.state = CoroutineState::Running;
+            self.stack.truncate(slots);
+            self.stack.append(&mut suspended.stack);
+            // closures may have changed the captured locals while it was suspended
+            for (upvalue, offset) in suspended.upvalues.drain(..) {
+                let slot = slots + offset;
+                let held = std::mem::replace(&mut *upvalue.borrow_mut(), ObjUpvalue::Open(slot));
+                if let ObjUpvalue::Closed(value) = held {
+                    self.stack[slot] = value;
+                }
+                self.open_upvalues.push(upvalue);
+            }
+            (suspended.closure.clone(), suspended.ip, suspended.arg_count)
+        };
+        if state == CoroutineState::Suspended {
+            self.push(sent);
+        }
+        self.frames.push(CallFrame {
+            closure,
+            ip,
+            slots,
+            arg_count,
+            coroutine: Some(coroutine),
+            loop_exit,
+        });
+        Ok(())
+    }
+
+    /// A runtime error for operands of the wrong type, naming the types they had, as
+    /// "Operands must be numbers, got string and nil."
+    fn operand_error(&self, message: &str, operands: &[&Value]) -> InterpreterError {
+        let types: Vec<&str> = operands.iter().map(|operand| operand.type_name()).collect();
+        self.runtime_error(&format!("{}, got {}.", message, types.join(" and ")))
+    }
+
+    /// Builds an error from `message` followed by a trace of every active call, innermost
+    /// first
     fn runtime_error(&self, message: &str) -> InterpreterError {
-        let line = self.chunk.lines[self.ip - 1];
-        let place = format!("[line {}] in script", line);
-        InterpreterError::RuntimeError(format!("{}\n{}", place, message))
+        let mut report = message.to_string();
+        for frame in self.frames.iter().rev() {
+            let function = &frame.closure.function;
+            // ip has moved past the instruction that failed, or past the call in outer frames
+            let line = function
+                .chunk
+                .line_at(frame.ip.saturating_sub(1))
+                .unwrap_or_default();
+            let place = match function.name {
+                Some(name) => format!("[line {}] in {}()", line, self.interner.lookup(name.0)),
+                None => format!("[line {}] in script", line),
+            };
+            report.push('\n');
+            report.push_str(&place);
+        }
+        InterpreterError::RuntimeError(report)
     }
 
     #[inline]
@@ -230,31 +2200,77 @@ impl<'vm> Vm<'vm> {
     }
 
     #[inline]
-    fn print_val(&self, val: Value) {
-        match val {
-            Value::Obj(obj) => match obj {
-                Object::String(idx) => println!("{}", self.interner.lookup(idx.0)),
-            },
-            _other => println!("{}", _other),
+    fn print_val(&mut self, val: Value) -> io::Result<()> {
+        let text = self.stringify(&val);
+        writeln!(self.output, "{}", text)
+    }
+
+    /// `a + b` where at least one side is a string, appending to `a`'s buffer when it has one
+    fn concatenate(&self, a: &Value, b: &Value) -> Value {
+        let suffix = self.stringify(b);
+        let buffer = match a {
+            Value::Obj(Object::StringBuffer(buffer)) => buffer.append(&suffix),
+            _ => ObjStringBuffer::new(self.stringify(a) + &suffix),
+        };
+        Value::Obj(Object::StringBuffer(Arc::new(buffer)))
+    }
+
+    /// Interns the concatenation buffers among the top `count` values, before an op compares,
+    /// hashes or stores them
+    fn flatten_top(&mut self, count: usize) {
+        let start = self.stack.len().saturating_sub(count);
+        for slot in start..self.stack.len() {
+            if let Value::Obj(Object::StringBuffer(_)) = &self.stack[slot] {
+                let value = std::mem::replace(&mut self.stack[slot], Value::Nil);
+                self.stack[slot] = self.flatten(value);
+            }
         }
     }
 
-    #[cfg(debug_assertions)]
-    fn dbg_show_stack(&self) {
-        println!("Stack: {:?}", &self.stack);
+    /// `value`, interned if it is a concatenation buffer
+    fn flatten(&mut self, value: Value) -> Value {
+        let buffer = match value {
+            Value::Obj(Object::StringBuffer(buffer)) => buffer,
+            value => return value,
+        };
+        let string = *buffer
+            .interned
+            .get_or_init(|| AloxString(self.interner.intern(&buffer.contents())));
+        Value::Obj(Object::String(string))
+    }
+
+    /// Renders a value the way `print` shows it
+    fn stringify(&self, val: &Value) -> String {
+        val.display(&self.interner)
+    }
+
+    fn dbg_show_stack(&mut self) -> io::Result<()> {
+        writeln!(self.trace_writer, "Stack: {:?}", &self.stack)
     }
 
-    #[cfg(debug_assertions)]
-    fn dbg_dissamble_instructions(&self) {
-        self.chunk
-            .disassemble_instruction(self.ip - 1, &self.interner);
+    fn dbg_dissamble_instructions(&mut self) -> io::Result<()> {
+        let frame = self.frames.last().expect(NO_FRAME);
+        let mut line = String::new();
+        frame
+            .closure
+            .function
+            .chunk
+            .write_instruction(&mut line, frame.ip - 1, &self.interner)
+            .expect("writing to a String can't fail");
+        self.trace_writer.write_all(line.as_bytes())
     }
 
-    #[cfg(debug_assertions)]
-    fn dbg_show_globals(&self) {
-        if !self.globals.is_empty() {
-            println!("Globals: {:?}", &self.globals);
+    fn dbg_show_globals(&mut self) -> io::Result<()> {
+        let globals: AHashMap<_, _> = self
+            .globals
+            .iter()
+            .map(|(name, value)| (self.interner.lookup(name.0), value))
+            .collect();
+        if !globals.is_empty() {
+            let globals = format!("{:?}", globals);
+            writeln!(self.trace_writer, "Globals: {}", globals)?;
         }
+        Ok(())
     }
 }
 
@@ -263,7 +2279,38 @@ pub enum InterpreterError {
     CompileError,
     RuntimeError(String),
     NoInstructions,
-    UnknownInstruction,
+    /// An instruction needed more values than the stack held
+    StackUnderflow,
+    /// A constant operand pointed past the end of the chunk's constant table
+    BadConstantIndex(usize),
+    /// A global operand pointed at a slot no name was ever resolved to
+    BadGlobalSlot(u16),
+    UnknownOpcode(u8),
+    /// A constant operand named a value of the wrong kind, such as a class name that isn't
+    /// a string
+    WrongConstantType(usize),
+    /// An `IsType` operand that isn't a `ValueType`
+    BadValueType(u8),
+    /// An upvalue operand pointed past the upvalues of the running closure
+    BadUpvalue(u8),
+    /// The bytecode reached a state the compiler never leads to
+    InvalidBytecode(&'static str),
+    /// The instruction budget given to `Vm::with_fuel` ran out
+    FuelExhausted,
+    /// An `InterruptHandle` asked the VM to stop
+    Interrupted,
+    /// Writing output, such as the trace, failed
+    Io(io::Error),
+    /// The script called `exit` with this code
+    Exit(i32),
+    /// A host defined a global when every slot was taken
+    TooManyGlobals,
+}
+
+impl From<io::Error> for InterpreterError {
+    fn from(err: io::Error) -> Self {
+        InterpreterError::Io(err)
+    }
 }
 
 impl Display for InterpreterError {
@@ -272,7 +2319,25 @@ impl Display for InterpreterError {
             InterpreterError::CompileError => write!(f, "Compilation error!"),
             InterpreterError::RuntimeError(err) => write!(f, "Runtime error: {}", err),
             InterpreterError::NoInstructions => write!(f, "No instructions!"),
-            InterpreterError::UnknownInstruction => write!(f, "Unkown instruction!"),
+            InterpreterError::StackUnderflow => write!(f, "Stack underflow!"),
+            InterpreterError::BadConstantIndex(index) => {
+                write!(f, "Constant index {} out of range!", index)
+            }
+            InterpreterError::BadGlobalSlot(slot) => {
+                write!(f, "Global slot {} was never resolved!", slot)
+            }
+            InterpreterError::UnknownOpcode(byte) => write!(f, "Unknown opcode {}!", byte),
+            InterpreterError::WrongConstantType(index) => {
+                write!(f, "Constant {} has the wrong type!", index)
+            }
+            InterpreterError::BadValueType(byte) => write!(f, "Unknown value type {}!", byte),
+            InterpreterError::BadUpvalue(index) => write!(f, "Upvalue {} out of range!", index),
+            InterpreterError::InvalidBytecode(reason) => write!(f, "Invalid bytecode: {}!", reason),
+            InterpreterError::FuelExhausted => write!(f, "Ran out of fuel!"),
+            InterpreterError::Interrupted => write!(f, "Interrupted!"),
+            InterpreterError::Io(err) => write!(f, "I/O error: {}", err),
+            InterpreterError::Exit(code) => write!(f, "Exited with code {}.", code),
+            InterpreterError::TooManyGlobals => write!(f, "Too many global variables!"),
         }
     }
 }
@@ -0,0 +1,36 @@
+use wasm_bindgen::prelude::*;
+
+use crate::run_script_with_output;
+
+/// What a script printed, and the error that stopped it if any
+#[wasm_bindgen]
+pub struct ScriptResult {
+    output: String,
+    error: Option<String>,
+}
+
+#[wasm_bindgen]
+impl ScriptResult {
+    #[wasm_bindgen(getter)]
+    pub fn output(&self) -> String {
+        self.output.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+}
+
+/// Compiles and runs `source`, capturing its output for the page to show
+#[wasm_bindgen(js_name = runScript)]
+pub fn run_script(source: &str) -> ScriptResult {
+    let mut output = Vec::new();
+    let error = run_script_with_output(source, &mut output)
+        .err()
+        .map(|err| err.to_string());
+    ScriptResult {
+        output: String::from_utf8_lossy(&output).into_owned(),
+        error,
+    }
+}
@@ -0,0 +1,25 @@
+mod common;
+
+use common::{error, run};
+
+#[test]
+fn setters_run_on_assignment() {
+    let source = r#"
+        class T {
+            get x { return this._x; }
+            set x(value) { this._x = value * 2; }
+        }
+        var t = T();
+        t.x = 3;
+        print t.x;
+    "#;
+    assert_eq!(run(source), ("6\n".to_string(), Ok(())));
+}
+
+#[test]
+fn properties_with_only_a_getter_cant_be_assigned() {
+    assert_eq!(
+        error("class N { get x { return 1; } } var n = N(); n.x = 3;"),
+        "Runtime error: Property 'x' has no setter."
+    );
+}
@@ -0,0 +1,9 @@
+mod common;
+
+use common::run;
+
+#[test]
+fn functions_in_a_local_scope_can_call_themselves() {
+    let source = "{ fun c(n) { if (n == 0) return 0; return c(n - 1); } print c(3); }";
+    assert_eq!(run(source), ("0\n".to_string(), Ok(())));
+}
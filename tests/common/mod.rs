@@ -0,0 +1,23 @@
+// each test crate uses its own share of the helpers
+#![allow(dead_code)]
+
+use alox_bytecode::load_script;
+
+/// Runs `source`, returning what it printed and the error it stopped with, if any
+pub fn run(source: &str) -> (String, Result<(), String>) {
+    let mut output = Vec::new();
+    let result = {
+        let mut vm = load_script(source)
+            .expect("script compiles")
+            .with_output(&mut output);
+        vm.run().map_err(|err| err.to_string())
+    };
+    (String::from_utf8(output).expect("output is UTF-8"), result)
+}
+
+/// The first line of the error `source` stops with, without the stack trace
+pub fn error(source: &str) -> String {
+    let (_, result) = run(source);
+    let error = result.expect_err("script fails");
+    error.lines().next().unwrap_or_default().to_string()
+}
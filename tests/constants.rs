@@ -0,0 +1,64 @@
+mod common;
+
+use alox_bytecode::{chunk::Chunk, compile, globals::Globals, interner::Interner, vm::Vm};
+use common::run;
+
+/// A script whose first 300 constants are numbers, so later ones need long operands
+fn past_256_constants(rest: &str) -> String {
+    let mut source: String = (0..300)
+        .map(|n| format!("var n{} = {}.5;\n", n, n))
+        .collect();
+    source.push_str(rest);
+    source
+}
+
+const CLASSES: &str = r#"
+    class A {
+        init(x) { this.x = x; }
+        m() { return this.x + 1; }
+        get double { return this.x * 2; }
+        set double(value) { this.x = value / 2; }
+    }
+    var a = A(20);
+    print a.m();
+    a.x += 1;
+    print a.x;
+    a.double = 10;
+    print a.double;
+    print a?.x;
+    var y = 3;
+    fun add(z) { return y + z; }
+    print add(4);
+    assert a.x == 5, "x";
+"#;
+
+#[test]
+fn classes_and_properties_work_past_256_constants() {
+    let (output, result) = run(&past_256_constants(CLASSES));
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "21\n21\n10\n5\n7\n");
+}
+
+#[test]
+fn long_operands_verify_and_survive_serializing() {
+    let mut interner = Interner::new();
+    let mut globals = Globals::new();
+    let source = past_256_constants(CLASSES);
+    let chunk = compile(&source, &mut interner, &mut globals).expect("script compiles");
+    assert_eq!(chunk.verify(&interner), Ok(()));
+    assert!(chunk
+        .disassemble("<script>", &interner)
+        .contains("ClassLong"));
+
+    let bytes = chunk.serialize(&interner, &globals);
+    let mut interner = Interner::new();
+    let mut globals = Globals::new();
+    let chunk = Chunk::deserialize(&bytes, &mut interner, &mut globals).expect("loads");
+    let mut output = Vec::new();
+    let result = Vm::new(chunk, interner, globals)
+        .with_output(&mut output)
+        .run()
+        .map_err(|err| err.to_string());
+    assert_eq!(result, Ok(()));
+    assert_eq!(String::from_utf8(output).unwrap(), "21\n21\n10\n5\n7\n");
+}
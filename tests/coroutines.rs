@@ -0,0 +1,40 @@
+mod common;
+
+use common::run;
+
+#[test]
+fn closures_share_locals_with_a_suspended_coroutine() {
+    let (output, result) = run(r#"
+        fun g() {
+            var a = 1;
+            var f = fun() { return a; };
+            yield f;
+            a = 2;
+            yield f;
+        }
+        var co = g();
+        var f = co();
+        print f();
+        co();
+        print f();
+    "#);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "1\n2\n");
+}
+
+#[test]
+fn a_suspended_coroutine_sees_what_closures_wrote() {
+    let (output, result) = run(r#"
+        fun g() {
+            var a = 1;
+            yield fun(value) { a = value; };
+            yield a;
+        }
+        var co = g();
+        var set = co();
+        set(5);
+        print co();
+    "#);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "5\n");
+}
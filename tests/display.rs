@@ -0,0 +1,44 @@
+mod common;
+
+use alox_bytecode::load_script;
+use common::run;
+
+#[test]
+fn lists_holding_themselves_print_once() {
+    let (output, result) = run("var l = [1]; push(l, l); print l;");
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "[1, [...]]\n");
+}
+
+#[test]
+fn maps_holding_themselves_print_once() {
+    let (output, result) = run(r#"var m = {}; m["a"] = m; print m;"#);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "{a: {...}}\n");
+}
+
+#[test]
+fn containers_shared_without_a_cycle_print_in_full() {
+    let (output, result) = run(r#"
+        var inner = [1];
+        var m = {};
+        m["x"] = [inner, inner, (inner,)];
+        var l = [m];
+        push(l, [l]);
+        print m;
+        print l;
+    "#);
+    assert_eq!(result, Ok(()));
+    assert_eq!(
+        output,
+        "{x: [[1], [1], ([1],)]}\n[{x: [[1], [1], ([1],)]}, [[...]]]\n"
+    );
+}
+
+#[test]
+fn debug_output_of_a_cycle_ends() {
+    let mut vm = load_script("var l = [1]; push(l, l);").expect("script compiles");
+    vm.run().expect("script runs");
+    let list = vm.get_global("l").expect("l is defined");
+    assert!(format!("{:?}", list).contains("..."));
+}
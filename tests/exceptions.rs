@@ -0,0 +1,78 @@
+mod common;
+
+use common::{error, run};
+
+#[test]
+fn exceptions_thrown_by_a_map_callback_reach_the_script() {
+    let (output, result) = run(r#"
+        try {
+            map([1], fun(x) { throw "e"; });
+        } catch (e) {
+            print e;
+        }
+        print "after";
+    "#);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "e\nafter\n");
+}
+
+#[test]
+fn exceptions_thrown_by_a_filter_callback_reach_the_script() {
+    let (output, result) = run(r#"
+        fun check(x) {
+            if (x > 1) throw x;
+            return true;
+        }
+        try {
+            print filter([1, 2, 3], check);
+        } catch (e) {
+            print "caught " + str(e);
+        }
+    "#);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "caught 2\n");
+}
+
+#[test]
+fn callbacks_catch_their_own_exceptions() {
+    let (output, result) = run(r#"
+        try {
+            print map([1, 2], fun(x) {
+                try { throw x; } catch (e) { return e * 10; }
+            });
+        } catch (e) {
+            print "outer";
+        }
+    "#);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "[10, 20]\n");
+}
+
+#[test]
+fn exceptions_cross_nested_callbacks() {
+    let (output, result) = run(r#"
+        try {
+            map([1], fun(x) { return map([x], fun(y) { throw "deep"; }); });
+        } catch (e) {
+            print e;
+        }
+    "#);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "deep\n");
+}
+
+#[test]
+fn uncaught_exceptions_in_callbacks_stay_errors() {
+    assert_eq!(
+        error(r#"map([1], fun(x) { throw "e"; });"#),
+        "Runtime error: Uncaught exception: e"
+    );
+}
+
+#[test]
+fn runtime_errors_in_callbacks_are_not_caught() {
+    assert_eq!(
+        error(r#"try { map([1], fun(x) { return x + nil; }); } catch (e) { print e; }"#),
+        "Runtime error: Operands must be two numbers or two strings, got int and nil."
+    );
+}
@@ -0,0 +1,60 @@
+use alox_bytecode::{
+    compile,
+    globals::Globals,
+    interner::Interner,
+    object::{AloxString, Object},
+    value::Value,
+    vm::{InterpreterError, Vm},
+};
+
+#[test]
+fn hosts_seed_and_read_globals() {
+    let mut interner = Interner::new();
+    let mut globals = Globals::new();
+    let chunk = compile("var doubled = limit * 2;", &mut interner, &mut globals).unwrap();
+    let mut vm = Vm::new(chunk, interner, globals);
+    vm.set_global("limit", Value::Int(21))
+        .expect("room for a global");
+    vm.run().expect("script runs");
+    assert_eq!(vm.get_global("doubled"), Some(Value::Int(42)));
+}
+
+#[test]
+fn concatenated_strings_equal_literals_on_the_host() {
+    let source = r#"
+        var a = "x";
+        var s = a + "y";
+        var t = "xy";
+        fun join(left, right) { return left + right; }
+    "#;
+    let mut interner = Interner::new();
+    let mut globals = Globals::new();
+    let chunk = compile(source, &mut interner, &mut globals).unwrap();
+    let mut vm = Vm::new(chunk, interner, globals);
+    vm.run().expect("script runs");
+    let t = vm.get_global("t").expect("t is defined");
+    assert!(matches!(t, Value::Obj(Object::String(_))));
+    assert_eq!(vm.get_global("s"), Some(t.clone()));
+    let args = [vm.get_global("a").unwrap(), vm.to_value("y")];
+    assert_eq!(vm.call_function("join", &args).ok(), Some(t));
+}
+
+#[test]
+fn a_full_global_table_is_an_error() {
+    let mut interner = Interner::new();
+    let mut globals = Globals::new();
+    let chunk = compile("print 1;", &mut interner, &mut globals).unwrap();
+    for n in 0..=u16::MAX as usize {
+        let name = AloxString(interner.intern(&format!("g{}", n)));
+        globals.resolve(name).expect("room for a global");
+    }
+    let mut vm = Vm::new(chunk, interner, globals).with_output(Vec::new());
+    assert!(matches!(
+        vm.set_global("extra", Value::Nil),
+        Err(InterpreterError::TooManyGlobals)
+    ));
+    // names that already have a slot can still be set
+    assert!(vm.set_global("g7", Value::Int(7)).is_ok());
+    assert_eq!(vm.get_global("g7"), Some(Value::Int(7)));
+    vm.run().expect("script runs");
+}
@@ -0,0 +1,78 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use alox_bytecode::load_script;
+
+/// A fresh directory for the files of one test
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("alox-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("lib")).expect("creates the scratch directory");
+    dir
+}
+
+#[test]
+fn imports_are_found_next_to_the_importing_script() {
+    let dir = scratch_dir("imports");
+    fs::write(dir.join("main.lox"), r#"import "lib/a.lox"; print a + b;"#).unwrap();
+    // the module's own import is relative to lib/, not to main.lox
+    fs::write(dir.join("lib/a.lox"), r#"import "b.lox"; var a = 1;"#).unwrap();
+    fs::write(dir.join("lib/b.lox"), "var b = 2;").unwrap();
+    assert_ne!(std::env::current_dir().unwrap(), dir);
+    assert_eq!(run_main(&dir), ("3\n".to_string(), Ok(())));
+}
+
+#[test]
+fn a_module_runs_once_however_its_path_is_spelled() {
+    let dir = scratch_dir("spellings");
+    let main = r#"
+        import "lib/c.lox";
+        import "./lib/c.lox";
+        import "lib/../lib/c.lox";
+        print c;
+    "#;
+    fs::write(dir.join("main.lox"), main).unwrap();
+    fs::write(dir.join("lib/c.lox"), r#"print "running c"; var c = 3;"#).unwrap();
+    assert_eq!(run_main(&dir), ("running c\n3\n".to_string(), Ok(())));
+}
+
+#[test]
+fn a_module_that_failed_to_load_can_be_imported_again() {
+    let dir = scratch_dir("retry");
+    let mut output = Vec::new();
+    {
+        let mut vm = load_script("")
+            .expect("script compiles")
+            .with_script_path(dir.join("main.lox"))
+            .with_output(&mut output);
+        let import = r#"import "lib/d.lox";"#;
+        let err = vm.interpret_line(import).unwrap_err().to_string();
+        assert!(err.contains("Could not read module"), "{}", err);
+        fs::write(dir.join("lib/d.lox"), "var d = ;").unwrap();
+        let err = vm.interpret_line(import).unwrap_err().to_string();
+        assert!(err.contains("Could not compile module"), "{}", err);
+        fs::write(dir.join("lib/d.lox"), "var d = 4;").unwrap();
+        vm.interpret_line(import)
+            .expect("the module loads this time");
+        vm.interpret_line("print d;").unwrap();
+    }
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), "4\n");
+}
+
+/// Runs `main.lox` in `dir`, then removes `dir`
+fn run_main(dir: &Path) -> (String, Result<(), String>) {
+    let source = fs::read_to_string(dir.join("main.lox")).unwrap();
+    let mut output = Vec::new();
+    let result = {
+        let mut vm = load_script(&source)
+            .expect("script compiles")
+            .with_script_path(dir.join("main.lox"))
+            .with_output(&mut output);
+        vm.run().map_err(|err| err.to_string())
+    };
+    fs::remove_dir_all(dir).unwrap();
+    (String::from_utf8(output).unwrap(), result)
+}
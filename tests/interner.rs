@@ -0,0 +1,34 @@
+use alox_bytecode::interner::{Interner, SharedInterner};
+
+#[test]
+fn shared_strings_are_looked_up_without_syncing() {
+    let shared = SharedInterner::new();
+    let mut first = Interner::with_shared(shared.clone());
+    let second = Interner::with_shared(shared.clone());
+    let idx = first.intern("late");
+    assert_eq!(second.lookup(idx), "late");
+    assert_eq!(second.hash(idx), first.hash(idx));
+    assert_eq!(shared.len(), 1);
+}
+
+#[test]
+fn syncing_takes_in_only_new_strings() {
+    let shared = SharedInterner::new();
+    let mut first = Interner::with_shared(shared.clone());
+    let mut second = Interner::with_shared(shared);
+    let a = first.intern("a");
+    second.sync();
+    let b = first.intern("b");
+    second.sync();
+    assert!(second.exists("a") && second.exists("b"));
+    assert_eq!(second.intern("b"), b);
+    assert_eq!(second.intern("a"), a);
+}
+
+#[test]
+fn unknown_indices_look_up_as_empty() {
+    let interner = Interner::new();
+    assert_eq!(interner.lookup(7), "");
+    let shared = Interner::with_shared(SharedInterner::new());
+    assert_eq!(shared.lookup(7), "");
+}
@@ -0,0 +1,12 @@
+mod common;
+
+use common::run;
+
+#[test]
+fn maps_are_walked_in_sorted_key_order() {
+    let source = r#"
+        var m = {"b": 1, "a": 2, "d": 3, "c": 4, "f": 5, "e": 6};
+        for (var k in m) print k;
+    "#;
+    assert_eq!(run(source), ("a\nb\nc\nd\ne\nf\n".to_string(), Ok(())));
+}
@@ -0,0 +1,26 @@
+use alox_bytecode::{builder::VmBuilder, compile, globals::Globals, interner::Interner};
+
+/// Runs `source` with room for `max_stack` values, returning its error
+fn run_with_stack(source: &str, max_stack: usize) -> Result<(), String> {
+    let mut interner = Interner::new();
+    let mut globals = Globals::new();
+    let chunk = compile(source, &mut interner, &mut globals).expect("script compiles");
+    let mut vm = VmBuilder::new()
+        .max_stack(max_stack)
+        .output(Vec::new())
+        .build(chunk, interner, globals);
+    vm.run().map_err(|err| err.to_string())
+}
+
+#[test]
+fn the_stack_limit_holds_within_one_frame() {
+    let items: Vec<String> = (1..=20).map(|n| n.to_string()).collect();
+    let source = format!("print [{}];", items.join(", "));
+    assert_eq!(run_with_stack(&source, 32), Ok(()));
+    let error = run_with_stack(&source, 8).expect_err("the stack overflows");
+    assert!(
+        error.starts_with("Runtime error: Stack overflow."),
+        "{}",
+        error
+    );
+}
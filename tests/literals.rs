@@ -0,0 +1,19 @@
+mod common;
+
+use alox_bytecode::load_script;
+use common::run;
+
+#[test]
+fn decimal_literals_too_large_for_an_integer_are_numbers() {
+    let (output, result) = run("print 99999999999999999999; print 9223372036854775807;");
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "100000000000000000000\n9223372036854775807\n");
+}
+
+#[test]
+fn hex_and_binary_literals_fill_all_64_bits() {
+    let (output, result) = run("print 0xFFFFFFFFFFFFFFFF; print 0x8000000000000000 == 1 << 63;");
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "-1\ntrue\n");
+    assert!(load_script("print 0x1FFFFFFFFFFFFFFFF;").is_err());
+}
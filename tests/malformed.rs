@@ -0,0 +1,130 @@
+use alox_bytecode::{
+    chunk::Chunk,
+    globals::Globals,
+    interner::Interner,
+    vm::{TraceOptions, Vm},
+};
+
+/// Runs a chunk assembled from `assembly`, which is never verified, returning its error
+fn run_unverified(assembly: &str) -> String {
+    let mut interner = Interner::new();
+    let chunk = Chunk::from_assembly(assembly, &mut interner).expect("assembles");
+    let mut vm = Vm::new(chunk, interner, Globals::new()).with_output(Vec::new());
+    vm.run().expect_err("bytecode is rejected").to_string()
+}
+
+#[test]
+fn names_that_are_not_strings_are_errors() {
+    for op in ["Class", "Import", "GetProperty"] {
+        let assembly = format!(".constants\n 1\n.code\n Nil\n Nil\n {} 0\n", op);
+        assert_eq!(run_unverified(&assembly), "Constant 0 has the wrong type!");
+    }
+}
+
+#[test]
+fn closures_of_a_constant_that_is_not_a_function_are_errors() {
+    let assembly = ".constants\n \"f\"\n.code\n Closure 0\n";
+    assert_eq!(run_unverified(assembly), "Constant 0 has the wrong type!");
+}
+
+#[test]
+fn unknown_value_types_are_errors() {
+    let assembly = ".code\n Nil\n IsType 200\n";
+    assert_eq!(run_unverified(assembly), "Unknown value type 200!");
+}
+
+#[test]
+fn upvalues_out_of_range_are_errors() {
+    assert_eq!(
+        run_unverified(".code\n GetUpvalue 3\n"),
+        "Upvalue 3 out of range!"
+    );
+}
+
+#[test]
+fn for_in_slots_without_an_iterator_are_errors() {
+    let assembly = ".code\n Nil\n IterNext 1 done\ndone:\n";
+    assert_eq!(
+        run_unverified(assembly),
+        "Invalid bytecode: for-in slot does not hold an iterator!"
+    );
+}
+
+#[test]
+fn yields_outside_a_coroutine_are_errors() {
+    assert_eq!(
+        run_unverified(".code\n Nil\n Yield\n"),
+        "Invalid bytecode: generator function running outside a coroutine!"
+    );
+}
+
+#[test]
+fn cut_off_instructions_and_wild_loops_are_errors() {
+    let mut interner = Interner::new();
+    let mut chunk = Chunk::from_assembly(".code\n GetGlobal 0\n", &mut interner).unwrap();
+    chunk.code.pop();
+    let mut vm = Vm::new(chunk, interner, Globals::new());
+    let error = vm.run().expect_err("bytecode is rejected").to_string();
+    assert_eq!(error, "Invalid bytecode: instruction cut off!");
+
+    let mut interner = Interner::new();
+    let mut chunk = Chunk::from_assembly(".code\n Loop 0\n", &mut interner).unwrap();
+    chunk.code[1..3].copy_from_slice(&100u16.to_be_bytes());
+    let mut vm = Vm::new(chunk, interner, Globals::new());
+    let error = vm.run().expect_err("bytecode is rejected").to_string();
+    assert_eq!(
+        error,
+        "Invalid bytecode: jump before the start of the chunk!"
+    );
+}
+
+#[test]
+fn building_from_missing_values_is_an_error() {
+    for op in [
+        "BuildString 3",
+        "BuildList 5",
+        "BuildTuple 2",
+        "BuildMap 1",
+        "PopN 4",
+    ] {
+        let assembly = format!(".code\n {}\n Return\n", op);
+        assert_eq!(run_unverified(&assembly), "Stack underflow!", "{}", op);
+    }
+    assert_eq!(
+        run_unverified(".code\n CloseUpvalue\n CloseUpvalue\n Return\n"),
+        "Stack underflow!"
+    );
+}
+
+#[test]
+fn disassembling_bad_constants_and_cut_off_instructions_does_not_panic() {
+    let mut interner = Interner::new();
+    let chunk =
+        Chunk::from_assembly(".code\n Constant 5\n Print\n Return\n", &mut interner).unwrap();
+    let listing = chunk.disassemble("<script>", &interner);
+    assert!(
+        listing.contains("Constant \t0 <bad constant 5>"),
+        "{}",
+        listing
+    );
+
+    let mut trace = Vec::new();
+    let mut vm = Vm::new(chunk, interner, Globals::new()).with_output(Vec::new());
+    vm.set_trace(TraceOptions {
+        instructions: true,
+        ..TraceOptions::default()
+    });
+    vm.set_trace_writer(&mut trace);
+    let error = vm.run().expect_err("bytecode is rejected").to_string();
+    assert_eq!(error, "Constant index 5 out of range!");
+    drop(vm);
+    assert!(String::from_utf8(trace)
+        .unwrap()
+        .contains("<bad constant 5>"));
+
+    let mut interner = Interner::new();
+    let mut chunk = Chunk::from_assembly(".code\n GetGlobal 0\n", &mut interner).unwrap();
+    chunk.code.pop();
+    let listing = chunk.disassemble("<script>", &interner);
+    assert!(listing.contains("GetGlobal\t0 <cut off>"), "{}", listing);
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use common::{error, run};
+
+#[test]
+fn format_fills_placeholders() {
+    let (output, result) = run(r#"print format("{} [{:>4}] {:.2}", "a", 1, 2);"#);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "a [   1] 2.00\n");
+}
+
+#[test]
+fn format_rejects_huge_precision() {
+    assert_eq!(
+        error(r#"format("{:.18446744073709551615}", 1.5);"#),
+        "Runtime error: Width and precision in a format string can't be over 1024."
+    );
+}
+
+#[test]
+fn format_rejects_huge_width() {
+    assert_eq!(
+        error(r#"format("{:18446744073709551615}", 1);"#),
+        "Runtime error: Width and precision in a format string can't be over 1024."
+    );
+}
+
+#[test]
+fn format_allows_the_largest_width() {
+    let (output, result) = run(r#"print len(format("{:1024}", 1));"#);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "1024\n");
+}
+
+#[test]
+fn exit_names_what_it_got_instead_of_a_code() {
+    assert_eq!(
+        error(r#"exit("a");"#),
+        "Runtime error: Exit code must be a whole number but got string."
+    );
+    assert_eq!(
+        error("exit(1.5);"),
+        "Runtime error: Exit code must be a whole number but got 1.5."
+    );
+}
+
+#[test]
+fn disassemble_names_nested_functions() {
+    let (output, result) =
+        run("fun outer() { fun inner() {} return inner; } print disassemble(outer);");
+    assert_eq!(result, Ok(()));
+    assert!(output.contains("Closure \t0 '<fn inner>'"), "{}", output);
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use alox_bytecode::{chunk::Chunk, globals::Globals, interner::Interner, vm::Vm};
+use common::{error, run};
+
+#[test]
+fn shifts_within_range() {
+    let (output, result) = run("print 1 << 63; print -8 >> 1; print 5 << 0;");
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "-9223372036854775808\n-4\n5\n");
+}
+
+#[test]
+fn shift_counts_out_of_range_are_errors() {
+    assert_eq!(
+        error("print 1 << 64;"),
+        "Runtime error: Shift count must be between 0 and 63 but got 64."
+    );
+    assert_eq!(
+        error("print 1 >> -1;"),
+        "Runtime error: Shift count must be between 0 and 63 but got -1."
+    );
+}
+
+/// A class overloading operators, for the tests of operator methods
+const V: &str = r#"
+    class V {
+        init(x) { this.x = x; }
+        __add__(other) { return V(this.x + other.x); }
+        __mul__(k) { return V(this.x * k); }
+        __rmul__(k) { return V(k * this.x); }
+        __rsub__(k) { return V(k - this.x); }
+        __eq__(other) { return other == this.x; }
+        __lt__(k) { return this.x < k; }
+    }
+"#;
+
+#[test]
+fn instances_overload_operators_on_either_side() {
+    let (output, result) = run(&format!(
+        "{}{}",
+        V,
+        r#"
+        print (V(1) + V(2)).x;
+        print (V(2) * 3).x;
+        print (3 * V(2)).x;
+        print (10 - V(4)).x;
+        print 3 > V(2);
+        print V(2) < 3;
+        "#
+    ));
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "3\n6\n6\n6\ntrue\ntrue\n");
+}
+
+#[test]
+fn equality_methods_are_symmetric() {
+    let (output, result) = run(&format!(
+        "{}{}",
+        V, "print V(1) == 1; print 1 == V(1); print 2 == V(1); print V(1) != 1;"
+    ));
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "true\ntrue\nfalse\nfalse\n");
+}
+
+#[test]
+fn operators_without_a_method_are_errors() {
+    assert_eq!(
+        error(&format!("{}print V(1) / 2;", V)),
+        "Runtime error: Operands must be numbers, got instance and int."
+    );
+    assert_eq!(
+        error(&format!("{}print 2 / V(1);", V)),
+        "Runtime error: Operands must be numbers, got int and instance."
+    );
+}
+
+#[test]
+fn compound_assignment_to_an_index_evaluates_it_once() {
+    let (output, result) = run(r#"
+        var calls = 0;
+        fun at(i) { calls += 1; return i; }
+        var l = [1, 2];
+        l[at(1)] += 10;
+        var m = {"k": 3};
+        fun scale() { m["k"] *= 2; }
+        scale();
+        print l;
+        print m;
+        print calls;
+        print l[0] -= 1;
+    "#);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "[1, 12]\n{k: 6}\n1\n0\n");
+}
+
+#[test]
+fn swap_exchanges_the_top_two_values() {
+    let mut interner = Interner::new();
+    let assembly = ".constants\n 1\n 2\n.code\n Constant 0\n Constant 1\n Swap\n Print\n Print\n";
+    let chunk = Chunk::from_assembly(assembly, &mut interner).expect("assembles");
+    assert_eq!(chunk.verify(&interner), Ok(()));
+    assert!(chunk.disassemble("<script>", &interner).contains("Swap"));
+    let mut output = Vec::new();
+    let result = Vm::new(chunk, interner, Globals::new())
+        .with_output(&mut output)
+        .run()
+        .map_err(|err| err.to_string());
+    assert_eq!(result, Ok(()));
+    assert_eq!(String::from_utf8(output).unwrap(), "1\n2\n");
+}
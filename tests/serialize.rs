@@ -0,0 +1,197 @@
+mod common;
+
+use alox_bytecode::{
+    chunk::Chunk,
+    compile,
+    globals::Globals,
+    interner::Interner,
+    object::AloxString,
+    opcodes::Op,
+    serialize::{BytecodeError, FORMAT_VERSION, MAGIC},
+    value::Value,
+    verify::VerifyErrorKind,
+    vm::Vm,
+};
+use common::run;
+
+const SCRIPT: &str = r#"
+    var greeting = "hello";
+    fun counter(start) {
+        var count = start;
+        fun next() { count += 1; return count; }
+        return next;
+    }
+    var next = counter(10);
+    next();
+    print greeting + " " + str(next());
+    print [1.5, 2, nil, true];
+"#;
+
+/// Compiles `source` and returns it serialized
+fn compiled(source: &str) -> Vec<u8> {
+    let mut interner = Interner::new();
+    let mut globals = Globals::new();
+    let chunk = compile(source, &mut interner, &mut globals).expect("script compiles");
+    chunk.serialize(&interner, &globals)
+}
+
+/// Why `bytes` don't load, `None` if they do
+fn rejection(bytes: &[u8]) -> Option<BytecodeError> {
+    Chunk::deserialize(bytes, &mut Interner::new(), &mut Globals::new()).err()
+}
+
+/// A file header naming `globals`, to be followed by the script's chunk
+fn header(globals: &[&str]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(globals.len() as u32).to_le_bytes());
+    for name in globals {
+        bytes.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(name.as_bytes());
+    }
+    bytes
+}
+
+/// A chunk of `code` on one line, to be followed by its `constants`
+fn chunk(bytes: &mut Vec<u8>, code: &[u8], constants: u32) {
+    bytes.extend_from_slice(&(code.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(code);
+    bytes.extend_from_slice(&1u32.to_le_bytes());
+    bytes.extend_from_slice(&1u32.to_le_bytes());
+    bytes.extend_from_slice(&(code.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&constants.to_le_bytes());
+}
+
+#[test]
+fn round_trips_run_like_the_source() {
+    let bytes = compiled(SCRIPT);
+    let mut interner = Interner::new();
+    let mut globals = Globals::new();
+    let chunk = Chunk::deserialize(&bytes, &mut interner, &mut globals).expect("loads");
+    let mut output = Vec::new();
+    let result = Vm::new(chunk, interner, globals)
+        .with_output(&mut output)
+        .run()
+        .map_err(|err| err.to_string());
+    assert_eq!(result, Ok(()));
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(output, "hello 12\n[1.5, 2, Nil, true]\n");
+    assert_eq!(run(SCRIPT), (output, Ok(())));
+}
+
+#[test]
+fn globals_are_remapped_into_the_loading_table() {
+    let bytes = compiled("var a = 1; var b = a + 1; print b;");
+    let mut interner = Interner::new();
+    let mut globals = Globals::new();
+    // taken slots and strings, so nothing lines up with the file by accident
+    for name in ["x", "y", "b"] {
+        let name = AloxString(interner.intern(name));
+        globals.resolve(name).expect("room for a global");
+    }
+    let chunk = Chunk::deserialize(&bytes, &mut interner, &mut globals).expect("loads");
+    let b = globals
+        .slot(AloxString(interner.intern("b")))
+        .expect("b is known");
+    assert_eq!(b, 2);
+    let a = globals
+        .slot(AloxString(interner.intern("a")))
+        .expect("a is added");
+    assert_eq!(a, 3);
+
+    let mut output = Vec::new();
+    let mut vm = Vm::new(chunk, interner, globals).with_output(&mut output);
+    assert_eq!(vm.run().map_err(|err| err.to_string()), Ok(()));
+    assert_eq!(vm.get_global("a"), Some(Value::Int(1)));
+    drop(vm);
+    assert_eq!(String::from_utf8(output).unwrap(), "2\n");
+}
+
+#[test]
+fn other_files_and_versions_are_rejected() {
+    assert_eq!(
+        rejection(b"#!/usr/bin/env alox"),
+        Some(BytecodeError::NotBytecode)
+    );
+
+    let mut bytes = compiled("print 1;");
+    bytes[4..6].copy_from_slice(&(FORMAT_VERSION - 1).to_le_bytes());
+    assert_eq!(
+        rejection(&bytes),
+        Some(BytecodeError::UnsupportedVersion(FORMAT_VERSION - 1))
+    );
+}
+
+#[test]
+fn cut_off_files_are_rejected() {
+    let bytes = compiled(SCRIPT);
+    for len in [2, 5, 9, bytes.len() / 2, bytes.len() - 1] {
+        assert_eq!(
+            rejection(&bytes[..len]),
+            Some(BytecodeError::Truncated),
+            "{}",
+            len
+        );
+    }
+}
+
+#[test]
+fn bad_constants_are_rejected() {
+    let mut bytes = header(&[]);
+    chunk(&mut bytes, &[], 1);
+    bytes.extend_from_slice(&[9]);
+    assert_eq!(rejection(&bytes), Some(BytecodeError::UnknownConstant(9)));
+
+    let mut bytes = header(&[]);
+    chunk(&mut bytes, &[], 1);
+    bytes.extend_from_slice(&[4, 1, 0, 0, 0, 0xff]);
+    assert_eq!(rejection(&bytes), Some(BytecodeError::InvalidString));
+}
+
+#[test]
+fn global_slots_must_be_named() {
+    let mut bytes = header(&["a"]);
+    chunk(&mut bytes, &[Op::GetGlobal.u8(), 0, 1, Op::Pop.u8()], 0);
+    assert_eq!(rejection(&bytes), Some(BytecodeError::BadGlobalSlot(1)));
+
+    let names: Vec<String> = (0..=u16::MAX as usize + 1).map(|n| n.to_string()).collect();
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+    let mut bytes = header(&names);
+    chunk(&mut bytes, &[], 0);
+    assert_eq!(rejection(&bytes), Some(BytecodeError::TooManyGlobals));
+}
+
+#[test]
+fn chunks_failing_verification_are_rejected() {
+    let mut bytes = compiled("fun greet() { print \"hi\"; } greet();");
+    // the function's name is the last place it is written, right before its code
+    let name = bytes
+        .windows(5)
+        .rposition(|window| window == b"greet")
+        .expect("function name is written");
+    bytes[name + 5 + 4] = 238;
+    let error = rejection(&bytes).expect("chunk is rejected");
+    match &error {
+        BytecodeError::Invalid(invalid) => {
+            assert_eq!(invalid.kind, VerifyErrorKind::UnknownOpcode(238))
+        }
+        other => panic!("unexpected error {:?}", other),
+    }
+    assert_eq!(
+        error.to_string(),
+        "Invalid bytecode! In function greet at offset 0: unknown opcode 238"
+    );
+}
+
+#[test]
+fn deeply_nested_functions_are_rejected() {
+    let mut bytes = header(&[]);
+    for _ in 0..100_000 {
+        chunk(&mut bytes, &[], 1);
+        // a function constant with no arity, upvalues or name
+        bytes.push(5);
+        bytes.extend_from_slice(&[0; 15]);
+    }
+    chunk(&mut bytes, &[], 0);
+    assert_eq!(rejection(&bytes), Some(BytecodeError::TooDeep));
+}
@@ -0,0 +1,146 @@
+use alox_bytecode::{
+    chunk::Chunk,
+    compile,
+    globals::Globals,
+    interner::Interner,
+    verify::{VerifyError, VerifyErrorKind},
+};
+
+/// Verifies a chunk assembled from `assembly`
+fn verify(assembly: &str) -> Result<(), VerifyError> {
+    let mut interner = Interner::new();
+    let chunk = Chunk::from_assembly(assembly, &mut interner).expect("assembles");
+    chunk.verify(&interner)
+}
+
+fn kind(assembly: &str) -> VerifyErrorKind {
+    verify(assembly).expect_err("chunk is rejected").kind
+}
+
+#[test]
+fn compiled_scripts_verify() {
+    let mut interner = Interner::new();
+    let mut globals = Globals::new();
+    let source = r#"
+        fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+        var l = [1, 2];
+        for (x in l) { l[0] += x; }
+        try { throw "e"; } catch (e) { print e; }
+        class A { init() { this.x = 1; } get y { return this.x; } }
+        print fib(5) + A().y;
+    "#;
+    let chunk = compile(source, &mut interner, &mut globals).expect("script compiles");
+    assert_eq!(chunk.verify(&interner), Ok(()));
+}
+
+#[test]
+fn errors_name_the_function() {
+    let assembly = ".constants\n .function greet 0\n .code\n Nil\n .end\n.code\n Closure 0\n Pop\n";
+    let error = verify(assembly).expect_err("chunk is rejected");
+    assert_eq!(error.function.as_deref(), Some("greet"));
+    assert_eq!(error.kind, VerifyErrorKind::MissingReturn);
+    assert_eq!(
+        error.to_string(),
+        "In function greet at offset 1: function can end without returning"
+    );
+    // the script's own closure sits in slot zero
+    let error = verify(".code\n Pop\n Pop\n").expect_err("chunk is rejected");
+    assert_eq!(error.to_string(), "In script at offset 1: stack underflow");
+}
+
+#[test]
+fn bad_operands_are_rejected() {
+    assert_eq!(
+        kind(".code\n Constant 3\n"),
+        VerifyErrorKind::BadConstantIndex(3)
+    );
+    assert_eq!(
+        kind(".constants\n 1\n.code\n Nil\n GetProperty 0\n"),
+        VerifyErrorKind::WrongConstantType(0)
+    );
+    assert_eq!(
+        kind(".code\n GetLocal 9\n"),
+        VerifyErrorKind::BadLocalSlot(9)
+    );
+    assert_eq!(
+        kind(".code\n GetUpvalue 0\n"),
+        VerifyErrorKind::BadUpvalue(0)
+    );
+    assert_eq!(
+        kind(".code\n Nil\n IsType 200\n"),
+        VerifyErrorKind::BadValueType(200)
+    );
+}
+
+#[test]
+fn undecodable_code_is_rejected() {
+    let mut interner = Interner::new();
+    let mut chunk = Chunk::from_assembly(".code\n Nil\n Pop\n", &mut interner).unwrap();
+    chunk.code[1] = 238;
+    assert_eq!(
+        chunk.verify(&interner).expect_err("chunk is rejected").kind,
+        VerifyErrorKind::UnknownOpcode(238)
+    );
+
+    let mut chunk = Chunk::from_assembly(".code\n GetGlobal 0\n", &mut interner).unwrap();
+    chunk.code.pop();
+    assert_eq!(
+        chunk.verify(&interner).expect_err("chunk is rejected").kind,
+        VerifyErrorKind::Truncated
+    );
+
+    let mut chunk = Chunk::from_assembly(".code\n Nil\n Pop\n", &mut interner).unwrap();
+    chunk.lines.clear();
+    assert_eq!(
+        chunk.verify(&interner).expect_err("chunk is rejected").kind,
+        VerifyErrorKind::MissingLines
+    );
+}
+
+#[test]
+fn jumps_must_land_on_an_instruction() {
+    let mut interner = Interner::new();
+    let assembly = ".code\n Jump end\n GetGlobal 0\n Pop\nend:\n";
+    let mut chunk = Chunk::from_assembly(assembly, &mut interner).unwrap();
+    // into the middle of GetGlobal's operand
+    chunk.code[1..3].copy_from_slice(&1u16.to_be_bytes());
+    assert_eq!(
+        chunk.verify(&interner).expect_err("chunk is rejected").kind,
+        VerifyErrorKind::BadJumpTarget(4)
+    );
+}
+
+#[test]
+fn closures_may_capture_the_slot_they_are_pushed_to() {
+    let mut interner = Interner::new();
+    let mut globals = Globals::new();
+    let source = "fun outer() { fun c(n) { if (n == 0) return 0; return c(n - 1); } return c(3); }";
+    let chunk = compile(source, &mut interner, &mut globals).expect("script compiles");
+    assert_eq!(chunk.verify(&interner), Ok(()));
+
+    let function = ".constants\n .function f 0 1\n .code\n Nil\n Return\n .end\n.code\n";
+    assert_eq!(
+        verify(&format!("{} Closure 0 local 1\n Pop\n", function)),
+        Ok(())
+    );
+    assert_eq!(
+        kind(&format!("{} Closure 0 local 2\n Pop\n", function)),
+        VerifyErrorKind::BadLocalSlot(2)
+    );
+}
+
+#[test]
+fn paths_must_agree_on_stack_depth() {
+    let assembly = ".code\n True\n JumpIfFalse skip\n Nil\nskip:\n Pop\n";
+    assert_eq!(
+        kind(assembly),
+        VerifyErrorKind::StackMismatch {
+            expected: 3,
+            found: 2
+        }
+    );
+    assert_eq!(
+        kind(".code\n Nil\n PopN 3\n"),
+        VerifyErrorKind::StackUnderflow
+    );
+}